@@ -0,0 +1,74 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+
+static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+
+/// Set the id of this process, an empty `configured` id generates one. Only the first call wins,
+/// call it before [`crate::log::setup`] so every log line carries the id
+pub fn init(configured: &str) -> &'static str {
+    INSTANCE_ID.get_or_init(|| resolve(configured))
+}
+
+/// Id set by [`init`], generated on first use otherwise
+pub fn id() -> &'static str {
+    init("")
+}
+
+/// `configured` when set, otherwise `<hostname>-<6 random hex digits>`, so replicas on one host
+/// and restarts of one replica stay apart
+pub fn resolve(configured: &str) -> String {
+    let configured = configured.trim();
+    if !configured.is_empty() {
+        return configured.to_string();
+    }
+
+    // RandomState is seeded per process
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    format!("{}-{:06x}", hostname(), hasher.finish() & 0xff_ffff)
+}
+
+fn hostname() -> String {
+    match system_hostname().as_deref().map(str::trim) {
+        None | Some("") => "unknown".to_string(),
+        Some(hostname) => hostname.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn system_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Set by most shells and container runtimes, Windows sets COMPUTERNAME
+#[cfg(not(unix))]
+fn system_hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured_id_wins() {
+        assert_eq!(resolve(" replica-a "), "replica-a");
+    }
+
+    #[test]
+    fn test_generated_id_is_hostname_and_random_suffix() {
+        let id = resolve("");
+        let (host, suffix) = id.rsplit_once('-').unwrap();
+        assert_eq!(host, hostname());
+        assert_eq!(suffix.len(), 6);
+        assert!(suffix.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
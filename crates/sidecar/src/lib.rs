@@ -1,5 +1,8 @@
+pub mod instance;
 pub mod lifecycle;
+pub mod lifecycle_events;
 pub mod log;
+pub mod metrics;
 pub mod prelude;
 pub mod repo;
 pub mod setup;
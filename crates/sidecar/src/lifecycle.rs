@@ -1,6 +1,7 @@
 use tokio::select;
 use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 use tracing::info;
@@ -35,8 +36,17 @@ impl LifecycleManager {
         self.task_tracker.spawn(task);
     }
 
+    pub fn spawn_blocking_task<F, T>(&self, task: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.task_tracker.spawn_blocking(task)
+    }
+
     // only main thread should call this
-    pub async fn wait(&self) {
+    /// Returns false when component tasks were force cancelled after the timeout
+    pub async fn wait(&self) -> bool {
         let (cancel_timeout_signal_sender, mut cancel_timeout_signal_receiver) =
             mpsc::channel::<()>(1);
 
@@ -66,7 +76,7 @@ impl LifecycleManager {
                 tokio::spawn(async move {
                     tokio::time::sleep(std::time::Duration::from_secs(10)).await;
                     _ = cancel_timeout_signal_sender.send(()).await;
-                })
+                });
             }
         });
 
@@ -74,10 +84,12 @@ impl LifecycleManager {
             _ = cancel_timeout_signal_receiver.recv() => {
                 // timeout
                 info!("component tasks cancel timeout, will force cancel");
+                false
             }
             _ = self.task_tracker.wait() => {
                 // wait all task down
                 info!("all component tasks down");
+                true
             }
         }
     }
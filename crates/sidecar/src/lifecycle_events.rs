@@ -0,0 +1,87 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Local;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::instance;
+use crate::prelude::*;
+
+/// Lifecycle transition of a component, the `event` field of a line
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    Starting,
+    Started,
+    StartFailed,
+    Stopping,
+    Stopped,
+    StopFailed,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts: String,
+    instance_id: &'a str,
+    component: &'a str,
+    event: LifecycleEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elapsed_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Appends one json line per component lifecycle transition to a file of its own, for ops
+/// tooling that shouldn't have to parse the application log
+pub struct LifecycleEventLog {
+    file: Mutex<File>,
+}
+
+impl LifecycleEventLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| {
+                format!("Failed to open lifecycle events file: {}", path.display())
+            })?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Failures are logged and otherwise ignored, the sink must never stop a component
+    pub fn record(
+        &self,
+        component: &str,
+        event: LifecycleEvent,
+        elapsed: Option<Duration>,
+        error: Option<&Report>,
+    ) {
+        let line = Line {
+            ts: Local::now().to_rfc3339(),
+            instance_id: instance::id(),
+            component,
+            event,
+            elapsed_ms: elapsed.map(|elapsed| elapsed.as_millis()),
+            error: error.map(|err| format!("{err:#}")),
+        };
+        let result = serde_json::to_string(&line)
+            .map_err(Report::from)
+            .and_then(|json| {
+                let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+                writeln!(file, "{json}").map_err(Report::from)
+            });
+        if let Err(err) = result {
+            warn!(component, event = ?event, error = ?err, "failed to write lifecycle event");
+        }
+    }
+}
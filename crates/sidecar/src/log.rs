@@ -1,21 +1,24 @@
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, TryLockError};
 
 use chrono::Local;
 use once_cell::sync::Lazy;
-use tracing::Level;
-use tracing_appender::non_blocking::WorkerGuard;
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_panic::panic_hook;
-use tracing_subscriber::fmt::format;
-use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::format::{self, Writer};
 use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{filter, prelude::*};
 
+use crate::instance;
 use crate::prelude::*;
 
 static PREPARE_STATE: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+static FILE_GUARD: Lazy<Mutex<Option<WorkerGuard>>> = Lazy::new(|| Mutex::new(None));
 
 #[derive(Clone, Copy)]
 struct LocalTimer;
@@ -27,18 +30,53 @@ impl FormatTime for LocalTimer {
     }
 }
 
-pub fn default_setup() -> Option<WorkerGuard> {
-    setup(Level::DEBUG, None, 14)
+/// Prefix every line with the instance id, so lines from several replicas can be told apart
+/// once they're collected in one place
+struct WithInstance<F> {
+    inner: F,
+    instance_id: &'static str,
 }
 
+impl<S, N, F> FormatEvent<S, N> for WithInstance<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        write!(writer, "[{}] ", self.instance_id)?;
+        self.inner.format_event(ctx, writer, event)
+    }
+}
+
+fn event_format() -> WithInstance<format::Format<format::Full, LocalTimer>> {
+    WithInstance {
+        inner: format::format().with_timer(LocalTimer).with_target(false),
+        instance_id: instance::id(),
+    }
+}
+
+pub fn default_setup() {
+    setup(Level::DEBUG, None, 14, true, true)
+}
+
+/// `console` also writes every line to stdout, off for a daemon whose stdout goes to a file that
+/// would otherwise duplicate the log files
 pub fn setup(
     log_level: Level,
     log_dir: Option<PathBuf>,
     max_log_files: u64,
-) -> Option<WorkerGuard> {
+    install_panic_hook: bool,
+    console: bool,
+) {
     let mut init_flag = PREPARE_STATE.lock().expect("Logger state poisoned");
     if *init_flag {
-        return None;
+        return;
     }
     *init_flag = true;
     drop(init_flag);
@@ -47,67 +85,281 @@ pub fn setup(
 
     let filter = filter::Targets::new().with_default(log_level);
 
-    let local_time = LocalTimer;
-
     // log output to file
-    let mut guard = None;
-    if let Some(log_dir) = log_dir {
-        if !cfg!(test) {
-            let log_dir_str = log_dir.display().to_string();
-            fs::create_dir_all(log_dir).expect("Failed to create log dir: {log_dir_str}");
-
-            let now = Local::now();
-            let today_log_file_name = now.format("%Y-%m-%d").to_string();
-            let now_time = now.format("%Y-%m-%d %H:%M:%S%.3f %:z").to_string();
-            let today_log_file_path = format!("{log_dir_str}/{today_log_file_name}.log");
-            if fs::metadata(&today_log_file_path).is_ok() {
-                fs::rename(
-                    &today_log_file_path,
-                    format!("{log_dir_str}/{now_time}.log"),
-                )
-                .wrap_err("Failed to rename log file")
-                .unwrap();
-            }
-
-            let (non_blocking_appender, _guard) = tracing_appender::non_blocking(
-                RollingFileAppender::builder()
-                    .rotation(Rotation::DAILY)
-                    .filename_suffix("log")
-                    .max_log_files(max_log_files as usize)
-                    .build(log_dir_str)
-                    .expect("Initializing rolling file appender failed"),
-            );
-            guard = Some(_guard);
-            layers.push(
-                tracing_subscriber::fmt::layer()
-                    .with_ansi(true)
-                    .fmt_fields(format::Pretty::default())
-                    .with_timer(local_time.clone())
-                    .with_target(false)
-                    .with_writer(non_blocking_appender)
-                    .with_filter(filter.clone())
-                    .boxed(),
-            );
-        }
+    if let Some(log_dir) = log_dir
+        && !cfg!(test)
+    {
+        let (non_blocking_appender, guard) = file_appender(log_dir, max_log_files);
+        install_file_guard(guard);
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(true)
+                .fmt_fields(format::Pretty::default())
+                .event_format(event_format())
+                .with_writer(non_blocking_appender)
+                .with_filter(filter.clone())
+                .boxed(),
+        );
     }
 
     // log output to console
-    layers.push(
-        tracing_subscriber::fmt::layer()
-            .with_ansi(true)
-            .fmt_fields(format::Pretty::default())
-            .with_timer(local_time.clone())
-            .with_target(false)
-            .with_filter(filter.clone())
-            .boxed(),
-    );
+    if console {
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(true)
+                .fmt_fields(format::Pretty::default())
+                .event_format(event_format())
+                .with_filter(filter.clone())
+                .boxed(),
+        );
+    }
     tracing_subscriber::registry().with(layers).init();
 
+    // embedders may already own the panic hook (e.g. color_eyre), leave it alone if asked
+    if !install_panic_hook {
+        return;
+    }
+
     let prev_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
         panic_hook(panic_info);
         prev_hook(panic_info);
+        // a panic on the main thread ends the process, other threads (e.g. tokio workers) keep running
+        if std::thread::current().name() == Some("main") {
+            flush();
+        }
     }));
+}
 
-    guard
+/// Flush buffered file logs and stop the background writer, call it right before the process exits
+pub fn flush() {
+    let guard = match FILE_GUARD.try_lock() {
+        Ok(mut guard) => guard.take(),
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().take(),
+        Err(TryLockError::WouldBlock) => None,
+    };
+    // dropping the guard waits for the background writer to drain its queue
+    drop(guard);
+}
+
+fn install_file_guard(guard: WorkerGuard) {
+    let mut file_guard = FILE_GUARD.lock().unwrap_or_else(|e| e.into_inner());
+    *file_guard = Some(guard);
+}
+
+fn file_appender(log_dir: PathBuf, max_log_files: u64) -> (NonBlocking, WorkerGuard) {
+    let log_dir_str = log_dir.display().to_string();
+    fs::create_dir_all(log_dir).expect("Failed to create log dir: {log_dir_str}");
+
+    // the appender keeps appending to today's file if it can't be moved aside, not worth a crash
+    if let Err(err) = rotate_today_log_file(Path::new(&log_dir_str)) {
+        eprintln!("Failed to rotate today's log file, appending to it: {err:?}");
+    }
+
+    tracing_appender::non_blocking(
+        RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_suffix("log")
+            .max_log_files(max_log_files as usize)
+            .build(log_dir_str)
+            .expect("Initializing rolling file appender failed"),
+    )
+}
+
+/// Delete the oldest `.log` files in `log_dir` beyond `max_log_files`, the retention the rolling
+/// appender only applies when it rotates. Returns the deleted files, oldest first
+pub fn prune_log_files(log_dir: &Path, max_log_files: u64) -> Result<Vec<PathBuf>> {
+    ensure!(max_log_files > 0, "max_log_files must be at least 1");
+    if !log_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut log_files = Vec::new();
+    for entry in fs::read_dir(log_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_file() || path.extension().is_none_or(|ext| ext != "log") {
+            continue;
+        }
+        log_files.push((entry.metadata()?.modified()?, path));
+    }
+    // oldest first, the name breaks ties of coarse mtimes
+    log_files.sort();
+
+    let stale_count = log_files.len().saturating_sub(max_log_files as usize);
+    let mut deleted = Vec::with_capacity(stale_count);
+    for (_, path) in log_files.into_iter().take(stale_count) {
+        fs::remove_file(&path)
+            .wrap_err(format!("Failed to delete log file: {}", path.display()))?;
+        deleted.push(path);
+    }
+    Ok(deleted)
+}
+
+/// Move the log file left by a previous run today aside, so the appender starts a fresh one
+fn rotate_today_log_file(log_dir: &Path) -> Result<()> {
+    let now = Local::now();
+    let today_log_file_path = log_dir.join(format!("{}.log", now.format("%Y-%m-%d")));
+    if fs::metadata(&today_log_file_path).is_err() {
+        return Ok(());
+    }
+
+    // keep the name free of spaces and colons, which some filesystems reject
+    let now_time = now.format("%Y-%m-%dT%H-%M-%S%.3f%z").to_string();
+    let mut target = log_dir.join(format!("{now_time}.log"));
+    let mut seq = 1;
+    // a hard link fails instead of replacing an existing target, unlike rename, so a concurrent
+    // start picking the same name can't clobber a rotated file
+    loop {
+        match fs::hard_link(&today_log_file_path, &target) {
+            Ok(()) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                target = log_dir.join(format!("{now_time}-{seq}.log"));
+                seq += 1;
+            }
+            Err(e) => {
+                return Err(e).wrap_err(format!("Failed to link log file to {}", target.display()));
+            }
+        }
+    }
+    fs::remove_file(&today_log_file_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use tracing::info;
+
+    use super::*;
+
+    #[test]
+    fn test_flush_writes_buffered_logs_to_file() -> Result<()> {
+        let tmp = tempdir()?;
+        let log_dir = tmp.path().join("logs");
+
+        let (non_blocking_appender, guard) = file_appender(log_dir.clone(), 14);
+        install_file_guard(guard);
+
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(non_blocking_appender),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            info!("last words before shutdown");
+        });
+
+        flush();
+
+        let mut content = String::new();
+        for entry in fs::read_dir(&log_dir)? {
+            content.push_str(&fs::read_to_string(entry?.path())?);
+        }
+        assert!(content.contains("last words before shutdown"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lines_carry_the_instance_id() -> Result<()> {
+        let tmp = tempdir()?;
+        let log_path = tmp.path().join("instance.log");
+
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .event_format(event_format())
+                .with_writer(Mutex::new(fs::File::create(&log_path)?)),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            info!("tagged line");
+        });
+
+        let content = fs::read_to_string(&log_path)?;
+        assert!(
+            content.starts_with(&format!("[{}] ", instance::id())),
+            "{content}"
+        );
+        assert!(content.contains("tagged line"), "{content}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rapid_setups_do_not_collide() -> Result<()> {
+        let tmp = tempdir()?;
+        let log_dir = tmp.path().join("logs");
+        fs::create_dir_all(&log_dir)?;
+        let today_log_file_path = log_dir.join(format!("{}.log", Local::now().format("%Y-%m-%d")));
+
+        for run in ["first", "second", "third"] {
+            fs::write(&today_log_file_path, run)?;
+            let (_appender, guard) = file_appender(log_dir.clone(), 14);
+            drop(guard);
+        }
+
+        // every previous run is kept next to the fresh file the last appender opened
+        let mut contents = fs::read_dir(&log_dir)?
+            .map(|entry| Ok(fs::read_to_string(entry?.path())?))
+            .collect::<Result<Vec<_>>>()?;
+        contents.sort();
+        assert_eq!(contents, ["", "first", "second", "third"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_today_log_file_uses_safe_unique_name() -> Result<()> {
+        let tmp = tempdir()?;
+        let today_log_file_path = tmp
+            .path()
+            .join(format!("{}.log", Local::now().format("%Y-%m-%d")));
+
+        fs::write(&today_log_file_path, "first")?;
+        rotate_today_log_file(tmp.path())?;
+        fs::write(&today_log_file_path, "second")?;
+        rotate_today_log_file(tmp.path())?;
+
+        let names: Vec<String> = fs::read_dir(tmp.path())?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<_>>()?;
+        assert_eq!(names.len(), 2, "rotated files: {names:?}");
+        assert!(!today_log_file_path.exists());
+        for name in &names {
+            assert!(
+                !name.contains(' ') && !name.contains(':'),
+                "unsafe name: {name}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_log_files_keeps_newest() -> Result<()> {
+        let tmp = tempdir()?;
+        let log_dir = tmp.path();
+        let now = std::time::SystemTime::now();
+        for (i, name) in ["2026-01-01.log", "2026-01-02.log", "2026-01-03.log"]
+            .iter()
+            .enumerate()
+        {
+            let file = fs::File::create(log_dir.join(name))?;
+            file.set_modified(now - std::time::Duration::from_secs(3600 * (3 - i as u64)))?;
+        }
+        fs::write(log_dir.join("daemon.out"), "kept")?;
+
+        let deleted = prune_log_files(log_dir, 2)?;
+        assert_eq!(deleted, [log_dir.join("2026-01-01.log")]);
+        assert!(log_dir.join("2026-01-02.log").exists());
+        assert!(log_dir.join("2026-01-03.log").exists());
+        assert!(log_dir.join("daemon.out").exists());
+
+        assert!(prune_log_files(log_dir, 2)?.is_empty());
+        assert!(prune_log_files(log_dir, 0).is_err());
+        assert!(prune_log_files(&log_dir.join("missing"), 2)?.is_empty());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Execution counters of one task, labeled by the owning component and the task name
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    pub component: String,
+    pub task_name: String,
+    /// Finished executions, a core task finishes once, cancelled or not, a scheduled task once
+    /// per tick
+    pub runs: u64,
+    /// Scheduled ticks that returned an error and blocking tasks that panicked, counted in `runs`
+    /// too. The output of a core task isn't inspected, so core tasks never count here
+    pub failures: u64,
+    pub total_duration: Duration,
+    pub last_duration: Duration,
+}
+
+/// In-process task metrics of a [`crate::sidecar::Sidecar`], shared by every component handle
+#[derive(Default)]
+pub struct TaskMetrics {
+    tasks: Mutex<BTreeMap<(String, String), TaskStats>>,
+}
+
+impl TaskMetrics {
+    pub fn record(&self, component: &str, task_name: &str, elapsed: Duration, failed: bool) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(PoisonError::into_inner);
+        let stats = tasks
+            .entry((component.to_string(), task_name.to_string()))
+            .or_insert_with(|| TaskStats {
+                component: component.to_string(),
+                task_name: task_name.to_string(),
+                ..Default::default()
+            });
+        stats.runs += 1;
+        stats.failures += u64::from(failed);
+        stats.total_duration += elapsed;
+        stats.last_duration = elapsed;
+    }
+
+    /// Sorted by component, then task name
+    pub fn snapshot(&self) -> Vec<TaskStats> {
+        let tasks = self.tasks.lock().unwrap_or_else(PoisonError::into_inner);
+        tasks.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates_per_component_and_task() {
+        let metrics = TaskMetrics::default();
+        metrics.record("db", "vacuum", Duration::from_millis(10), false);
+        metrics.record("db", "vacuum", Duration::from_millis(30), true);
+        metrics.record("bus", "vacuum", Duration::from_millis(5), false);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].component, "bus");
+        assert_eq!(snapshot[0].runs, 1);
+
+        let db = &snapshot[1];
+        assert_eq!(
+            (db.component.as_str(), db.task_name.as_str()),
+            ("db", "vacuum")
+        );
+        assert_eq!(db.runs, 2);
+        assert_eq!(db.failures, 1);
+        assert_eq!(db.total_duration, Duration::from_millis(40));
+        assert_eq!(db.last_duration, Duration::from_millis(30));
+    }
+}
@@ -1,10 +1,13 @@
+use std::collections::BTreeMap;
 use std::io;
 use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
-use config::{Case, Config, Environment, File, FileFormat};
+use chrono::Utc;
+use config::{Case, Config, Environment, File, FileFormat, Source, Value, ValueKind};
 use serde::Serialize;
 use tokio::fs;
+use tracing::warn;
 
 use crate::prelude::*;
 
@@ -13,6 +16,50 @@ pub trait IConfig:
     Serialize + for<'a> serde::Deserialize<'a> + Default + Clone + Send + Sync
 {
     async fn init(&mut self, repo_root: PathBuf) -> Result<()>;
+
+    /// How many `config.<timestamp>.toml.bak` copies [`Repo::save`] keeps, 0 disables backups
+    fn config_backup_retention(&self) -> usize {
+        DEFAULT_CONFIG_BACKUP_RETENTION
+    }
+}
+
+pub const DEFAULT_CONFIG_BACKUP_RETENTION: usize = 5;
+
+/// Name of the pid file in the repo root, see [`Repo::pid_file_path`]
+pub const PID_FILE_NAME: &str = "process.pid";
+
+/// Extra config values keyed by dotted path (e.g. `http.port`), layered above file and env
+#[async_trait]
+pub trait ConfigSource: Send + Sync {
+    async fn load(&self) -> Result<BTreeMap<String, String>>;
+}
+
+#[async_trait]
+impl ConfigSource for BTreeMap<String, String> {
+    async fn load(&self) -> Result<BTreeMap<String, String>> {
+        Ok(self.clone())
+    }
+}
+
+/// Layer a config value was last set by, see [`Repo::config_sources`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigOrigin {
+    Default,
+    File,
+    Env,
+    Override,
+}
+
+impl std::fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConfigOrigin::Default => "default",
+            ConfigOrigin::File => "file",
+            ConfigOrigin::Env => "env",
+            ConfigOrigin::Override => "override",
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -20,6 +67,8 @@ pub struct Repo<C: IConfig> {
     pub app_name: String,
     pub root: PathBuf,
     pub cfg: C,
+    overrides: BTreeMap<String, String>,
+    unknown_keys: Vec<String>,
 }
 
 impl<C: IConfig> Repo<C> {
@@ -32,6 +81,8 @@ impl<C: IConfig> Repo<C> {
             app_name,
             root: root.clone(),
             cfg,
+            overrides: BTreeMap::new(),
+            unknown_keys: Vec::new(),
         };
         repo.reload().await?;
         repo.cfg.init(root).await?;
@@ -50,12 +101,55 @@ impl<C: IConfig> Repo<C> {
         self.config_path().exists()
     }
 
+    /// Backups of config.toml written by [`Self::backup_config`], oldest first
+    pub async fn config_backups(&self) -> Result<Vec<PathBuf>> {
+        let mut backups = Vec::new();
+        let mut entries = fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("config.") && name.ends_with(".toml.bak") {
+                backups.push(entry.path());
+            }
+        }
+        // the timestamp sorts lexicographically
+        backups.sort();
+        Ok(backups)
+    }
+
+    /// Copy the current config.toml to `config.<timestamp>.toml.bak` and prune backups beyond
+    /// the configured retention. Nothing is written when there's no config file yet
+    pub async fn backup_config(&self) -> Result<Option<PathBuf>> {
+        let retention = self.cfg.config_backup_retention();
+        if retention == 0 || !self.config_exists() {
+            return Ok(None);
+        }
+
+        // utc keeps the names sorted across dst and time zone changes
+        let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.6fZ");
+        let backup_path = self.root.join(format!("config.{timestamp}.toml.bak"));
+        fs::copy(self.config_path(), &backup_path).await?;
+
+        let backups = self.config_backups().await?;
+        for stale in &backups[..backups.len().saturating_sub(retention)] {
+            fs::remove_file(stale).await?;
+        }
+
+        Ok(Some(backup_path))
+    }
+
+    /// Prefix of the env vars read by [`Self::reload`], e.g. `RS_PROJECT_STARTUP_HTTP_PORT` sets
+    /// `http.port`. Matched case-insensitively, segments are joined by `_`
+    pub fn env_prefix(&self) -> String {
+        self.app_name.to_lowercase().replace("-", "_")
+    }
+
     pub fn ipc_file_path(&self) -> PathBuf {
         self.root.join("ipc.sock")
     }
 
     pub fn pid_file_path(&self) -> PathBuf {
-        self.root.join("process.pid")
+        self.root.join(PID_FILE_NAME)
     }
 
     pub async fn write_pid(&self) -> Result<()> {
@@ -71,6 +165,35 @@ impl<C: IConfig> Repo<C> {
         Ok(())
     }
 
+    /// Top-level keys of config.toml that no config field reads, found by the last reload. They
+    /// are ignored, usually a typo or an option that was renamed or removed
+    pub fn unknown_keys(&self) -> &[String] {
+        &self.unknown_keys
+    }
+
+    /// The warning about [`Self::unknown_keys`], none when every key is known
+    pub fn unknown_keys_warning(&self) -> Option<String> {
+        if self.unknown_keys.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "unknown config keys ignored, check for typos or removed options: {}",
+            self.unknown_keys.join(", ")
+        ))
+    }
+
+    /// Values last loaded from a [`ConfigSource`]
+    pub fn overrides(&self) -> &BTreeMap<String, String> {
+        &self.overrides
+    }
+
+    /// Reload with the values of `source` taking precedence over everything else
+    pub async fn reload_from(&mut self, source: &dyn ConfigSource) -> Result<()> {
+        self.overrides = source.load().await?;
+        self.reload().await
+    }
+
+    /// Precedence from low to high: default < config.toml < env < source overrides
     pub async fn reload(&mut self) -> Result<()> {
         dotenv::from_path(self.root.join(".env")).ok();
 
@@ -82,43 +205,150 @@ impl<C: IConfig> Repo<C> {
             }
         };
 
-        let default_cfg = Config::try_from(&C::default())?;
-        let env_prefix = self.app_name.to_lowercase().replace("-", "_");
-        let config_stem = self.config_stem();
-        let config_stem = config_stem.to_string_lossy().into_owned();
-        self.cfg = Config::builder()
-            .add_source(default_cfg)
-            .add_source(
-                File::with_name(&config_stem)
-                    .format(FileFormat::Toml)
-                    .required(false),
-            )
-            .add_source(
-                Environment::with_prefix(&env_prefix)
-                    .convert_case(Case::Snake)
-                    .separator("_"),
-            )
-            .build()?
-            .try_deserialize::<C>()?;
+        self.check_config_file().await?;
+
+        let mut builder = Config::builder()
+            .add_source(Config::try_from(&C::default())?)
+            .add_source(self.file_source())
+            .add_source(self.env_source());
+        for (key, value) in &self.overrides {
+            builder = builder.set_override(key, value.as_str())?;
+        }
+        self.cfg = builder.build()?.try_deserialize::<C>()?;
+
+        self.unknown_keys = self.find_unknown_keys().await?;
+        if let Some(warning) = self.unknown_keys_warning() {
+            warn!(path = %self.config_path().display(), "{warning}");
+        }
+
+        Ok(())
+    }
+
+    fn file_source(&self) -> File<config::FileSourceFile, FileFormat> {
+        File::with_name(&self.config_stem().to_string_lossy())
+            .format(FileFormat::Toml)
+            .required(false)
+    }
+
+    fn env_source(&self) -> Environment {
+        Environment::with_prefix(&self.env_prefix())
+            .convert_case(Case::Snake)
+            .separator("_")
+    }
+
+    /// Layer each config value comes from, keyed by dotted path, following the precedence of
+    /// [`Repo::reload`]. Lists and empty tables are reported as a whole
+    pub fn config_sources(&self) -> Result<BTreeMap<String, ConfigOrigin>> {
+        let mut overrides = Config::builder();
+        for (key, value) in &self.overrides {
+            overrides = overrides.set_override(key, value.as_str())?;
+        }
+        let layers = [
+            (
+                ConfigOrigin::Default,
+                Config::builder().add_source(Config::try_from(&C::default())?),
+            ),
+            (
+                ConfigOrigin::File,
+                Config::builder().add_source(self.file_source()),
+            ),
+            (
+                ConfigOrigin::Env,
+                Config::builder().add_source(self.env_source()),
+            ),
+            (ConfigOrigin::Override, overrides),
+        ];
+
+        let mut origins = BTreeMap::new();
+        for (origin, layer) in layers {
+            let mut keys = Vec::new();
+            for (key, value) in layer.build()?.collect()? {
+                leaf_keys(key, &value, &mut keys);
+            }
+            for key in keys {
+                origins.insert(key, origin);
+            }
+        }
+        Ok(origins)
+    }
 
+    /// The `config` crate reports a broken config.toml without naming the file or the position,
+    /// check it up front
+    async fn check_config_file(&self) -> Result<()> {
+        let config_path = self.config_path();
+        if !self.config_exists() {
+            return Ok(());
+        }
+        let bytes = fs::read(&config_path).await?;
+        let content = String::from_utf8(bytes).map_err(|err| {
+            eyre!(
+                "Config file {} is not valid UTF-8 at byte {}, it may be binary or corrupted",
+                config_path.display(),
+                err.utf8_error().valid_up_to()
+            )
+        })?;
+        if let Err(err) = content.parse::<toml::Table>() {
+            let offset = err.span().map_or(0, |span| span.start);
+            let before = &content[..offset];
+            let line = before.matches('\n').count() + 1;
+            let column = before
+                .rsplit('\n')
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .count()
+                + 1;
+            bail!(
+                "Config file {} is not valid TOML at line {line}, column {column}: {}",
+                config_path.display(),
+                err.message()
+            );
+        }
         Ok(())
     }
 
+    async fn find_unknown_keys(&self) -> Result<Vec<String>> {
+        if !self.config_exists() {
+            return Ok(Vec::new());
+        }
+        let file = fs::read_to_string(self.config_path())
+            .await?
+            .parse::<toml::Table>()?;
+        let known = toml::Table::try_from(C::default())?;
+
+        Ok(file
+            .keys()
+            .filter(|key| !known.contains_key(*key))
+            .cloned()
+            .collect())
+    }
+
     pub async fn save(&self) -> Result<()> {
         let config_path = self.config_path();
-        if let Some(parent) = config_path.parent() {
-            if let Err(e) = fs::create_dir_all(parent).await {
-                if e.kind() != io::ErrorKind::AlreadyExists {
-                    return Err(e.into());
-                }
-            }
+        if let Some(parent) = config_path.parent()
+            && let Err(e) = fs::create_dir_all(parent).await
+            && e.kind() != io::ErrorKind::AlreadyExists
+        {
+            return Err(e.into());
         }
+        self.backup_config().await?;
         let cfg_data = toml::to_string(&self.cfg)?;
         fs::write(&config_path, cfg_data).await?;
         Ok(())
     }
 }
 
+fn leaf_keys(path: String, value: &Value, out: &mut Vec<String>) {
+    match &value.kind {
+        ValueKind::Table(table) if !table.is_empty() => {
+            for (key, value) in table {
+                leaf_keys(format!("{path}.{key}"), value, out);
+            }
+        }
+        _ => out.push(path),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use async_trait::async_trait;
@@ -176,7 +406,7 @@ mod tests {
 
     #[async_trait]
     impl IConfig for TestConfig {
-        async fn init(&mut self, repo_root: PathBuf) -> Result<()> {
+        async fn init(&mut self, _repo_root: PathBuf) -> Result<()> {
             self.value += 1;
             Ok(())
         }
@@ -184,7 +414,7 @@ mod tests {
 
     #[async_trait]
     impl IConfig for DefaultOnlyConfig {
-        async fn init(&mut self, repo_root: PathBuf) -> Result<()> {
+        async fn init(&mut self, _repo_root: PathBuf) -> Result<()> {
             Ok(())
         }
     }
@@ -233,6 +463,101 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_save_backs_up_previous_config() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<TestConfig>::new(tmp.path(), "demo-app").await?;
+
+        repo.save().await?;
+        assert!(repo.config_backups().await?.is_empty());
+
+        repo.cfg.value = 42;
+        repo.save().await?;
+
+        let backups = repo.config_backups().await?;
+        assert_eq!(backups.len(), 1);
+        let backup = tokio::fs::read_to_string(&backups[0]).await?;
+        assert!(backup.contains("value = 1"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_save_prunes_backups_beyond_retention() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<TestConfig>::new(tmp.path(), "demo-app").await?;
+
+        for value in 0..DEFAULT_CONFIG_BACKUP_RETENTION as u32 + 3 {
+            repo.cfg.value = value;
+            repo.save().await?;
+        }
+
+        let backups = repo.config_backups().await?;
+        assert_eq!(backups.len(), DEFAULT_CONFIG_BACKUP_RETENTION);
+        let newest = tokio::fs::read_to_string(backups.last().unwrap()).await?;
+        assert!(newest.contains(&format!("value = {}", DEFAULT_CONFIG_BACKUP_RETENTION + 1)));
+        let oldest = tokio::fs::read_to_string(&backups[0]).await?;
+        assert!(oldest.contains("value = 2"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_broken_config_file_error_names_file_and_position() -> Result<()> {
+        let tmp = tempdir()?;
+        let config_path = tmp.path().join("config.toml");
+
+        tokio::fs::write(&config_path, "value = 3\n[http\nport = 1\n").await?;
+        let err = Repo::<TestConfig>::new(tmp.path(), "demo-app")
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&config_path.display().to_string()), "{err}");
+        assert!(err.contains("line 2, column 6"), "{err}");
+
+        tokio::fs::write(&config_path, b"value = 3\n\xff\xfe\x00").await?;
+        let err = Repo::<TestConfig>::new(tmp.path(), "demo-app")
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("not valid UTF-8 at byte 10"), "{err}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_unknown_keys_warn_without_failing_the_load() -> Result<()> {
+        let tmp = tempdir()?;
+        let log_file = tempfile::NamedTempFile::new()?;
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(std::sync::Mutex::new(log_file.reopen()?))
+            .finish();
+        let _log_guard = tracing::subscriber::set_default(subscriber);
+
+        tokio::fs::write(
+            tmp.path().join("config.toml"),
+            "value = 3\nvalu = 4\n\n[removed]\nenable = true\n",
+        )
+        .await?;
+        let repo = Repo::<TestConfig>::new(tmp.path(), "demo-app").await?;
+
+        assert_eq!(repo.cfg.value, 4);
+        assert_eq!(repo.unknown_keys(), ["removed", "valu"]);
+        assert!(
+            repo.unknown_keys_warning()
+                .is_some_and(|warning| warning.ends_with("options: removed, valu"))
+        );
+        let logs = std::fs::read_to_string(log_file.path())?;
+        assert!(logs.contains("unknown config keys ignored"), "{logs}");
+        assert!(logs.contains("valu"), "{logs}");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_default_used_when_no_config_file() -> Result<()> {
         let tmp = tempdir()?;
@@ -260,4 +585,51 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_config_sources_report_the_winning_layer() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<TestConfig>::new(tmp.path(), "demo-app").await?;
+        assert_eq!(repo.config_sources()?["value"], ConfigOrigin::Default);
+
+        repo.save().await?;
+        assert_eq!(repo.config_sources()?["value"], ConfigOrigin::File);
+
+        let _guard = EnvVarGuard::set("DEMO_APP_VALUE", "41");
+        assert_eq!(repo.config_sources()?["value"], ConfigOrigin::Env);
+
+        repo.reload_from(&MockSource(99)).await?;
+        assert_eq!(repo.config_sources()?["value"], ConfigOrigin::Override);
+
+        Ok(())
+    }
+
+    struct MockSource(u32);
+
+    #[async_trait]
+    impl ConfigSource for MockSource {
+        async fn load(&self) -> Result<BTreeMap<String, String>> {
+            Ok(BTreeMap::from([("value".to_string(), self.0.to_string())]))
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_source_overrides_env_file_and_default() -> Result<()> {
+        let tmp = tempdir()?;
+        let _guard = EnvVarGuard::set("DEMO_APP_VALUE", "41");
+        let mut repo = Repo::<TestConfig>::new(tmp.path(), "demo-app").await?;
+
+        repo.cfg.value = 10;
+        repo.save().await?;
+
+        repo.reload_from(&MockSource(99)).await?;
+        assert_eq!(repo.cfg.value, 99);
+
+        repo.reload_from(&BTreeMap::new()).await?;
+        assert_eq!(repo.cfg.value, 41);
+
+        Ok(())
+    }
 }
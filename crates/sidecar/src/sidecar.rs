@@ -1,15 +1,20 @@
 use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
 use std::pin::Pin;
-use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
 use crate::lifecycle::LifecycleManager;
+use crate::lifecycle_events::{LifecycleEvent, LifecycleEventLog};
+use crate::metrics::{TaskMetrics, TaskStats};
 use crate::prelude::*;
 
 type ComponentHandle = Arc<dyn Component>;
@@ -18,6 +23,10 @@ type AppReadyFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
 #[async_trait]
 pub trait Component: Send + Sync {
     fn name(&self) -> &str;
+    /// Higher stops earlier, components of the same priority stop in reverse start order
+    fn shutdown_priority(&self) -> i32 {
+        0
+    }
     async fn start(&self) -> Result<()> {
         Ok(())
     }
@@ -26,11 +35,25 @@ pub trait Component: Send + Sync {
     }
 }
 
+/// End-of-life record logged when [`Sidecar::run`] returns
+#[derive(Debug, Clone)]
+pub struct ShutdownSummary {
+    pub uptime: Duration,
+    /// Components stopped, in stop order
+    pub components_stopped: Vec<String>,
+    /// False when tasks were force cancelled on timeout or a component failed to stop
+    pub clean: bool,
+}
+
 struct SidecarInner {
     lifecycle_manager: LifecycleManager,
     components: RwLock<Vec<ComponentHandle>>,
     no_block_app_ready_callbacks: Mutex<Vec<AppReadyFuture>>,
     block_app_ready_callbacks: Mutex<Vec<AppReadyFuture>>,
+    /// Set once all components started in [`Sidecar::run`]
+    started_at: OnceLock<Instant>,
+    task_metrics: TaskMetrics,
+    lifecycle_events: OnceLock<LifecycleEventLog>,
 }
 
 #[derive(Clone)]
@@ -48,6 +71,9 @@ impl Sidecar {
                 components: RwLock::new(Vec::new()),
                 no_block_app_ready_callbacks: Mutex::new(Vec::new()),
                 block_app_ready_callbacks: Mutex::new(Vec::new()),
+                started_at: OnceLock::new(),
+                task_metrics: TaskMetrics::default(),
+                lifecycle_events: OnceLock::new(),
             }),
         }
     }
@@ -58,6 +84,31 @@ impl Sidecar {
         c
     }
 
+    /// Time since all components started, zero before [`Sidecar::run`] got there
+    pub fn uptime(&self) -> Duration {
+        self.inner
+            .started_at
+            .get()
+            .map(Instant::elapsed)
+            .unwrap_or_default()
+    }
+
+    /// Execution counts and durations of the tasks spawned so far, labeled by component and task
+    pub fn task_metrics(&self) -> Vec<TaskStats> {
+        self.inner.task_metrics.snapshot()
+    }
+
+    /// Also write every component start and stop transition as a json line to `path`, call it
+    /// before [`Sidecar::run`]
+    pub fn log_lifecycle_events_to(&self, path: &Path) -> Result<()> {
+        let log = LifecycleEventLog::open(path)?;
+        ensure!(
+            self.inner.lifecycle_events.set(log).is_ok(),
+            "Lifecycle events are already logged"
+        );
+        Ok(())
+    }
+
     pub async fn canceled(&self) -> Result<()> {
         self.inner.lifecycle_manager.canceled().await;
         Ok(())
@@ -71,16 +122,39 @@ impl Sidecar {
         Ok(())
     }
 
+    /// Names identify components in logs, task attribution and stop order, so they must be unique
     pub async fn register_component<C>(&self, component: Arc<C>) -> Result<()>
     where
         C: Component + 'static,
     {
         let mut components = self.inner.components.write().await;
+        ensure!(
+            components.iter().all(|c| c.name() != component.name()),
+            "Component already registered: {}",
+            component.name()
+        );
         let handle: ComponentHandle = component;
         components.push(handle);
         Ok(())
     }
 
+    /// Build a component around a sidecar carrying its `name`, then register it. Spares every
+    /// component `new` the naming and the easy to forget registration
+    pub async fn build_component<C, F, Fut>(
+        &self,
+        name: impl Into<String>,
+        factory: F,
+    ) -> Result<Arc<C>>
+    where
+        C: Component + 'static,
+        F: FnOnce(Sidecar) -> Fut,
+        Fut: Future<Output = Result<C>>,
+    {
+        let component = Arc::new(factory(self.with_component_name(name)).await?);
+        self.register_component(component.clone()).await?;
+        Ok(component)
+    }
+
     pub async fn register_app_ready_callback<F, Fut>(&self, callback: F)
     where
         F: FnOnce() -> Fut + Send + 'static,
@@ -119,9 +193,11 @@ impl Sidecar {
         let handle = TaskHandle::new();
         let cancel_token = handle.cancellation_token();
         let completion_handle = handle.clone();
+        let inner = self.inner.clone();
         info!(component = ?component_name, task = ?task_name, "core task run");
         self.inner.lifecycle_manager.spawn_task(async move {
             let mut task = Box::pin(task);
+            let started = Instant::now();
             tokio::select! {
                 _ = cancel_token.cancelled() => {
                     info!(component = ?component_name, task = ?task_name, "core task cancelled");
@@ -130,12 +206,49 @@ impl Sidecar {
                     info!(component = ?component_name, task = ?task_name, "core task down");
                 }
             }
+            // cancelled or not, the run is over
+            inner
+                .task_metrics
+                .record(&component_name, &task_name, started.elapsed(), false);
             completion_handle.mark_complete();
         });
 
         handle
     }
 
+    /// Run CPU heavy `task` on the blocking pool, tracked like core tasks so shutdown waits for
+    /// it. A running closure can't be interrupted, cancelling only stops waiting for its result
+    pub fn spawn_blocking_core<F, T>(
+        &self,
+        task_name: impl Into<String>,
+        task: F,
+    ) -> BlockingTaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let component_name = self.current_component_name.clone();
+        let task_name = task_name.into();
+        let handle = TaskHandle::new();
+        let completion_handle = handle.clone();
+        let inner = self.inner.clone();
+        let join = self.inner.lifecycle_manager.spawn_blocking_task(move || {
+            let started = Instant::now();
+            // caught to mark the handle complete and count the panic, then resumed for `join`
+            let output = panic::catch_unwind(AssertUnwindSafe(task));
+            inner.task_metrics.record(
+                &component_name,
+                &task_name,
+                started.elapsed(),
+                output.is_err(),
+            );
+            completion_handle.mark_complete();
+            output.unwrap_or_else(|payload| panic::resume_unwind(payload))
+        });
+
+        BlockingTaskHandle { handle, join }
+    }
+
     pub fn spawn_scheduled_task<T, F, Fut>(
         &self,
         task_name: impl Into<String>,
@@ -174,9 +287,26 @@ impl Sidecar {
                         info!(component = ?component_name, task = ?task_name, "scheduled task cancelled");
                         break;
                     }
-                    _ = ticker.tick() => {
-                        let fut = task(state.clone());
-                        let result = fut.await;
+                    _ = ticker.tick() => {}
+                }
+
+                // a tick in progress is dropped on cancellation so a slow one can't hold up shutdown
+                tokio::select! {
+                    _ = sidecar.canceled() => {
+                        info!(component = ?component_name, task = ?task_name, "scheduled task interrupted mid-tick, down");
+                        break;
+                    }
+                    _ = cancel_token.cancelled() => {
+                        info!(component = ?component_name, task = ?task_name, "scheduled task interrupted mid-tick, cancelled");
+                        break;
+                    }
+                    (result, elapsed) = timed(task(state.clone())) => {
+                        sidecar.inner.task_metrics.record(
+                            &component_name,
+                            &task_name,
+                            elapsed,
+                            result.is_err(),
+                        );
                         if let Err(err) = result {
                             warn!(
                                 component = ?component_name,
@@ -195,12 +325,13 @@ impl Sidecar {
         handle
     }
 
-    pub async fn run(self) -> Result<()> {
+    pub async fn run(self) -> Result<ShutdownSummary> {
         info!("components starting");
         let start_time = Instant::now();
         let active_components = self.start_components().await?;
         let elapsed = start_time.elapsed();
         info!(elapsed = ?elapsed, "components started");
+        _ = self.inner.started_at.set(Instant::now());
 
         for future in {
             let mut guard = self.inner.no_block_app_ready_callbacks.lock().await;
@@ -217,17 +348,31 @@ impl Sidecar {
         }
 
         info!("app is running");
-        self.inner.lifecycle_manager.wait().await;
+        let tasks_down = self.inner.lifecycle_manager.wait().await;
 
         info!("components stopping");
         let start_time = Instant::now();
-        self.stop_components(active_components).await?;
+        let mut components_stopped = Vec::new();
+        let stop_result = self
+            .stop_components(active_components, &mut components_stopped)
+            .await;
         let elapsed = start_time.elapsed();
         info!(elapsed = ?elapsed, "components stopped");
         self.inner.components.write().await.clear();
 
-        info!("app down");
-        Ok(())
+        let summary = ShutdownSummary {
+            uptime: self.uptime(),
+            components_stopped,
+            clean: tasks_down && stop_result.is_ok(),
+        };
+        info!(
+            uptime = ?summary.uptime,
+            components_stopped = ?summary.components_stopped,
+            clean = summary.clean,
+            "app down"
+        );
+        stop_result?;
+        Ok(summary)
     }
 
     async fn start_components(&self) -> Result<Vec<ComponentHandle>> {
@@ -242,37 +387,98 @@ impl Sidecar {
             let name = component.name().to_string();
             let start_time = Instant::now();
             info!(component = ?name, "component starting");
+            self.lifecycle_event(&name, LifecycleEvent::Starting, None, None);
             if let Err(err) = component
                 .start()
                 .await
                 .wrap_err_with(|| format!("Failed to start component[{name}] "))
             {
-                if let Err(stop_err) = self.stop_components(started).await {
+                self.lifecycle_event(
+                    &name,
+                    LifecycleEvent::StartFailed,
+                    Some(start_time.elapsed()),
+                    Some(&err),
+                );
+                if let Err(stop_err) = self.stop_components(started, &mut Vec::new()).await {
                     error!(error = ?stop_err, "rollback components failed after start error");
                 }
                 return Err(err);
             }
             info!(component = ?name, elapsed = ?start_time.elapsed(), "component started");
+            self.lifecycle_event(
+                &name,
+                LifecycleEvent::Started,
+                Some(start_time.elapsed()),
+                None,
+            );
             started.push(component.clone());
         }
 
         Ok(handles)
     }
 
-    async fn stop_components(&self, handles: Vec<ComponentHandle>) -> Result<()> {
-        for component in handles.into_iter().rev() {
+    async fn stop_components(
+        &self,
+        mut handles: Vec<ComponentHandle>,
+        stopped: &mut Vec<String>,
+    ) -> Result<()> {
+        handles.reverse();
+        // stable, so reverse start order is kept within a priority
+        handles.sort_by_key(|component| std::cmp::Reverse(component.shutdown_priority()));
+        for component in handles {
             let name = component.name().to_string();
             let start_time = Instant::now();
             info!(component = ?name, "component stopping");
-            component
+            self.lifecycle_event(&name, LifecycleEvent::Stopping, None, None);
+            if let Err(err) = component
                 .stop()
                 .await
-                .wrap_err_with(|| format!("Failed to stop component[{name}] "))?;
+                .wrap_err_with(|| format!("Failed to stop component[{name}] "))
+            {
+                self.lifecycle_event(
+                    &name,
+                    LifecycleEvent::StopFailed,
+                    Some(start_time.elapsed()),
+                    Some(&err),
+                );
+                return Err(err);
+            }
             info!(component = ?name, elapsed = ?start_time.elapsed(), "component stopped");
+            self.lifecycle_event(
+                &name,
+                LifecycleEvent::Stopped,
+                Some(start_time.elapsed()),
+                None,
+            );
+            stopped.push(name);
         }
 
         Ok(())
     }
+
+    fn lifecycle_event(
+        &self,
+        component: &str,
+        event: LifecycleEvent,
+        elapsed: Option<Duration>,
+        error: Option<&Report>,
+    ) {
+        if let Some(log) = self.inner.lifecycle_events.get() {
+            log.record(component, event, elapsed, error);
+        }
+    }
+}
+
+async fn timed<F: Future>(future: F) -> (F::Output, Duration) {
+    let started = Instant::now();
+    let output = future.await;
+    (output, started.elapsed())
+}
+
+impl Default for Sidecar {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Clone)]
@@ -315,10 +521,9 @@ impl TaskHandle {
             return true;
         }
 
-        match tokio::time::timeout(timeout, self.inner.completion_notify.notified()).await {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        tokio::time::timeout(timeout, self.inner.completion_notify.notified())
+            .await
+            .is_ok()
     }
 
     fn cancellation_token(&self) -> CancellationToken {
@@ -330,6 +535,28 @@ impl TaskHandle {
     }
 }
 
+/// Result of [`Sidecar::spawn_blocking_core`]
+pub struct BlockingTaskHandle<T> {
+    handle: TaskHandle,
+    join: JoinHandle<T>,
+}
+
+impl<T> BlockingTaskHandle<T> {
+    /// Cancels the wait in [`BlockingTaskHandle::join`] from elsewhere
+    pub fn task_handle(&self) -> TaskHandle {
+        self.handle.clone()
+    }
+
+    /// Output of the closure, an error when it panicked or the handle was cancelled first
+    pub async fn join(self) -> Result<T> {
+        let cancel_token = self.handle.cancellation_token();
+        tokio::select! {
+            _ = cancel_token.cancelled() => Err(eyre!("blocking task cancelled")),
+            output = self.join => output.wrap_err("blocking task panicked"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -365,6 +592,27 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_uptime_increases_after_run() -> Result<()> {
+        log::default_setup();
+        let sidecar = Sidecar::new();
+        assert_eq!(sidecar.uptime(), Duration::ZERO);
+
+        let run = tokio::spawn(sidecar.clone().run());
+        sleep(Duration::from_millis(100)).await;
+        let first = sidecar.uptime();
+        assert!(first > Duration::ZERO);
+
+        sleep(Duration::from_millis(100)).await;
+        let second = sidecar.uptime();
+        assert!(second >= first + Duration::from_millis(100));
+
+        sidecar.cancel().await?;
+        let summary = run.await??;
+        assert!(summary.uptime >= second);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_app_ready_callbacks() -> Result<()> {
         log::default_setup();
@@ -399,16 +647,16 @@ mod tests {
 
     impl TrackingComponent {
         async fn new(sidecar: &Sidecar) -> Result<Arc<Self>> {
-            let component = Arc::new(TrackingComponent {
-                name: "tracking",
-                sidecar: sidecar.with_component_name("tracking"),
-                start_count: Arc::new(AtomicUsize::new(0)),
-                stop_count: Arc::new(AtomicUsize::new(0)),
-            });
-
-            sidecar.register_component(component.clone()).await?;
-
-            Ok(component)
+            sidecar
+                .build_component("tracking", |sidecar| async move {
+                    Ok(TrackingComponent {
+                        name: "tracking",
+                        sidecar,
+                        start_count: Arc::new(AtomicUsize::new(0)),
+                        stop_count: Arc::new(AtomicUsize::new(0)),
+                    })
+                })
+                .await
         }
     }
 
@@ -449,7 +697,10 @@ mod tests {
 
         let component = TrackingComponent::new(&sidecar).await?;
 
-        sidecar.run().await?;
+        let summary = sidecar.run().await?;
+        assert!(summary.clean, "Shutdown not clean");
+        assert_eq!(summary.components_stopped, ["tracking"]);
+        assert!(summary.uptime >= Duration::from_secs(1));
 
         assert_eq!(
             component.start_count.load(Ordering::SeqCst),
@@ -465,6 +716,119 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_lifecycle_events_written_as_json_lines() -> Result<()> {
+        log::default_setup();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("logs").join("lifecycle.jsonl");
+        let sidecar = Sidecar::new();
+        sidecar.log_lifecycle_events_to(&path)?;
+        TrackingComponent::new(&sidecar).await?;
+
+        sidecar.run().await?;
+
+        let lines = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        let events = lines
+            .iter()
+            .map(|line| (line["component"].as_str(), line["event"].as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(events, [
+            (Some("tracking"), Some("starting")),
+            (Some("tracking"), Some("started")),
+            (Some("tracking"), Some("stopping")),
+            (Some("tracking"), Some("stopped")),
+        ]);
+        assert_eq!(lines[1]["instance_id"], crate::instance::id());
+        assert!(lines[1]["elapsed_ms"].is_u64());
+        assert!(lines[1].get("error").is_none());
+        Ok(())
+    }
+
+    struct PriorityComponent {
+        name: &'static str,
+        priority: i32,
+    }
+
+    #[async_trait]
+    impl Component for PriorityComponent {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn shutdown_priority(&self) -> i32 {
+            self.priority
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_priority_overrides_reverse_start_order() -> Result<()> {
+        log::default_setup();
+        let sidecar = Sidecar::new();
+
+        for (name, priority) in [("bus", 0), ("db", 0), ("http", 10), ("metrics", 0)] {
+            sidecar
+                .register_component(Arc::new(PriorityComponent { name, priority }))
+                .await?;
+        }
+        sidecar
+            .register_no_block_app_ready_callback({
+                let sidecar = sidecar.clone();
+                move || async move {
+                    sidecar.cancel().await.unwrap();
+                }
+            })
+            .await;
+
+        let summary = sidecar.run().await?;
+        assert_eq!(summary.components_stopped, ["http", "metrics", "db", "bus"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_register_component_rejects_duplicate_name() -> Result<()> {
+        let sidecar = Sidecar::new();
+        sidecar
+            .register_component(Arc::new(PriorityComponent {
+                name: "db",
+                priority: 0,
+            }))
+            .await?;
+
+        let err = sidecar
+            .register_component(Arc::new(PriorityComponent {
+                name: "db",
+                priority: 10,
+            }))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("db"), "{err}");
+        assert_eq!(sidecar.inner.components.read().await.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_component_names_and_registers() -> Result<()> {
+        let sidecar = Sidecar::new();
+        let tracking = TrackingComponent::new(&sidecar).await?;
+        assert_eq!(tracking.sidecar.current_component_name, "tracking");
+        assert_eq!(sidecar.inner.components.read().await.len(), 1);
+
+        // a failed factory registers nothing
+        let err = sidecar
+            .build_component("broken", |_| async {
+                Err::<PriorityComponent, _>(eyre!("no config"))
+            })
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no config"), "{err}");
+        assert_eq!(sidecar.inner.components.read().await.len(), 1);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_core_task_handle_cancel() -> Result<()> {
         log::default_setup();
@@ -493,6 +857,80 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_blocking_task_is_awaited_on_shutdown() -> Result<()> {
+        log::default_setup();
+        let sidecar = Sidecar::new().with_component_name("blocking");
+
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = sidecar.spawn_blocking_core("slow_hash", {
+            let done = done.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(300));
+                done.store(true, Ordering::SeqCst);
+                42
+            }
+        });
+
+        let run = tokio::spawn(sidecar.clone().run());
+        sidecar.cancel().await?;
+        let summary = run.await??;
+        assert!(summary.clean);
+        assert!(
+            done.load(Ordering::SeqCst),
+            "shutdown did not wait for the blocking task"
+        );
+        assert_eq!(handle.join().await?, 42);
+
+        let stats = sidecar.task_metrics();
+        assert!(
+            stats
+                .iter()
+                .any(|s| s.task_name == "slow_hash" && s.runs == 1)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_task_handle_cancel_stops_waiting() -> Result<()> {
+        let sidecar = Sidecar::new();
+        let handle = sidecar.spawn_blocking_core("slow", || {
+            std::thread::sleep(Duration::from_millis(300));
+        });
+
+        handle.task_handle().cancel(Duration::from_millis(10)).await;
+        let err = tokio::time::timeout(Duration::from_millis(100), handle.join())
+            .await?
+            .unwrap_err();
+        assert!(err.to_string().contains("cancelled"), "{err}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_task_panic_completes_and_counts_as_failure() -> Result<()> {
+        let sidecar = Sidecar::new();
+        let handle = sidecar.spawn_blocking_core("explode", || -> u32 {
+            panic!("blocking task expected panic");
+        });
+        let task_handle = handle.task_handle();
+
+        let err = handle.join().await.unwrap_err();
+        assert!(err.to_string().contains("panicked"), "{err}");
+        assert!(task_handle.cancel(Duration::from_millis(10)).await);
+
+        let stats = sidecar.task_metrics();
+        assert!(
+            stats
+                .iter()
+                .any(|s| s.task_name == "explode" && s.runs == 1 && s.failures == 1),
+            "{stats:?}"
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_spawn_scheduled_task_runs_multiple_times() -> Result<()> {
         log::default_setup();
@@ -557,6 +995,34 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_spawn_scheduled_task_cancel_interrupts_running_tick() -> Result<()> {
+        log::default_setup();
+        let sidecar = Sidecar::new().with_component_name("interval_long_tick");
+
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let handle = sidecar.spawn_scheduled_task(
+            "scheduled_long_tick",
+            Duration::from_millis(10),
+            finished.clone(),
+            |finished| async move {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                finished.store(true, Ordering::SeqCst);
+                Ok(())
+            },
+        );
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(
+            handle.cancel(Duration::from_millis(100)).await,
+            "Running tick not interrupted by cancellation"
+        );
+        assert!(!finished.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_spawn_scheduled_task_handles_error() -> Result<()> {
         log::default_setup();
@@ -611,4 +1077,63 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_task_metrics_labeled_by_component_and_task() -> Result<()> {
+        log::default_setup();
+        let sidecar = Sidecar::new();
+
+        let toggle = Arc::new(AtomicBool::new(false));
+        sidecar.with_component_name("cleaner").spawn_scheduled_task(
+            "sweep",
+            Duration::from_millis(10),
+            toggle,
+            |toggle| async move {
+                if toggle.fetch_not(Ordering::SeqCst) {
+                    eyre::bail!("sweep failed")
+                }
+                Ok(())
+            },
+        );
+        let core = sidecar
+            .with_component_name("loader")
+            .spawn_core_task("warmup", async {});
+        let watch = sidecar
+            .with_component_name("loader")
+            .spawn_core_task("watch", std::future::pending::<()>());
+
+        tokio::time::sleep(Duration::from_millis(45)).await;
+        assert!(watch.cancel(Duration::from_millis(100)).await);
+        sidecar.cancel().await?;
+        assert!(core.cancel(Duration::from_millis(100)).await);
+
+        let metrics = sidecar.task_metrics();
+        assert_eq!(metrics.len(), 3);
+
+        let sweep = &metrics[0];
+        assert_eq!(
+            (sweep.component.as_str(), sweep.task_name.as_str()),
+            ("cleaner", "sweep")
+        );
+        assert!(sweep.runs >= 2, "{sweep:?}");
+        assert!(
+            sweep.failures >= 1 && sweep.failures < sweep.runs,
+            "{sweep:?}"
+        );
+
+        let warmup = &metrics[1];
+        assert_eq!(
+            (warmup.component.as_str(), warmup.task_name.as_str()),
+            ("loader", "warmup")
+        );
+        assert_eq!(warmup.runs, 1);
+        assert_eq!(warmup.failures, 0);
+
+        // a cancelled core task finished a run too
+        let watch = &metrics[2];
+        assert_eq!(watch.task_name, "watch");
+        assert_eq!(watch.runs, 1);
+
+        Ok(())
+    }
 }
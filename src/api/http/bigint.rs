@@ -0,0 +1,67 @@
+//! Integer response fields as json strings, for javascript clients whose numbers lose precision
+//! past 2^53. Fields opt in with `#[serde(serialize_with = "bigint::serialize")]` and
+//! `#[schema(value_type = BigInt)]`, and switch together with `http.bigint_as_string` while an
+//! envelope is rendered
+
+use std::cell::Cell;
+
+use serde::{Serialize, Serializer};
+
+thread_local! {
+    static AS_STRING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Schema of the opted in fields, either form is valid whatever `http.bigint_as_string` says
+#[allow(dead_code)]
+#[derive(Serialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum BigInt {
+    /// Integer, by default
+    Integer(i64),
+    /// Decimal string, with `http.bigint_as_string`
+    String(String),
+}
+
+/// Run `render` with the opted in fields serialized as strings when `as_string`. Rendering is
+/// synchronous, so the flag can't leak into other requests served by the same thread
+pub fn render_with<R>(as_string: bool, render: impl FnOnce() -> R) -> R {
+    let previous = AS_STRING.replace(as_string);
+    let rendered = render();
+    AS_STRING.set(previous);
+    rendered
+}
+
+pub fn serialize<S: Serializer>(value: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    if AS_STRING.get() {
+        serializer.collect_str(value)
+    } else {
+        serializer.serialize_i64(*value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::api::http::user::LoginRes;
+
+    #[test]
+    fn test_expired_time_is_a_string_only_when_enabled() {
+        let res = LoginRes {
+            user_id: "alice".to_string(),
+            jwt_token: "token".to_string(),
+            expired_time: 9_007_199_254_740_993,
+        };
+
+        let value = render_with(false, || serde_json::to_value(&res).unwrap());
+        assert_eq!(value["expired_time"], json!(9_007_199_254_740_993i64));
+
+        let value = render_with(true, || serde_json::to_value(&res).unwrap());
+        assert_eq!(value["expired_time"], json!("9007199254740993"));
+
+        // restored once rendered
+        let value = serde_json::to_value(&res).unwrap();
+        assert!(value["expired_time"].is_i64(), "{value}");
+    }
+}
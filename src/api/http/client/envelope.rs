@@ -0,0 +1,110 @@
+use std::fmt::Debug;
+
+use serde::Deserialize;
+use sidecar::prelude::*;
+
+use super::apis;
+use super::models;
+use crate::kit::error::Error;
+
+/// Failure reported by the server in the response envelope, `code` is the server side error code
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Request api failed code: {code}, msg: {msg}")]
+pub struct ApiError {
+    pub code: i64,
+    pub msg: String,
+}
+
+impl ApiError {
+    /// Whether the server reported a transient failure, see [`Error::is_retryable`]
+    pub fn is_retryable(&self) -> bool {
+        u64::try_from(self.code).is_ok_and(Error::is_retryable_code)
+    }
+
+    /// Whether the server failed with `err`, to react to specific errors
+    pub fn is(&self, err: &Error) -> bool {
+        u64::try_from(self.code).is_ok_and(|code| code == err.code())
+    }
+}
+
+/// `Response { code, msg, data }` envelope of the generated client models
+pub trait Envelope {
+    type Data;
+
+    fn into_parts(self) -> (i64, String, Option<Self::Data>);
+}
+
+macro_rules! impl_envelope {
+    ($($response:ty => $data:ty),* $(,)?) => {
+        $(impl Envelope for $response {
+            type Data = $data;
+
+            fn into_parts(self) -> (i64, String, Option<Self::Data>) {
+                (self.code, self.msg, self.data.map(|data| *data))
+            }
+        })*
+    };
+}
+
+impl_envelope! {
+    models::ResponseRegisterRes => models::ResponseRegisterResData,
+    models::ResponseLoginRes => models::ResponseLoginResData,
+    models::ResponseRefreshTokenRes => models::ResponseRefreshTokenResData,
+}
+
+impl Envelope for models::ResponseString {
+    type Data = String;
+
+    fn into_parts(self) -> (i64, String, Option<Self::Data>) {
+        (self.code, self.msg, self.data)
+    }
+}
+
+/// Envelope of endpoints without a generated client, e.g. the ipc only `/internal` ones
+#[derive(Debug, Deserialize)]
+pub struct JsonEnvelope {
+    code: i64,
+    msg: String,
+    data: Option<serde_json::Value>,
+}
+
+impl Envelope for JsonEnvelope {
+    type Data = serde_json::Value;
+
+    fn into_parts(self) -> (i64, String, Option<Self::Data>) {
+        (self.code, self.msg, self.data)
+    }
+}
+
+/// Unwraps the data of a generated api call, every non-zero envelope code becomes an [`ApiError`]
+/// so callers can branch on it with `downcast_ref`
+pub fn response_data<R, E>(response: std::result::Result<R, apis::Error<E>>) -> Result<R::Data>
+where
+    R: Envelope,
+    E: Debug + Send + Sync + 'static,
+{
+    let (code, msg, data) = match response {
+        Ok(response) => response.into_parts(),
+        Err(apis::Error::ResponseError(resp)) => {
+            #[derive(Deserialize)]
+            struct ErrorEnvelope {
+                code: i64,
+                msg: String,
+            }
+
+            return match serde_json::from_str::<ErrorEnvelope>(&resp.content) {
+                Ok(ErrorEnvelope { code, msg }) => Err(ApiError { code, msg }.into()),
+                Err(_) => Err(eyre!(
+                    "Request failed, status code: {}, body: {}",
+                    resp.status,
+                    resp.content
+                )),
+            };
+        }
+        Err(other) => return Err(eyre!(other)),
+    };
+    if code != 0 {
+        return Err(ApiError { code, msg }.into());
+    }
+    data.ok_or_else(|| eyre!("Not found data"))
+}
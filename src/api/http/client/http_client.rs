@@ -0,0 +1,191 @@
+use sidecar::prelude::*;
+
+use super::apis::configuration::Configuration;
+use super::apis::user_api::{self, UserLoginParams, UserRegisterParams};
+use super::envelope::response_data;
+use super::models;
+
+/// Async sdk for the http transport, wraps the generated apis and keeps the bearer token issued by
+/// login/refresh for later calls
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    configuration: Configuration,
+}
+
+impl HttpClient {
+    pub fn new(base_url: impl Into<String>, bearer_token: Option<String>) -> Self {
+        let mut configuration = Configuration::new();
+        configuration.base_path = base_url.into().trim_end_matches('/').to_string();
+        configuration.bearer_access_token = bearer_token;
+        Self { configuration }
+    }
+
+    /// Generated apis not covered by the typed methods can be called with this
+    pub fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
+
+    pub fn bearer_token(&self) -> Option<&str> {
+        self.configuration.bearer_access_token.as_deref()
+    }
+
+    pub fn set_bearer_token(&mut self, bearer_token: Option<String>) {
+        self.configuration.bearer_access_token = bearer_token;
+    }
+
+    /// Login and use the issued token for the following calls
+    pub async fn login(
+        &mut self,
+        auth_type: models::AuthType,
+        auth_id: impl Into<String>,
+        auth_token: impl Into<String>,
+    ) -> Result<models::ResponseLoginResData> {
+        let data = response_data(
+            user_api::user_login(&self.configuration, UserLoginParams {
+                auth_type,
+                auth_id: auth_id.into(),
+                auth_token: auth_token.into(),
+            })
+            .await,
+        )?;
+        self.set_bearer_token(Some(data.jwt_token.clone()));
+        Ok(data)
+    }
+
+    pub async fn register(
+        &self,
+        register_req: models::RegisterReq,
+    ) -> Result<models::ResponseRegisterResData> {
+        response_data(
+            user_api::user_register(&self.configuration, UserRegisterParams { register_req }).await,
+        )
+    }
+
+    /// Exchange the current token for a new one and keep using the new one
+    pub async fn refresh_token(&mut self) -> Result<models::ResponseRefreshTokenResData> {
+        let data = response_data(user_api::user_refresh_token(&self.configuration).await)?;
+        self.set_bearer_token(Some(data.jwt_token.clone()));
+        Ok(data)
+    }
+}
+
+impl models::BigInt {
+    /// The integer, whichever form `http.bigint_as_string` had the server send it in
+    pub fn to_i64(&self) -> Result<i64> {
+        match self {
+            models::BigInt::I64(value) => Ok(*value),
+            models::BigInt::String(value) => value
+                .parse()
+                .wrap_err(format!("Invalid integer string: {value}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use sea_orm::{DatabaseBackend, MockDatabase, Set, TryIntoModel};
+    use sidecar::repo::Repo;
+    use sidecar::sidecar::Sidecar;
+    use tempfile::{TempDir, tempdir};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::api::http::client::ApiError;
+    use crate::api::http::server::{AppState, Server};
+    use crate::core::core::Core;
+    use crate::core::model::{user, user_auth};
+    use crate::core::service::user::hash_password;
+    use crate::kit::config::Config;
+    use crate::kit::error::Error;
+
+    /// Serve the router over tcp with a mocked db that lets `admin`/`secret` login once and
+    /// resolve the user twice
+    async fn serve(configure: impl FnOnce(&mut Config)) -> Result<(String, user::Model, TempDir)> {
+        let mut user = user::ActiveModel::create();
+        user.role = Set(user::Role::Admin);
+        let user = user.try_into_model()?;
+        let mut auth = user_auth::ActiveModel::create();
+        auth.user_id = Set(user.id.clone());
+        auth.auth_id = Set("admin".to_string());
+        auth.auth_token = Set(hash_password("secret")?);
+        let auth = auth.try_into_model()?;
+
+        let tmp = tempdir()?;
+        let mut repo = Repo::<Config>::new(tmp.path(), "http-client-test").await?;
+        configure(&mut repo.cfg);
+        let core = Core::new(Sidecar::new(), repo).await?;
+        core.db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![auth]])
+                    .append_query_results([vec![user.clone()], vec![user.clone()]])
+                    .into_connection(),
+            )
+            .await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let router = Server::router().with_state(AppState {
+            core,
+            is_ipc: false,
+            rate_limiter: None,
+            login_backoff: None,
+            scheduler: None,
+            shutting_down: Default::default(),
+        });
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        Ok((format!("http://{addr}"), user, tmp))
+    }
+
+    #[tokio::test]
+    async fn login_then_authenticated_call_against_in_process_server() -> Result<()> {
+        let (base_url, user, _tmp) = serve(|_| {}).await?;
+
+        let mut client = HttpClient::new(base_url, None);
+        let login = client
+            .login(models::AuthType::Username, "admin", "secret")
+            .await?;
+        assert_eq!(login.user_id, user.id);
+        assert!(matches!(*login.expired_time, models::BigInt::I64(_)));
+        assert_eq!(client.bearer_token(), Some(login.jwt_token.as_str()));
+
+        let refreshed = client.refresh_token().await?;
+        assert_eq!(refreshed.user_id, user.id);
+
+        client.set_bearer_token(None);
+        let err = client.refresh_token().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ApiError>().map(|err| err.code),
+            Some(Error::MissingToken.code() as i64)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn login_and_refresh_with_bigint_as_string() -> Result<()> {
+        let (base_url, user, _tmp) = serve(|cfg| cfg.http.bigint_as_string = true).await?;
+
+        let mut client = HttpClient::new(base_url, None);
+        let login = client
+            .login(models::AuthType::Username, "admin", "secret")
+            .await?;
+        assert_eq!(login.user_id, user.id);
+        assert!(matches!(*login.expired_time, models::BigInt::String(_)));
+        assert!(login.expired_time.to_i64()? > 0);
+
+        let refreshed = client.refresh_token().await?;
+        assert!(refreshed.expired_time.to_i64()? >= login.expired_time.to_i64()?);
+
+        Ok(())
+    }
+}
@@ -1,2 +1,10 @@
+// apis and models are generated by `just generate-openapi-client`, keep them untouched by lints
+#[allow(clippy::all)]
 pub mod apis;
+pub mod envelope;
+pub mod http_client;
+#[allow(clippy::all)]
 pub mod models;
+
+pub use envelope::{ApiError, response_data};
+pub use http_client::HttpClient;
@@ -0,0 +1,28 @@
+/*
+ * rs-project-startup
+ *
+ * A framework for quickly starting a Rust project
+ *
+ * The version of the OpenAPI document: 0.1.0
+ *
+ * Generated by: https://openapi-generator.tech
+ */
+
+use serde::{Deserialize, Serialize};
+
+/// BigInt : Schema of the opted in fields, either form is valid whatever `http.bigint_as_string` says
+/// Schema of the opted in fields, either form is valid whatever `http.bigint_as_string` says
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BigInt {
+    /// Integer, by default
+    I64(i64),
+    /// Decimal string, with `http.bigint_as_string`
+    String(String),
+}
+
+impl Default for BigInt {
+    fn default() -> Self {
+        Self::I64(Default::default())
+    }
+}
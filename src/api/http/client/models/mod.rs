@@ -1,5 +1,7 @@
 pub mod auth_type;
 pub use self::auth_type::AuthType;
+pub mod big_int;
+pub use self::big_int::BigInt;
 pub mod login_req;
 pub use self::login_req::LoginReq;
 pub mod login_res;
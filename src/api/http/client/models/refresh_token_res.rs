@@ -10,12 +10,14 @@
 
 use serde::{Deserialize, Serialize};
 
+use super::super::models;
+
 /// RefreshTokenRes : Refresh JWT Token response body
 #[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct RefreshTokenRes {
-    /// Token expiration time (Unix timestamp, seconds)
+    /// Token expiration time (Unix timestamp, seconds), a string with `http.bigint_as_string`
     #[serde(rename = "expired_time")]
-    pub expired_time: i64,
+    pub expired_time: Box<models::BigInt>,
     /// Issued JWT token
     #[serde(rename = "jwt_token")]
     pub jwt_token: String,
@@ -26,9 +28,13 @@ pub struct RefreshTokenRes {
 
 impl RefreshTokenRes {
     /// Refresh JWT Token response body
-    pub fn new(expired_time: i64, jwt_token: String, user_id: String) -> RefreshTokenRes {
+    pub fn new(
+        expired_time: models::BigInt,
+        jwt_token: String,
+        user_id: String,
+    ) -> RefreshTokenRes {
         RefreshTokenRes {
-            expired_time,
+            expired_time: Box::new(expired_time),
             jwt_token,
             user_id,
         }
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use serde_json::Value;
+use sidecar::prelude::*;
+use utoipa::OpenApi;
+
+use crate::api::http::validation::ValidateRequest;
+use crate::core::core::Core;
+use crate::core::db::PoolStats;
+use crate::kit::context::Context;
+use crate::kit::response::Response;
+
+/// Internal module OpenAPI documentation, these endpoints are only served over ipc
+#[derive(OpenApi)]
+#[openapi(
+    paths(db_stats, config),
+    components(schemas(PoolStats, Response<PoolStats>, Response<Value>)),
+    tags((name = "internal", description = "Operational APIs served over ipc"))
+)]
+pub struct InternalApiDoc;
+
+/// DB pool stats endpoint
+#[utoipa::path(
+    tag = "internal",
+    operation_id = "internal_db_stats",
+    get,
+    path = "/db/stats",
+    summary = "DB connection pool stats",
+    description = "Return the size, idle and in-use connection counts of the db pool.",
+    responses((status = 200, description = "Success", body = Response<PoolStats>))
+)]
+pub async fn db_stats(
+    state: Arc<Core>,
+    _ctx: Context,
+    _headers: HeaderMap,
+    _req: (),
+) -> Result<PoolStats> {
+    state.db.pool_stats().await
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConfigReq {
+    /// Return the db password and jwt hmac key in clear text
+    #[serde(default)]
+    show_secrets: bool,
+}
+
+impl ValidateRequest for ConfigReq {}
+
+/// Effective config endpoint
+#[utoipa::path(
+    tag = "internal",
+    operation_id = "internal_config",
+    get,
+    path = "/config",
+    params(ConfigReq),
+    summary = "Effective config",
+    description = "Return the config the running instance uses, including env and config_kv overrides. Secrets are redacted unless show_secrets is set.",
+    responses((status = 200, description = "Success", body = Response<Value>))
+)]
+pub async fn config(
+    state: Arc<Core>,
+    _ctx: Context,
+    _headers: HeaderMap,
+    req: ConfigReq,
+) -> Result<Value> {
+    let cfg = state.repo.cfg.clone();
+    let cfg = if req.show_secrets {
+        cfg
+    } else {
+        cfg.redacted()
+    };
+    Ok(serde_json::to_value(cfg)?)
+}
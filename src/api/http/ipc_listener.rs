@@ -0,0 +1,90 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::serve::Listener;
+use tokio::net::{UnixListener, UnixStream, unix};
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::api::http::server::secure_ipc_socket;
+use crate::kit::config::IPC;
+
+/// Unix listener that binds the socket file again once it's deleted from under it, e.g. by a tmp
+/// cleaner. The old listener keeps its inode but nobody can connect to it anymore. Connections
+/// already accepted are untouched
+pub struct IpcListener {
+    listener: UnixListener,
+    path: PathBuf,
+    ipc_cfg: IPC,
+    /// None when `ipc.rebind_check_interval` is zero
+    rebind_check: Option<Interval>,
+}
+
+impl IpcListener {
+    pub fn new(listener: UnixListener, path: PathBuf, ipc_cfg: IPC) -> Self {
+        let rebind_check = (ipc_cfg.rebind_check_interval > Duration::ZERO).then(|| {
+            let mut interval = tokio::time::interval(ipc_cfg.rebind_check_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        Self {
+            listener,
+            path,
+            ipc_cfg,
+            rebind_check,
+        }
+    }
+
+    /// A failed rebind is retried on the next check
+    fn rebind_if_missing(&mut self) {
+        if self.path.exists() {
+            return;
+        }
+        warn!(path = %self.path.display(), "ipc file was removed, rebinding");
+        let listener = match UnixListener::bind(&self.path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!(path = %self.path.display(), err = ?err, "ipc rebind failed");
+                return;
+            }
+        };
+        if let Err(err) = secure_ipc_socket(&self.path, &self.ipc_cfg) {
+            warn!(path = %self.path.display(), err = ?err, "ipc rebind failed");
+            return;
+        }
+        self.listener = listener;
+        info!(path = %self.path.display(), "ipc server listen again");
+    }
+}
+
+impl Listener for IpcListener {
+    type Addr = unix::SocketAddr;
+    type Io = UnixStream;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let tick = async {
+                match self.rebind_check.as_mut() {
+                    Some(interval) => _ = interval.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok(accepted) => return accepted,
+                    Err(err) => {
+                        warn!(err = ?err, "ipc server accept failed");
+                        // e.g. out of fds, don't spin on it
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                },
+                _ = tick => self.rebind_if_missing(),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.listener.local_addr()
+    }
+}
@@ -0,0 +1,109 @@
+use axum::http::{HeaderMap, header};
+use sidecar::prelude::*;
+
+use crate::kit::config::JsonLimit;
+
+/// Same check as axum's `Json` extractor, which can't be used because the body is scanned first
+pub fn is_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == "application/json"
+        || (essence.starts_with("application/") && essence.ends_with("+json"))
+}
+
+/// Reject bodies nested deeper than `max_depth` or with more than `max_fields` object members in
+/// total. Runs on the raw bytes so an abusive payload is refused before serde allocates for it,
+/// malformed json is left for the deserializer to report
+pub fn check(body: &[u8], limit: &JsonLimit) -> Result<()> {
+    let mut depth = 0usize;
+    let mut fields = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for &byte in body {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                ensure!(
+                    depth <= limit.max_depth,
+                    "json nested deeper than {} levels",
+                    limit.max_depth
+                );
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            // every object member has exactly one colon outside of strings
+            b':' => {
+                fields += 1;
+                ensure!(
+                    fields <= limit.max_fields,
+                    "json has more than {} fields",
+                    limit.max_fields
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: JsonLimit = JsonLimit {
+        max_depth: 4,
+        max_fields: 3,
+    };
+
+    #[test]
+    fn test_check_rejects_deep_nesting_and_too_many_fields() {
+        assert!(check(br#"{"a":[{"b":[1]}]}"#, &LIMIT).is_ok());
+        assert!(check(br#"{"a":[{"b":[[1]]}]}"#, &LIMIT).is_err());
+        assert!(check(&[b'['; 10_000], &LIMIT).is_err());
+
+        assert!(check(br#"{"a":1,"b":2,"c":3}"#, &LIMIT).is_ok());
+        assert!(check(br#"{"a":1,"b":2,"c":{"d":4}}"#, &LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_check_ignores_structure_inside_strings() {
+        assert!(check(br#"{"a":"[[[[[:::::\"{{{{{"}"#, &LIMIT).is_ok());
+    }
+
+    #[test]
+    fn test_json_content_type() {
+        let headers = |value: &'static str| {
+            HeaderMap::from_iter([(header::CONTENT_TYPE, value.parse().unwrap())])
+        };
+
+        assert!(is_json_content_type(&headers("application/json")));
+        assert!(is_json_content_type(&headers(
+            "application/json; charset=utf-8"
+        )));
+        assert!(is_json_content_type(&headers("application/problem+json")));
+        assert!(!is_json_content_type(&headers("text/plain")));
+        assert!(!is_json_content_type(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,294 @@
+use axum::extract::{FromRequestParts, Query};
+use axum::http::Uri;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response as AxumResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::kit::config::PageLimits;
+use crate::kit::error::Error;
+use crate::kit::query;
+use crate::kit::response::Response;
+
+/// Limits of a list endpoint, attached to its route as an `Extension` (see `wrap_list_handler`).
+/// Extractors on routes without one use [`ListSpec::default`]
+#[derive(Debug, Clone)]
+pub struct ListSpec {
+    default_limit: u64,
+    max_limit: u64,
+    sort_fields: Vec<&'static str>,
+    default_sort: Option<(&'static str, SortDir)>,
+}
+
+impl Default for ListSpec {
+    fn default() -> Self {
+        Self {
+            default_limit: 20,
+            max_limit: 100,
+            sort_fields: vec![],
+            default_sort: None,
+        }
+    }
+}
+
+impl ListSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn default_limit(mut self, default_limit: u64) -> Self {
+        self.default_limit = default_limit;
+        self
+    }
+
+    /// Larger limits are clamped instead of rejected
+    pub fn max_limit(mut self, max_limit: u64) -> Self {
+        self.max_limit = max_limit;
+        self
+    }
+
+    /// Overrides of `http.page_limits`, a max below the default also lowers the default
+    pub fn with_page_limits(mut self, limits: Option<&PageLimits>) -> Self {
+        let Some(limits) = limits else {
+            return self;
+        };
+        if let Some(max_limit) = limits.max_limit {
+            self.max_limit = max_limit;
+        }
+        if let Some(default_limit) = limits.default_limit {
+            self.default_limit = default_limit;
+        }
+        self.default_limit = self.default_limit.min(self.max_limit);
+        self
+    }
+
+    /// Fields accepted by `sort`, anything else is rejected. No fields means no sorting
+    pub fn sort_fields(mut self, sort_fields: impl IntoIterator<Item = &'static str>) -> Self {
+        self.sort_fields = sort_fields.into_iter().collect();
+        self
+    }
+
+    /// Used when the request has no `sort`
+    pub fn default_sort(mut self, field: &'static str, dir: SortDir) -> Self {
+        self.default_sort = Some((field, dir));
+        self
+    }
+}
+
+/// Request of a `wrap_list_handler` endpoint, `filter` is the rest of the query
+#[derive(Debug)]
+pub struct ListReq<Q> {
+    pub filter: Q,
+    pub page: PageParams,
+    pub sort: SortParams,
+}
+
+/// `?offset=40&limit=20`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageParams {
+    pub offset: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDir {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// `?sort=create_time&dir=desc`, `field` is one of [`ListSpec::sort_fields`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortParams {
+    pub field: Option<&'static str>,
+    pub dir: SortDir,
+}
+
+#[derive(Deserialize)]
+struct RawPage {
+    #[serde(default, deserialize_with = "query::flexible_option_number")]
+    offset: Option<u64>,
+    #[serde(default, deserialize_with = "query::flexible_option_number")]
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RawSort {
+    #[serde(default)]
+    sort: Option<String>,
+    #[serde(default)]
+    dir: Option<SortDir>,
+}
+
+impl PageParams {
+    fn from_parts(parts: &Parts) -> Result<Self, ListParamsRejection> {
+        Self::from_uri(&parts.uri, &spec(parts))
+    }
+
+    pub fn from_uri(uri: &Uri, spec: &ListSpec) -> Result<Self, ListParamsRejection> {
+        let Query(raw) = Query::<RawPage>::try_from_uri(uri)
+            .map_err(|rejection| ListParamsRejection(rejection.body_text()))?;
+
+        let limit = raw.limit.unwrap_or(spec.default_limit);
+        if limit == 0 {
+            return Err(ListParamsRejection("limit must be positive".to_string()));
+        }
+        Ok(Self {
+            offset: raw.offset.unwrap_or(0),
+            limit: limit.min(spec.max_limit),
+        })
+    }
+}
+
+impl SortParams {
+    fn from_parts(parts: &Parts) -> Result<Self, ListParamsRejection> {
+        let spec = spec(parts);
+        let Query(raw) = Query::<RawSort>::try_from_uri(&parts.uri)
+            .map_err(|rejection| ListParamsRejection(rejection.body_text()))?;
+
+        let Some(sort) = raw.sort.filter(|sort| !sort.is_empty()) else {
+            let (field, dir) = spec.default_sort.unzip();
+            return Ok(Self {
+                field,
+                dir: raw.dir.or(dir).unwrap_or_default(),
+            });
+        };
+        let field = spec
+            .sort_fields
+            .iter()
+            .find(|field| **field == sort)
+            .ok_or_else(|| {
+                ListParamsRejection(format!(
+                    "unsupported sort field {sort}, expected one of {:?}",
+                    spec.sort_fields
+                ))
+            })?;
+        Ok(Self {
+            field: Some(field),
+            dir: raw.dir.unwrap_or_default(),
+        })
+    }
+}
+
+fn spec(parts: &Parts) -> ListSpec {
+    parts
+        .extensions
+        .get::<ListSpec>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for PageParams {
+    type Rejection = ListParamsRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::from_parts(parts)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for SortParams {
+    type Rejection = ListParamsRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::from_parts(parts)
+    }
+}
+
+/// Invalid paging or sorting, answered as [`Error::InvidRequestParameter`]
+#[derive(Debug)]
+pub struct ListParamsRejection(pub String);
+
+impl IntoResponse for ListParamsRejection {
+    fn into_response(self) -> AxumResponse {
+        Response::<()>::err(&Error::InvidRequestParameter(self.0)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    fn parts(uri: &str, spec: Option<ListSpec>) -> Parts {
+        let mut request = Request::get(uri).body(()).unwrap();
+        if let Some(spec) = spec {
+            request.extensions_mut().insert(spec);
+        }
+        request.into_parts().0
+    }
+
+    #[tokio::test]
+    async fn test_page_params_defaults_and_clamping() {
+        let spec = ListSpec::new().default_limit(10).max_limit(50);
+
+        let page = PageParams::from_request_parts(&mut parts("/list", Some(spec.clone())), &())
+            .await
+            .unwrap();
+        assert_eq!(page, PageParams {
+            offset: 0,
+            limit: 10
+        });
+
+        let page = PageParams::from_request_parts(
+            &mut parts("/list?offset=5&limit=500", Some(spec.clone())),
+            &(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page, PageParams {
+            offset: 5,
+            limit: 50
+        });
+
+        for uri in ["/list?limit=0", "/list?limit=-1", "/list?offset=abc"] {
+            assert!(
+                PageParams::from_request_parts(&mut parts(uri, Some(spec.clone())), &())
+                    .await
+                    .is_err(),
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sort_params_allowlist() {
+        let spec = ListSpec::new()
+            .sort_fields(["create_time", "name"])
+            .default_sort("create_time", SortDir::Desc);
+
+        let sort = SortParams::from_request_parts(&mut parts("/list", Some(spec.clone())), &())
+            .await
+            .unwrap();
+        assert_eq!(sort, SortParams {
+            field: Some("create_time"),
+            dir: SortDir::Desc
+        });
+
+        let sort = SortParams::from_request_parts(
+            &mut parts("/list?sort=name&dir=desc", Some(spec.clone())),
+            &(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(sort, SortParams {
+            field: Some("name"),
+            dir: SortDir::Desc
+        });
+
+        for uri in ["/list?sort=password", "/list?sort=name&dir=up"] {
+            assert!(
+                SortParams::from_request_parts(&mut parts(uri, Some(spec.clone())), &())
+                    .await
+                    .is_err(),
+                "{uri}"
+            );
+        }
+        // without an allowlist nothing is sortable
+        assert!(
+            SortParams::from_request_parts(&mut parts("/list?sort=name", None), &())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::kit::config::LoginBackoff as LoginBackoffConfig;
+
+// drop stale entries once the table grows past this size, at most once per `reset_after`
+const MAX_CLIENTS_BEFORE_CLEANUP: usize = 10_000;
+
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    held_until: Instant,
+}
+
+struct Clients {
+    failures: HashMap<String, Failures>,
+    /// A sweep keeps the live entries, another one within `reset_after` would scan them again for
+    /// nothing
+    last_sweep: Instant,
+}
+
+/// Progressive delay of failed logins per client ip. Every consecutive failure doubles the delay
+/// up to `max_delay`, a success or `reset_after` without failures starts over. Slows down password
+/// guessing without locking the account out for its owner
+pub struct LoginBackoff {
+    base_delay: Duration,
+    max_delay: Duration,
+    reset_after: Duration,
+    clients: Mutex<Clients>,
+}
+
+impl LoginBackoff {
+    pub fn new(cfg: &LoginBackoffConfig) -> Self {
+        Self {
+            base_delay: cfg.base_delay,
+            max_delay: cfg.max_delay,
+            reset_after: cfg.reset_after,
+            clients: Mutex::new(Clients {
+                failures: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Count a failed login of `client_ip`, returns how long to hold back the response
+    pub fn failed(&self, client_ip: &str) -> Duration {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let Clients {
+            failures,
+            last_sweep,
+        } = &mut *clients;
+        if failures.len() > MAX_CLIENTS_BEFORE_CLEANUP
+            && now.duration_since(*last_sweep) >= self.reset_after
+        {
+            failures.retain(|_, entry| now.duration_since(entry.last_failure) < self.reset_after);
+            *last_sweep = now;
+        }
+
+        let entry = failures.entry(client_ip.to_string()).or_insert(Failures {
+            count: 0,
+            last_failure: now,
+            held_until: now,
+        });
+        if now.duration_since(entry.last_failure) >= self.reset_after {
+            entry.count = 0;
+        }
+        entry.count = entry.count.saturating_add(1);
+        entry.last_failure = now;
+
+        let factor = 2u32.saturating_pow(entry.count - 1);
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        entry.held_until = now + delay;
+        delay
+    }
+
+    /// What's left of the delay of the last failure of `client_ip`. Logins are rejected meanwhile,
+    /// otherwise guesses sent in parallel would each only wait out their own failure
+    pub fn held_back(&self, client_ip: &str) -> Option<Duration> {
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients
+            .failures
+            .get(client_ip)
+            .map(|entry| entry.held_until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn succeeded(&self, client_ip: &str) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.failures.remove(client_ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff() -> LoginBackoff {
+        LoginBackoff::new(&LoginBackoffConfig {
+            enable: true,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            reset_after: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn test_delay_grows_with_consecutive_failures_up_to_cap() {
+        let backoff = backoff();
+
+        let delays = (0..5)
+            .map(|_| backoff.failed("10.0.0.1").as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+
+        // other clients are tracked on their own
+        assert_eq!(backoff.failed("10.0.0.2"), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_client_is_held_back_until_the_delay_passes() {
+        let backoff = LoginBackoff::new(&LoginBackoffConfig {
+            enable: true,
+            base_delay: Duration::from_millis(20),
+            max_delay: Duration::from_secs(1),
+            reset_after: Duration::from_secs(60),
+        });
+        assert_eq!(backoff.held_back("10.0.0.1"), None);
+
+        backoff.failed("10.0.0.1");
+        assert!(backoff.held_back("10.0.0.1").is_some());
+        assert_eq!(backoff.held_back("10.0.0.2"), None);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(backoff.held_back("10.0.0.1"), None);
+    }
+
+    #[test]
+    fn test_success_resets_the_delay() {
+        let backoff = backoff();
+        backoff.failed("10.0.0.1");
+        backoff.failed("10.0.0.1");
+
+        backoff.succeeded("10.0.0.1");
+        assert_eq!(backoff.failed("10.0.0.1"), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_failures_are_forgotten_after_reset_after() {
+        let backoff = LoginBackoff::new(&LoginBackoffConfig {
+            enable: true,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            reset_after: Duration::ZERO,
+        });
+        backoff.failed("10.0.0.1");
+
+        assert_eq!(backoff.failed("10.0.0.1"), Duration::from_millis(100));
+    }
+}
@@ -1,3 +1,15 @@
+pub mod bigint;
 pub mod client;
+pub mod internal;
+pub mod ipc_listener;
+pub mod json_limit;
+pub mod list_params;
+pub mod login_backoff;
+pub mod priority;
+pub mod proxy_protocol;
+pub mod rate_limit;
+pub mod request_params;
 pub mod server;
+pub mod spec_validation;
 pub mod user;
+pub mod validation;
@@ -0,0 +1,152 @@
+//! Concurrency limit of api handlers that serves ipc requests ahead of http ones, so an operator
+//! can still run `ipc` commands while public traffic saturates the server.
+//!
+//! Every request takes one of `max_in_flight` slots. Http requests first take one of
+//! `max_in_flight - ipc_reserved` http slots, so at least `ipc_reserved` slots are never held or
+//! waited on by http traffic and a queued ipc request gets the next free one. Tradeoffs:
+//! - reserved slots idle while there is no ipc traffic, http throughput is capped below
+//!   `max_in_flight`
+//! - http requests queue at most `queue_timeout` and are then shed with `TooManyRequests`, a
+//!   burst is answered quickly instead of piling up until the request timeout
+//! - the limit applies per handler, not per connection, requests rejected by auth or the rate
+//!   limit never take a slot
+
+use std::time::Duration;
+
+use sidecar::prelude::*;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+use crate::kit::config::Concurrency;
+use crate::kit::error::Error;
+
+pub struct RequestScheduler {
+    slots: Semaphore,
+    http_slots: Semaphore,
+    queue_timeout: Duration,
+}
+
+/// Held while the handler runs, frees the slot on drop
+pub struct Slot<'a> {
+    _slot: SemaphorePermit<'a>,
+    _http_slot: Option<SemaphorePermit<'a>>,
+}
+
+impl RequestScheduler {
+    pub fn new(cfg: &Concurrency) -> Result<Self> {
+        ensure!(
+            cfg.max_in_flight > cfg.ipc_reserved,
+            "http.concurrency.max_in_flight ({}) must be greater than ipc_reserved ({})",
+            cfg.max_in_flight,
+            cfg.ipc_reserved
+        );
+        Ok(Self {
+            slots: Semaphore::new(cfg.max_in_flight),
+            http_slots: Semaphore::new(cfg.max_in_flight - cfg.ipc_reserved),
+            queue_timeout: cfg.queue_timeout,
+        })
+    }
+
+    /// Wait for a slot, ipc requests until `deadline`, http requests at most `queue_timeout`
+    pub async fn acquire(&self, is_ipc: bool, deadline: Instant) -> Result<Slot<'_>> {
+        if is_ipc {
+            let slot = tokio::time::timeout_at(deadline, self.slots.acquire())
+                .await
+                .map_err(|_| eyre!(Error::RequestTimeout))
+                .wrap_err("no request slot freed up before the deadline")??;
+            return Ok(Slot {
+                _slot: slot,
+                _http_slot: None,
+            });
+        }
+
+        let queue_deadline = deadline.min(Instant::now() + self.queue_timeout);
+        let acquire = async {
+            let http_slot = self.http_slots.acquire().await?;
+            let slot = self.slots.acquire().await?;
+            Ok::<_, Report>(Slot {
+                _slot: slot,
+                _http_slot: Some(http_slot),
+            })
+        };
+        tokio::time::timeout_at(queue_deadline, acquire)
+            .await
+            .map_err(|_| eyre!(Error::TooManyRequests))
+            .wrap_err(format!(
+                "request queued longer than {:?}",
+                self.queue_timeout
+            ))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(max_in_flight: usize, ipc_reserved: usize) -> RequestScheduler {
+        RequestScheduler::new(&Concurrency {
+            enable: true,
+            max_in_flight,
+            ipc_reserved,
+            queue_timeout: Duration::from_millis(50),
+        })
+        .unwrap()
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    #[tokio::test]
+    async fn test_ipc_gets_reserved_slot_while_http_saturates() -> Result<()> {
+        let scheduler = scheduler(3, 1);
+        let _http_a = scheduler.acquire(false, deadline()).await?;
+        let _http_b = scheduler.acquire(false, deadline()).await?;
+
+        let err = scheduler.acquire(false, deadline()).await.err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::TooManyRequests)
+        ));
+
+        let _ipc = tokio::time::timeout(
+            Duration::from_millis(100),
+            scheduler.acquire(true, deadline()),
+        )
+        .await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queued_ipc_is_served_before_queued_http() -> Result<()> {
+        let scheduler = scheduler(2, 1);
+        let http = scheduler.acquire(false, deadline()).await?;
+        let ipc = scheduler.acquire(true, deadline()).await?;
+
+        // both wait, the http one waits for the http slot and never competes for the ipc slot
+        let queued_http = scheduler.acquire(false, deadline());
+        let queued_ipc = scheduler.acquire(true, deadline());
+        drop(ipc);
+        tokio::select! {
+            _ = queued_http => panic!("http request served ahead of ipc"),
+            slot = queued_ipc => drop(slot?),
+        }
+        drop(http);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_must_leave_http_slots() {
+        let err = RequestScheduler::new(&Concurrency {
+            enable: true,
+            max_in_flight: 4,
+            ipc_reserved: 4,
+            queue_timeout: Duration::from_secs(1),
+        })
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("max_in_flight"), "{err}");
+    }
+}
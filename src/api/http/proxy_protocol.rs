@@ -0,0 +1,183 @@
+//! PROXY protocol v1/v2 header of tcp load balancers such as AWS NLB, see
+//! <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>. The header precedes the http
+//! request and carries the client address the balancer accepted, read with `http.proxy_protocol`
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use sidecar::prelude::*;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Longest a connection may take to send its header, so idle connections can't pile up
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V1_PREFIX: &[u8] = b"PROXY";
+/// Longest v1 line including the trailing CRLF
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Consume the header from `stream` and return the client address it carries. None when the
+/// balancer sent the connection on its own behalf (v1 `UNKNOWN`, v2 `LOCAL`, non-ip families),
+/// the peer address then stands. A connection without a valid header is an error, once enabled
+/// every connection must come through the balancer
+pub async fn read_header<S>(stream: &mut S) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // the shortest header, "PROXY UNKNOWN\r\n", is 15 bytes, read no more than the prefix
+    // before knowing the version so not a byte of the http request is consumed
+    let mut prefix = [0u8; 5];
+    stream.read_exact(&mut prefix).await?;
+    if prefix == V1_PREFIX {
+        read_v1(stream, prefix).await
+    } else if prefix == V2_SIGNATURE[..5] {
+        read_v2(stream).await
+    } else {
+        bail!("connection does not start with a PROXY protocol header")
+    }
+}
+
+async fn read_v1<S>(stream: &mut S, prefix: [u8; 5]) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        ensure!(line.len() < V1_MAX_LEN, "PROXY v1 header too long");
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])?;
+
+    let mut fields = line.split(' ');
+    let (Some("PROXY"), Some(family)) = (fields.next(), fields.next()) else {
+        bail!("malformed PROXY v1 header: {line}");
+    };
+    if family == "UNKNOWN" {
+        return Ok(None);
+    }
+    let (Some(src_ip), Some(_dst_ip), Some(src_port), Some(_dst_port), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        bail!("malformed PROXY v1 header: {line}");
+    };
+    let ip = match family {
+        "TCP4" => IpAddr::V4(src_ip.parse()?),
+        "TCP6" => IpAddr::V6(src_ip.parse()?),
+        _ => bail!("unsupported PROXY v1 family: {family}"),
+    };
+    Ok(Some(SocketAddr::new(ip, src_port.parse()?)))
+}
+
+async fn read_v2<S>(stream: &mut S) -> Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = [0u8; 11];
+    stream.read_exact(&mut head).await?;
+    ensure!(
+        head[..7] == V2_SIGNATURE[5..],
+        "malformed PROXY v2 signature"
+    );
+    let version_command = head[7];
+    let family = head[8];
+    let len = u16::from_be_bytes([head[9], head[10]]) as usize;
+    ensure!(
+        version_command >> 4 == 2,
+        "unsupported PROXY protocol version: {}",
+        version_command >> 4
+    );
+
+    let mut addresses = vec![0u8; len];
+    stream.read_exact(&mut addresses).await?;
+
+    match version_command & 0x0f {
+        // LOCAL, e.g. health checks of the balancer itself
+        0x0 => return Ok(None),
+        0x1 => {}
+        command => bail!("unsupported PROXY v2 command: {command}"),
+    }
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    match family >> 4 {
+        // AF_INET: src addr, dst addr, src port, dst port
+        0x1 => {
+            ensure!(len >= 12, "PROXY v2 ipv4 addresses truncated");
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4])?);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port(8))))
+        }
+        // AF_INET6
+        0x2 => {
+            ensure!(len >= 36, "PROXY v2 ipv6 addresses truncated");
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16])?);
+            Ok(Some(SocketAddr::new(IpAddr::V6(ip), port(32))))
+        }
+        // AF_UNSPEC or AF_UNIX carry no client ip
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(header: &[u8]) -> Result<(Option<SocketAddr>, Vec<u8>)> {
+        let stream = [header, b"GET / HTTP/1.1\r\n"].concat();
+        let mut reader = stream.as_slice();
+        let addr = read_header(&mut reader).await?;
+        Ok((addr, reader.to_vec()))
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn test_v1_header_yields_source_and_leaves_request() -> Result<()> {
+        let (addr, rest) = read(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\n").await?;
+        assert_eq!(addr, Some("203.0.113.7:51234".parse()?));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let (addr, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n").await?;
+        assert_eq!(addr, Some("[2001:db8::1]:4000".parse()?));
+
+        let (addr, rest) = read(b"PROXY UNKNOWN\r\n").await?;
+        assert_eq!(addr, None);
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v2_header_yields_source_and_leaves_request() -> Result<()> {
+        let ipv4 = [203, 0, 113, 7, 10, 0, 0, 1, 0xc8, 0x22, 0, 80];
+        let (addr, rest) = read(&v2(0x1, 0x11, &ipv4)).await?;
+        assert_eq!(addr, Some("203.0.113.7:51234".parse()?));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let (addr, rest) = read(&v2(0x0, 0x00, &[])).await?;
+        assert_eq!(addr, None);
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_or_malformed_header_is_rejected() {
+        for header in [
+            &b""[..],
+            b"PROXY TCP4 203.0.113.7\r\n",
+            b"PROXY TCP4 not-an-ip 10.0.0.1 1 2\r\n",
+        ] {
+            assert!(read(header).await.is_err(), "{header:?}");
+        }
+        assert!(read(&v2(0x1, 0x11, &[1, 2, 3])).await.is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sidecar::prelude::*;
+
+use crate::kit::config::RateLimit;
+use crate::kit::error::Error;
+
+// drop expired buckets once the table grows past this size, at most once per window
+const MAX_BUCKETS_BEFORE_CLEANUP: usize = 10_000;
+
+/// Who a request is accounted to, resolved after authentication
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    Anonymous(String),
+    Authenticated(String),
+}
+
+impl RateLimitKey {
+    pub fn resolve(user_id: &str, client_ip: &str) -> Self {
+        if user_id.is_empty() {
+            RateLimitKey::Anonymous(client_ip.to_string())
+        } else {
+            RateLimitKey::Authenticated(user_id.to_string())
+        }
+    }
+}
+
+struct Bucket {
+    window_start: Instant,
+    count: u64,
+}
+
+struct Buckets {
+    buckets: HashMap<RateLimitKey, Bucket>,
+    /// A sweep keeps the live buckets, another one within the window would scan them again for
+    /// nothing
+    last_sweep: Instant,
+}
+
+impl Buckets {
+    fn new() -> Mutex<Self> {
+        Mutex::new(Self {
+            buckets: HashMap::new(),
+            last_sweep: Instant::now(),
+        })
+    }
+}
+
+/// Fixed window rate limiter with separate limits for anonymous and authenticated traffic, plus a
+/// strict limit for sensitive endpoints counted in its own buckets
+pub struct RateLimiter {
+    /// Off leaves only the strict limit
+    enable: bool,
+    window: Duration,
+    anonymous_limit: u64,
+    authenticated_limit: u64,
+    strict_limit: u64,
+    buckets: Mutex<Buckets>,
+    strict_buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(cfg: &RateLimit) -> Self {
+        Self {
+            enable: cfg.enable,
+            window: cfg.window,
+            anonymous_limit: cfg.anonymous,
+            authenticated_limit: cfg.authenticated,
+            strict_limit: cfg.strict,
+            buckets: Buckets::new(),
+            strict_buckets: Buckets::new(),
+        }
+    }
+
+    pub fn check(&self, key: &RateLimitKey) -> Result<()> {
+        if !self.enable {
+            return Ok(());
+        }
+        let limit = match key {
+            RateLimitKey::Anonymous(_) => self.anonymous_limit,
+            RateLimitKey::Authenticated(_) => self.authenticated_limit,
+        };
+        self.check_in(&self.buckets, key, limit)
+    }
+
+    /// Same limit for anonymous and authenticated keys, applied even when the limiter is disabled
+    pub fn check_strict(&self, key: &RateLimitKey) -> Result<()> {
+        self.check_in(&self.strict_buckets, key, self.strict_limit)
+    }
+
+    fn check_in(&self, buckets: &Mutex<Buckets>, key: &RateLimitKey, limit: u64) -> Result<()> {
+        let now = Instant::now();
+        let mut table = buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let Buckets {
+            buckets,
+            last_sweep,
+        } = &mut *table;
+        if buckets.len() > MAX_BUCKETS_BEFORE_CLEANUP
+            && now.duration_since(*last_sweep) >= self.window
+        {
+            buckets.retain(|_, bucket| now.duration_since(bucket.window_start) < self.window);
+            *last_sweep = now;
+        }
+
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+            window_start: now,
+            count: 0,
+        });
+        if now.duration_since(bucket.window_start) >= self.window {
+            bucket.window_start = now;
+            bucket.count = 0;
+        }
+
+        if bucket.count >= limit {
+            return Err(Error::TooManyRequests).wrap_err(format!("key: {key:?}, limit: {limit}"));
+        }
+        bucket.count += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(anonymous: u64, authenticated: u64) -> RateLimiter {
+        RateLimiter::new(&RateLimit {
+            enable: true,
+            window: Duration::from_secs(60),
+            anonymous,
+            authenticated,
+            strict: 1,
+        })
+    }
+
+    fn allowed(limiter: &RateLimiter, key: &RateLimitKey) -> u64 {
+        let mut count = 0;
+        while limiter.check(key).is_ok() {
+            count += 1;
+            assert!(count <= 1000, "limiter never rejected");
+        }
+        count
+    }
+
+    #[test]
+    fn test_authenticated_user_gets_higher_limit() {
+        let limiter = limiter(2, 5);
+
+        let anonymous = RateLimitKey::resolve("", "10.0.0.1");
+        let authenticated = RateLimitKey::resolve("user-1", "10.0.0.1");
+
+        assert_eq!(allowed(&limiter, &anonymous), 2);
+        assert_eq!(allowed(&limiter, &authenticated), 5);
+    }
+
+    #[test]
+    fn test_keys_are_limited_independently() {
+        let limiter = limiter(1, 1);
+
+        assert!(
+            limiter
+                .check(&RateLimitKey::resolve("", "10.0.0.1"))
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .check(&RateLimitKey::resolve("", "10.0.0.2"))
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .check(&RateLimitKey::resolve("", "10.0.0.1"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_strict_limit_is_counted_separately() {
+        let limiter = limiter(5, 5);
+        let key = RateLimitKey::resolve("user-1", "10.0.0.1");
+
+        assert!(limiter.check_strict(&key).is_ok());
+        assert!(limiter.check_strict(&key).is_err());
+        assert_eq!(allowed(&limiter, &key), 5);
+    }
+}
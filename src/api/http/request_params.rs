@@ -0,0 +1,108 @@
+use axum::body::Bytes;
+use serde_json::{Map, Value};
+
+use crate::kit::config::REDACTED;
+
+/// Captured params longer than this are cut, a failed upload shouldn't flood the log
+const MAX_RENDERED_LEN: usize = 2048;
+
+/// Field names containing any of these (case-insensitive) are logged as [`REDACTED`]
+const SENSITIVE_KEY_PARTS: &[&str] = &[
+    "password",
+    "passwd",
+    "token",
+    "secret",
+    "hmac",
+    "authorization",
+];
+
+/// Raw request input kept for the error log, rendered only when the request fails so the happy
+/// path pays for nothing but a cheap clone
+#[derive(Debug, Clone)]
+pub enum RequestParams {
+    Query(String),
+    Json(Bytes),
+}
+
+impl RequestParams {
+    /// Json with sensitive fields redacted, query params become an object of strings.
+    /// Bodies that aren't valid json are not logged at all since they can't be redacted
+    pub fn render(&self) -> String {
+        let mut value = match self {
+            RequestParams::Query(query) => Value::Object(
+                url::form_urlencoded::parse(query.as_bytes())
+                    .map(|(key, value)| (key.into_owned(), Value::String(value.into_owned())))
+                    .collect::<Map<_, _>>(),
+            ),
+            RequestParams::Json(body) => match serde_json::from_slice::<Value>(body) {
+                Ok(value) => value,
+                Err(_) => return format!("<{} bytes, not json>", body.len()),
+            },
+        };
+        redact(&mut value);
+
+        let mut rendered = value.to_string();
+        if rendered.len() > MAX_RENDERED_LEN {
+            let mut end = MAX_RENDERED_LEN;
+            while !rendered.is_char_boundary(end) {
+                end -= 1;
+            }
+            rendered.truncate(end);
+            rendered.push_str("...");
+        }
+        rendered
+    }
+}
+
+fn is_sensitive_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE_KEY_PARTS.iter().any(|part| key.contains(part))
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                if is_sensitive_key(key) {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_redacts_sensitive_fields() {
+        let query = RequestParams::Query("auth_id=alice&auth_token=s3cret&Password=x".to_string());
+        let rendered: Value = serde_json::from_str(&query.render()).unwrap();
+        assert_eq!(rendered["auth_id"], "alice");
+        assert_eq!(rendered["auth_token"], REDACTED);
+        assert_eq!(rendered["Password"], REDACTED);
+
+        let body = RequestParams::Json(Bytes::from_static(
+            br#"{"auth_id":"alice","nested":[{"jwt_token":"t"}],"role":"User"}"#,
+        ));
+        let rendered: Value = serde_json::from_str(&body.render()).unwrap();
+        assert_eq!(rendered["auth_id"], "alice");
+        assert_eq!(rendered["nested"][0]["jwt_token"], REDACTED);
+        assert_eq!(rendered["role"], "User");
+    }
+
+    #[test]
+    fn test_render_never_logs_unparsable_or_huge_bodies() {
+        let body = RequestParams::Json(Bytes::from_static(b"password=s3cret"));
+        assert_eq!(body.render(), "<15 bytes, not json>");
+
+        let huge = format!(r#"{{"desc":"{}"}}"#, "a".repeat(10 * MAX_RENDERED_LEN));
+        let rendered = RequestParams::Json(Bytes::from(huge)).render();
+        assert_eq!(rendered.len(), MAX_RENDERED_LEN + 3);
+    }
+}
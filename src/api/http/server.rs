@@ -1,44 +1,73 @@
 use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::future::Future;
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::os::fd::AsRawFd;
-use std::sync::Arc;
-use std::time::Instant;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use axum::{
-    Router,
+    Extension, Router,
+    body::Bytes,
     extract::{
-        ConnectInfo, FromRequestParts, Json, OriginalUri, Query, State,
-        rejection::{JsonRejection, QueryRejection},
+        ConnectInfo, FromRequestParts, Json, MatchedPath, OriginalUri, Query, RawPathParams, State,
+        rejection::{MissingJsonContentType, QueryRejection, RawPathParamsRejection},
     },
-    http::{HeaderMap, header, request::Parts},
-    response::{IntoResponse, Response as AxumResponse},
+    http::{HeaderMap, HeaderName, HeaderValue, header, request::Parts},
+    response::{IntoResponse, Redirect, Response as AxumResponse},
     routing::{MethodRouter, get, post},
 };
 use axum_client_ip::{
     CloudFrontViewerAddress, FlyClientIp, RightmostForwarded, RightmostXForwardedFor, TrueClientIp,
 };
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use sea_orm::DbErr;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sidecar::metrics::TaskStats;
 use sidecar::prelude::*;
 use sidecar::repo::Repo;
 use sidecar::sidecar::{Component, Sidecar};
+use sidecar::version;
 use strip_ansi_escapes::strip_str;
 use tokio::fs;
 use tokio::net::{TcpListener, UnixListener, UnixStream};
-use tokio::sync::RwLock;
-use tracing::{info, warn};
-use utoipa::openapi::Components;
+use tower::ServiceExt as _;
+use tracing::{debug, error, info, warn};
 use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::SwaggerUi;
+use uuid::Uuid;
 
-use crate::api::http::user::{self, UserApiDoc};
+use crate::api::http::bigint;
+use crate::api::http::internal::{self, InternalApiDoc};
+use crate::api::http::ipc_listener::IpcListener;
+use crate::api::http::json_limit;
+use crate::api::http::list_params::{
+    ListParamsRejection, ListReq, ListSpec, PageParams, SortDir, SortParams,
+};
+use crate::api::http::login_backoff::LoginBackoff;
+use crate::api::http::priority::{RequestScheduler, Slot};
+use crate::api::http::proxy_protocol;
+use crate::api::http::rate_limit::{RateLimitKey, RateLimiter};
+use crate::api::http::request_params::RequestParams;
+use crate::api::http::spec_validation::SpecValidator;
+use crate::api::http::user::{self, AuthClaims, UserApiDoc};
+use crate::api::http::validation::{self, FieldErrors, ValidateRequest};
 use crate::core::core::Core;
-use crate::kit::config::Config;
+use crate::core::db::PoolStats;
+use crate::core::model::user as user_model;
+use crate::kit::config::{Config, HTTP, IPC, JWT, SubjectSource};
 use crate::kit::context::Context;
 use crate::kit::error::Error;
 use crate::kit::jwt;
@@ -46,8 +75,15 @@ use crate::kit::response::Response;
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(ping),
-    components(schemas(Response<String>)),
+    paths(ping, root_info, healthz, readyz, metrics),
+    components(schemas(
+        Response<String>,
+        RootInfo,
+        Response<RootInfo>,
+        Metrics,
+        TaskMetric,
+        Response<Metrics>
+    )),
     tags((name = "system", description = "System related APIs")),
     modifiers(&BearerAuthAddon)
 )]
@@ -57,7 +93,7 @@ struct BearerAuthAddon;
 
 impl Modify for BearerAuthAddon {
     fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
-        let mut components = openapi.components.take().unwrap_or_else(Components::new);
+        let mut components = openapi.components.take().unwrap_or_default();
 
         if !components.security_schemes.contains_key("bearer_auth") {
             components.add_security_scheme(
@@ -76,13 +112,21 @@ impl Modify for BearerAuthAddon {
 }
 
 pub fn base_openapi_doc() -> utoipa::openapi::OpenApi {
-    ApiDoc::openapi().nest("/api/v1/user", UserApiDoc::openapi())
+    ApiDoc::openapi()
+        .nest("/api/v1/user", UserApiDoc::openapi())
+        .nest("/internal", InternalApiDoc::openapi())
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub core: Arc<Core>,
     pub is_ipc: bool,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub login_backoff: Option<Arc<LoginBackoff>>,
+    /// Shared by both listeners so ipc requests can be served ahead of http ones
+    pub scheduler: Option<Arc<RequestScheduler>>,
+    /// Set once the listeners start draining, new requests are then answered with 503
+    pub shutting_down: Arc<AtomicBool>,
 }
 
 pub struct Server {
@@ -90,17 +134,28 @@ pub struct Server {
     repo: Repo<Config>,
 
     core: Arc<Core>,
+    /// Set once the http listener is bound, see [`Server::http_local_addr`]
+    http_local_addr: OnceLock<SocketAddr>,
 }
 
 impl Server {
     pub async fn new(sidecar: Sidecar, repo: Repo<Config>, core: Arc<Core>) -> Result<Arc<Self>> {
-        let server = Arc::new(Server {
-            sidecar: sidecar.with_component_name("http-server"),
-            repo,
-            core,
-        });
-        sidecar.register_component(server.clone()).await?;
-        Ok(server)
+        sidecar
+            .build_component("http-server", |sidecar| async move {
+                Ok(Server {
+                    sidecar,
+                    repo,
+                    core,
+                    http_local_addr: OnceLock::new(),
+                })
+            })
+            .await
+    }
+
+    /// Address the http listener actually bound, resolves `http.port = 0` to the ephemeral port.
+    /// None before [`Component::start`] or with http disabled
+    pub fn http_local_addr(&self) -> Option<SocketAddr> {
+        self.http_local_addr.get().copied()
     }
 
     pub fn router() -> Router<AppState> {
@@ -108,23 +163,130 @@ impl Server {
             let user_router = Router::new()
                 .route(
                     "/register",
-                    wrap_post_handler(user::register, ApiConfig::default().with_from_ipc()),
+                    wrap_post_handler(
+                        user::register,
+                        ApiConfig::new("user_register")
+                            .with_from_ipc()
+                            .with_params_on_error(),
+                    ),
+                )
+                .route(
+                    "/check-availability",
+                    wrap_get_handler(
+                        user::check_availability,
+                        ApiConfig::new("user_check_availability").with_strict_rate_limit(),
+                    ),
                 )
                 .route(
                     "/login",
-                    wrap_get_handler(user::login, ApiConfig::default()),
+                    wrap_get_handler(
+                        user::login,
+                        ApiConfig::new("user_login")
+                            .with_params_on_error()
+                            .with_login_backoff(),
+                    ),
                 )
                 .route(
                     "/refresh-token",
-                    wrap_get_handler(user::refresh_token, ApiConfig::default().with_auth()),
+                    wrap_get_handler(
+                        user::refresh_token,
+                        ApiConfig::new("user_refresh_token").with_auth(),
+                    ),
+                )
+                .route(
+                    "/{id}/impersonate",
+                    wrap_post_handler(
+                        user::impersonate,
+                        ApiConfig::new("user_impersonate")
+                            .with_auth()
+                            .with_params_on_error(),
+                    ),
+                )
+                .route(
+                    "/export",
+                    wrap_get_raw_handler(
+                        user::export,
+                        ApiConfig::new("user_export").with_auth().with_query_token(),
+                    ),
+                )
+                .route(
+                    "/list",
+                    wrap_list_handler(
+                        user::list,
+                        ApiConfig::new("user_list").with_auth(),
+                        ListSpec::new()
+                            .sort_fields(user::LIST_SORT_FIELDS)
+                            .default_sort("create_time", SortDir::Desc),
+                    ),
+                )
+                .route(
+                    "/bulk-delete",
+                    wrap_post_handler(
+                        user::bulk_delete,
+                        ApiConfig::new("user_bulk_delete")
+                            .with_auth()
+                            .with_params_on_error(),
+                    ),
                 );
 
             Router::new().nest("/user", user_router)
         };
 
+        let internal_router = Router::new()
+            .route(
+                "/db/stats",
+                wrap_get_handler(
+                    internal::db_stats,
+                    ApiConfig::new("internal_db_stats").with_from_ipc(),
+                ),
+            )
+            .route(
+                "/config",
+                wrap_get_handler(
+                    internal::config,
+                    ApiConfig::new("internal_config").with_from_ipc(),
+                ),
+            );
+
         Router::new()
-            .route("/ping", wrap_get_handler(ping, ApiConfig::default()))
+            .route(
+                "/",
+                wrap_get_raw_handler(
+                    root_info,
+                    ApiConfig::new("root_info").with_cache_control(Duration::from_secs(60)),
+                ),
+            )
+            .route("/ping", wrap_get_handler(ping, ApiConfig::new("ping")))
+            .merge(Self::probe_router())
             .nest("/api/v1", api_v1_router)
+            .nest("/internal", internal_router)
+    }
+
+    /// [`Server::router`] nested under `http.base_path`. Probes stay reachable unprefixed as
+    /// well, orchestrators hit the instance directly rather than through the proxy
+    pub fn router_with_base_path(base_path: Option<&str>) -> Router<AppState> {
+        match base_path {
+            Some(base_path) => Router::new()
+                .nest(base_path, Self::router())
+                .merge(Self::probe_router()),
+            None => Self::router(),
+        }
+    }
+
+    fn probe_router() -> Router<AppState> {
+        Router::new()
+            .route(
+                "/healthz",
+                wrap_get_handler(healthz, ApiConfig::new("healthz").with_infrastructure()),
+            )
+            .route(
+                "/readyz",
+                wrap_get_raw_handler(readyz, ApiConfig::new("readyz").with_infrastructure()),
+            )
+            .route(
+                "/metrics",
+                wrap_get_handler(metrics, ApiConfig::new("metrics").with_infrastructure()),
+            )
     }
 
     pub async fn is_socket_in_use(&self) -> bool {
@@ -151,6 +313,120 @@ impl Server {
     }
 }
 
+#[derive(Clone, Copy)]
+struct TcpServeOptions {
+    max_header_bytes: usize,
+    max_header_count: usize,
+    tcp_nodelay: bool,
+    keep_alive: bool,
+    proxy_protocol: bool,
+}
+
+impl From<&HTTP> for TcpServeOptions {
+    fn from(http_cfg: &HTTP) -> Self {
+        Self {
+            max_header_bytes: http_cfg.max_header_bytes,
+            max_header_count: http_cfg.max_header_count,
+            tcp_nodelay: http_cfg.tcp_nodelay,
+            keep_alive: http_cfg.keep_alive,
+            proxy_protocol: http_cfg.proxy_protocol,
+        }
+    }
+}
+
+/// Like `axum::serve` with connect info, but with the connection options applied to every
+/// connection, e.g. oversized heads are rejected by hyper before routing. With `proxy_protocol`
+/// the connect info is the client address of the PROXY header instead of the balancer's
+async fn serve_tcp(
+    listener: TcpListener,
+    router: Router,
+    options: TcpServeOptions,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        // hyper requires at least 8KiB of read buffer
+        .max_buf_size(options.max_header_bytes.max(8 * 1024))
+        .max_headers(options.max_header_count)
+        .keep_alive(options.keep_alive);
+    builder
+        .http2()
+        .max_header_list_size(u32::try_from(options.max_header_bytes).unwrap_or(u32::MAX));
+
+    let graceful = GracefulShutdown::new();
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        let (mut stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(err = ?err, "http server accept failed");
+                    // e.g. out of fds, don't spin on it
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        if let Err(err) = stream.set_nodelay(options.tcp_nodelay) {
+            warn!(err = ?err, "http server set tcp nodelay failed");
+        }
+
+        let router = router.clone();
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        // the header is read off the accept loop, a slow client must not hold up the others
+        tokio::spawn(async move {
+            let mut remote_addr = peer_addr;
+            if options.proxy_protocol {
+                let header = tokio::time::timeout(
+                    proxy_protocol::HEADER_TIMEOUT,
+                    proxy_protocol::read_header(&mut stream),
+                )
+                .await
+                .unwrap_or_else(|_| Err(eyre!("PROXY header timed out")));
+                match header {
+                    Ok(client_addr) => remote_addr = client_addr.unwrap_or(peer_addr),
+                    Err(err) => {
+                        warn!(err = %err, peer_addr = %peer_addr, "http connection rejected");
+                        return;
+                    }
+                }
+            }
+
+            let service = router.map_request(move |mut request: hyper::Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(remote_addr));
+                request
+            });
+            let connection = builder
+                .serve_connection(TokioIo::new(stream), TowerToHyperService::new(service))
+                .into_owned();
+            if let Err(err) = watcher.watch(connection).await {
+                debug!(err = ?err, client_addr = %remote_addr, "http connection closed with error");
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Restrict who can connect to the ipc socket, it serves admin-only endpoints
+pub fn secure_ipc_socket(path: &Path, ipc_cfg: &IPC) -> Result<()> {
+    let mode = ipc_cfg.socket_mode()?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .wrap_err(format!("Failed to chmod ipc file: {}", path.display()))?;
+
+    if ipc_cfg.uid.is_some() || ipc_cfg.gid.is_some() {
+        std::os::unix::fs::chown(path, ipc_cfg.uid, ipc_cfg.gid)
+            .wrap_err(format!("Failed to chown ipc file: {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
 #[async_trait]
 impl Component for Server {
     fn name(&self) -> &str {
@@ -158,7 +434,36 @@ impl Component for Server {
     }
 
     async fn start(&self) -> Result<()> {
+        jwt_self_test(&self.repo.cfg.http.jwt).wrap_err("Jwt self-test failed, check http.jwt")?;
+        let base_path = self.repo.cfg.http.base_path()?.map(str::to_string);
+
         let root_router = Self::router();
+        // built even when disabled, the strict limit always applies
+        let rate_limiter = Some(Arc::new(RateLimiter::new(&self.repo.cfg.http.rate_limit)));
+        let login_backoff = self
+            .repo
+            .cfg
+            .http
+            .login_backoff
+            .enable
+            .then(|| Arc::new(LoginBackoff::new(&self.repo.cfg.http.login_backoff)));
+        let concurrency = &self.repo.cfg.http.concurrency;
+        let scheduler = concurrency
+            .enable
+            .then(|| RequestScheduler::new(concurrency).map(Arc::new))
+            .transpose()?;
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let spec_validator = if self.repo.cfg.http.validate_against_spec {
+            warn!(
+                "http.validate_against_spec is enabled, bodies are checked against the openapi spec"
+            );
+            Some(Arc::new(SpecValidator::new(
+                &base_openapi_doc(),
+                base_path.as_deref(),
+            )?))
+        } else {
+            None
+        };
 
         let ipc_file_path = self.repo.ipc_file_path();
         if self.is_socket_in_use().await {
@@ -179,68 +484,88 @@ impl Component for Server {
             "Failed to bind ipc file, may be other process is running: {}",
             ipc_file_path.display()
         ))?;
+        secure_ipc_socket(&ipc_file_path, &self.repo.cfg.ipc)?;
+        let listener = IpcListener::new(listener, ipc_file_path.clone(), self.repo.cfg.ipc.clone());
         info!("ipc server listen on: {}", ipc_file_path.display());
         self.sidecar.spawn_core_task("ipc-listener", {
-            let root_router = root_router.clone().with_state(AppState {
-                core: self.core.clone(),
-                is_ipc: true,
-            });
+            let root_router = SpecValidator::layer(root_router.clone(), spec_validator.clone())
+                .with_state(AppState {
+                    core: self.core.clone(),
+                    is_ipc: true,
+                    rate_limiter: None,
+                    login_backoff: None,
+                    scheduler: scheduler.clone(),
+                    shutting_down: shutting_down.clone(),
+                });
             let sidecar = self.sidecar.clone();
+            let shutting_down = shutting_down.clone();
             async move {
-                axum::serve(listener, root_router)
-                    .with_graceful_shutdown(async move {
-                        if let Err(e) = sidecar.canceled().await {
-                            warn!("ipc server cancel error: {}", e);
-                        }
-                    })
-                    .await
+                let result = axum::serve(listener, root_router)
+                    .with_graceful_shutdown(shutdown_signal(sidecar.clone(), shutting_down, "ipc"))
+                    .await;
+                stop_app_on_listener_error(&sidecar, "ipc", result).await;
             }
         });
 
         if self.repo.cfg.http.enable {
             let listener =
                 TcpListener::bind(format!("0.0.0.0:{}", self.repo.cfg.http.port)).await?;
+            let local_addr = listener.local_addr()?;
+            _ = self.http_local_addr.set(local_addr);
             info!(
                 "http server listen on: http://127.0.0.1:{}",
-                self.repo.cfg.http.port
+                local_addr.port()
             );
             self.sidecar.spawn_core_task("http-listener", {
-                let mut root_router = root_router.clone().with_state(AppState {
+                let mut root_router = SpecValidator::layer(
+                    Self::router_with_base_path(base_path.as_deref()),
+                    spec_validator,
+                )
+                .with_state(AppState {
                     core: self.core.clone(),
                     is_ipc: false,
+                    rate_limiter,
+                    login_backoff,
+                    scheduler,
+                    shutting_down: shutting_down.clone(),
                 });
                 let sidecar = self.sidecar.clone();
-                let host = format!(
-                    "{}:{}",
-                    self.repo.cfg.http.swagger.host, self.repo.cfg.http.port
-                );
+                let host = format!("{}:{}", self.repo.cfg.http.swagger.host, local_addr.port());
                 let swagger_enable = self.repo.cfg.http.swagger.enable;
+                let swagger_disabled_redirect =
+                    self.repo.cfg.http.swagger.disabled_redirect.clone();
+                let serve_options = TcpServeOptions::from(&self.repo.cfg.http);
+                let base_path = base_path.unwrap_or_default();
                 if swagger_enable {
-                    info!("swagger ui listen on: {}/swagger-ui", host);
+                    info!("swagger ui listen on: {}{}/swagger-ui", host, base_path);
                 }
                 async move {
                     if swagger_enable {
                         let mut doc = base_openapi_doc();
                         doc.servers = Some(vec![
                             utoipa::openapi::ServerBuilder::new()
-                                .url(host.clone())
+                                .url(format!("{host}{base_path}"))
                                 .build(),
                         ]);
                         root_router = root_router.merge(
-                            SwaggerUi::new("/swagger-ui").url("/swagger-ui/openapi.json", doc),
+                            SwaggerUi::new(format!("{base_path}/swagger-ui"))
+                                .url(format!("{base_path}/swagger-ui/openapi.json"), doc),
                         );
+                    } else {
+                        root_router = root_router.merge(swagger_disabled_router(
+                            &base_path,
+                            swagger_disabled_redirect,
+                        ));
                     }
 
-                    axum::serve(
+                    let result = serve_tcp(
                         listener,
-                        root_router.into_make_service_with_connect_info::<SocketAddr>(),
+                        root_router,
+                        serve_options,
+                        shutdown_signal(sidecar.clone(), shutting_down, "http"),
                     )
-                    .with_graceful_shutdown(async move {
-                        if let Err(e) = sidecar.canceled().await {
-                            warn!("http server cancel error: {}", e);
-                        }
-                    })
-                    .await
+                    .await;
+                    stop_app_on_listener_error(&sidecar, "http", result).await;
                 }
             });
         }
@@ -250,16 +575,66 @@ impl Component for Server {
 
     async fn stop(&self) -> Result<()> {
         let ipc_file_path = self.repo.ipc_file_path();
-        if ipc_file_path.exists() {
-            if let Err(e) = fs::remove_file(ipc_file_path).await {
-                warn!("failed to remove ipc file: {}", e);
-            }
+        if ipc_file_path.exists()
+            && let Err(e) = fs::remove_file(ipc_file_path).await
+        {
+            warn!("failed to remove ipc file: {}", e);
         }
 
         Ok(())
     }
 }
 
+/// Graceful shutdown trigger shared by the ipc and http listeners. However `canceled()` resolves
+/// the listener shuts down, an error is logged and turned into an app cancel so the other
+/// listener follows instead of serving on alone. Raises `shutting_down` first so requests arriving
+/// while in-flight ones drain get a 503 rather than a reset connection
+async fn shutdown_signal(sidecar: Sidecar, shutting_down: Arc<AtomicBool>, listener: &'static str) {
+    if let Err(err) = sidecar.canceled().await {
+        warn!(err = ?err, listener, "wait for cancel failed, shutting down listener anyway");
+        if let Err(err) = sidecar.cancel().await {
+            warn!(err = ?err, listener, "failed to request app cancel");
+        }
+    }
+    shutting_down.store(true, Ordering::SeqCst);
+    info!(listener, "listener shutting down");
+}
+
+/// A listener that stops on its own (e.g. the socket broke) takes the app down with it, a half
+/// reachable server is harder to notice than a stopped one
+async fn stop_app_on_listener_error(
+    sidecar: &Sidecar,
+    listener: &'static str,
+    result: io::Result<()>,
+) {
+    if let Err(err) = result {
+        error!(err = ?err, listener, "listener stopped with error, cancel app");
+        if let Err(err) = sidecar.cancel().await {
+            warn!(err = ?err, listener, "failed to request app cancel");
+        }
+    }
+}
+
+/// Answers `/swagger-ui` while swagger is disabled, without the access log and rate limit of api
+/// routes so scanners and stale bookmarks stay quiet
+fn swagger_disabled_router<S: Clone + Send + Sync + 'static>(
+    base_path: &str,
+    redirect: Option<String>,
+) -> Router<S> {
+    let handler = move || {
+        let redirect = redirect.clone();
+        async move {
+            match redirect {
+                Some(location) => Redirect::temporary(&location).into_response(),
+                None => (axum::http::StatusCode::NOT_FOUND, "Not Found").into_response(),
+            }
+        }
+    };
+    Router::new()
+        .route(&format!("{base_path}/swagger-ui"), get(handler.clone()))
+        .route(&format!("{base_path}/swagger-ui/{{*rest}}"), get(handler))
+}
+
 #[derive(Debug, Deserialize, utoipa::IntoParams)]
 #[into_params(parameter_in = Query)]
 struct PingReq {
@@ -268,6 +643,127 @@ struct PingReq {
     content: Option<String>,
 }
 
+impl ValidateRequest for PingReq {}
+
+/// Liveness probe endpoint
+#[utoipa::path(
+    tag = "system",
+    operation_id = "healthz",
+    get,
+    path = "/healthz",
+    summary = "Liveness probe",
+    description = "Answer as long as the process serves http. Exempt from auth, rate limiting and access logging.",
+    responses((status = 200, description = "Alive", body = Response<String>))
+)]
+async fn healthz(
+    _state: Arc<Core>,
+    _ctx: Context,
+    _headers: HeaderMap,
+    _req: (),
+) -> Result<String> {
+    Ok("ok".to_string())
+}
+
+/// Readiness probe endpoint
+#[utoipa::path(
+    tag = "system",
+    operation_id = "readyz",
+    get,
+    path = "/readyz",
+    summary = "Readiness probe",
+    description = "Answer 200 when the app can serve requests, 503 while the db is unreachable. Exempt from auth, rate limiting and access logging.",
+    responses(
+        (status = 200, description = "Ready", body = Response<String>),
+        (status = 503, description = "Not ready", body = Response<String>)
+    )
+)]
+async fn readyz(
+    state: Arc<Core>,
+    _ctx: Context,
+    _headers: HeaderMap,
+    _req: (),
+) -> Result<AxumResponse> {
+    if !state.repo.cfg.db.enable {
+        return Ok(render_envelope("ready".to_string()));
+    }
+
+    let ready = async { Ok::<_, Report>(state.db.get_connection().await?.ping().await?) };
+    match ready.await {
+        Ok(()) => Ok(render_envelope("ready".to_string())),
+        Err(err) => {
+            warn!(err = ?err, "readiness check failed");
+            // probes only look at the status, the envelope is for humans
+            let mut response =
+                Response::<()>::err(&Error::DBUnavailable(one_line_error(&err))).into_response();
+            *response.status_mut() = axum::http::StatusCode::SERVICE_UNAVAILABLE;
+            Ok(response)
+        }
+    }
+}
+
+/// Execution counters of one sidecar task, see [`sidecar::metrics::TaskStats`]
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct TaskMetric {
+    component: String,
+    task_name: String,
+    runs: u64,
+    failures: u64,
+    total_duration_ms: u64,
+    last_duration_ms: u64,
+}
+
+impl From<TaskStats> for TaskMetric {
+    fn from(stats: TaskStats) -> Self {
+        Self {
+            component: stats.component,
+            task_name: stats.task_name,
+            runs: stats.runs,
+            failures: stats.failures,
+            total_duration_ms: stats.total_duration.as_millis() as u64,
+            last_duration_ms: stats.last_duration.as_millis() as u64,
+        }
+    }
+}
+
+/// Task counters and db pool utilization of the instance
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct Metrics {
+    tasks: Vec<TaskMetric>,
+    /// Absent while the db is disabled or not connected
+    db_pool: Option<PoolStats>,
+}
+
+/// Metrics endpoint
+#[utoipa::path(
+    tag = "system",
+    operation_id = "metrics",
+    get,
+    path = "/metrics",
+    summary = "Instance metrics",
+    description = "Return the run counters of the sidecar tasks and the db pool utilization. Exempt from auth, rate limiting and access logging.",
+    responses((status = 200, description = "Success", body = Response<Metrics>))
+)]
+async fn metrics(
+    state: Arc<Core>,
+    _ctx: Context,
+    _headers: HeaderMap,
+    _req: (),
+) -> Result<Metrics> {
+    let db_pool = match state.repo.cfg.db.enable {
+        true => state.db.pool_stats().await.ok(),
+        false => None,
+    };
+    Ok(Metrics {
+        tasks: state
+            .sidecar
+            .task_metrics()
+            .into_iter()
+            .map(TaskMetric::from)
+            .collect(),
+        db_pool,
+    })
+}
+
 #[utoipa::path(
     tag = "system",
     get,
@@ -282,17 +778,147 @@ async fn ping(
     req: PingReq,
 ) -> Result<String> {
     let content = req.content.unwrap_or("".to_string());
-    ctx.add_log_field("content", content.clone()).await;
+    ctx.add_log_field("content", content.clone());
     Ok(content)
 }
 
-#[derive(Default, Debug, Clone)]
+/// Landing response for anyone probing the service
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct RootInfo {
+    app_name: String,
+    version: String,
+    /// Entry points by name, e.g. `ping` => `/ping`
+    links: BTreeMap<String, String>,
+}
+
+#[utoipa::path(
+    tag = "system",
+    operation_id = "root_info",
+    get,
+    path = "/",
+    summary = "Service info",
+    description = "Return the app name, version and links to the main entry points. Answers 404 when http.root_info is disabled.",
+    responses((status = 200, description = "Success", body = Response<RootInfo>))
+)]
+async fn root_info(
+    state: Arc<Core>,
+    ctx: Context,
+    _headers: HeaderMap,
+    _req: (),
+) -> Result<AxumResponse> {
+    let http_cfg = &state.repo.cfg.http;
+    if !http_cfg.root_info {
+        return Ok(axum::http::StatusCode::NOT_FOUND.into_response());
+    }
+
+    let v = version::current();
+    // links as reached by the caller, `base_path` was validated on start and only prefixes http
+    let base_path = if ctx.is_ipc {
+        None
+    } else {
+        http_cfg.base_path().ok().flatten()
+    };
+    let base_path = base_path.unwrap_or_default();
+    let mut links = BTreeMap::from([("ping".to_string(), format!("{base_path}/ping"))]);
+    if http_cfg.enable && http_cfg.swagger.enable {
+        links.insert("swagger_ui".to_string(), format!("{base_path}/swagger-ui"));
+        links.insert(
+            "openapi".to_string(),
+            format!("{base_path}/swagger-ui/openapi.json"),
+        );
+    }
+    Ok(render_envelope(RootInfo {
+        app_name: v.app_name.to_string(),
+        version: v.version.to_string(),
+        links,
+    }))
+}
+
+#[derive(Debug, Clone)]
 pub struct ApiConfig {
+    /// Same as the openapi operation id, used by `http.disabled_endpoints`
+    operation_id: &'static str,
     need_auth: bool,
     need_from_ipc: bool,
+    /// Also count against `http.rate_limit.strict`, for endpoints that can be used to enumerate
+    strict_rate_limit: bool,
+    /// `max-age` of successful responses, errors are always `no-store`
+    cache_max_age: Option<Duration>,
+    /// Probe endpoints (liveness, readiness, metrics) must answer whoever asks: no auth, rate
+    /// limit, disabled endpoints or ipc allow-list, and successes are not logged as access
+    infrastructure: bool,
+    /// Log the redacted query or body as `request_params` when the request fails
+    params_on_error: bool,
+    /// Hold back failed logins per client ip, see `http.login_backoff`
+    login_backoff: bool,
+    /// Parse post bodies as json whatever `Content-Type` they are sent with
+    any_content_type: bool,
+    /// Also take the bearer token from the `access_token` query parameter
+    query_token: bool,
 }
 
 impl ApiConfig {
+    fn new(operation_id: &'static str) -> Self {
+        Self {
+            operation_id,
+            need_auth: false,
+            need_from_ipc: false,
+            strict_rate_limit: false,
+            cache_max_age: None,
+            infrastructure: false,
+            params_on_error: false,
+            login_backoff: false,
+            any_content_type: false,
+            query_token: false,
+        }
+    }
+
+    /// For downloads started by the browser, which can't set the authorization header. Only for
+    /// such routes, a token in the url ends up in browser history and proxy logs
+    fn with_query_token(mut self) -> Self {
+        self.query_token = true;
+        self
+    }
+
+    /// For clients that send json as `text/plain` or without a content type, the body still has
+    /// to be valid json within `http.json_limit`
+    pub fn accept_any_content_type(mut self) -> Self {
+        self.any_content_type = true;
+        self
+    }
+
+    fn with_login_backoff(mut self) -> Self {
+        self.login_backoff = true;
+        self
+    }
+
+    fn with_params_on_error(mut self) -> Self {
+        self.params_on_error = true;
+        self
+    }
+
+    fn with_infrastructure(mut self) -> Self {
+        self.infrastructure = true;
+        self
+    }
+
+    fn with_cache_control(mut self, max_age: Duration) -> Self {
+        self.cache_max_age = Some(max_age);
+        self
+    }
+
+    /// Shared caches may only keep responses that don't depend on the caller
+    fn cache_control(&self) -> Option<HeaderValue> {
+        let max_age = self.cache_max_age?.as_secs();
+        let scope = if self.need_auth { "private" } else { "public" };
+        HeaderValue::from_str(&format!("{scope}, max-age={max_age}")).ok()
+    }
+
+    fn with_strict_rate_limit(mut self) -> Self {
+        self.strict_rate_limit = true;
+        self
+    }
+
     fn with_auth(mut self) -> Self {
         self.need_auth = true;
         self
@@ -304,267 +930,838 @@ impl ApiConfig {
     }
 }
 
+fn ipc_allows(ipc_cfg: &IPC, operation_id: &str) -> bool {
+    ipc_cfg.allowed_operations.is_empty()
+        || operation_id == "ping"
+        || ipc_cfg
+            .allowed_operations
+            .iter()
+            .any(|allowed| allowed == operation_id)
+}
+
+/// Issue and verify a token with the configured key, so a broken key or duration fails startup
+/// instead of the first login
+fn jwt_self_test(jwt_cfg: &JWT) -> Result<()> {
+    ensure!(
+        !jwt_cfg.token_hmac_key.is_empty(),
+        "token_hmac_key must not be empty"
+    );
+    ensure!(
+        !jwt_cfg.token_valid_duration.is_zero(),
+        "token_valid_duration must not be zero"
+    );
+
+    let (token, _) = jwt::generate_with_hmac_key(
+        &jwt_cfg.token_hmac_key,
+        chrono::Duration::from_std(jwt_cfg.token_valid_duration)?,
+        chrono::Duration::from_std(jwt_cfg.clock_skew)?,
+        "self-test",
+        AuthClaims::default(),
+    )?;
+    let (subject, _) = jwt::parse_with_hmac_key::<AuthClaims>(
+        &jwt_cfg.token_hmac_key,
+        chrono::Duration::from_std(jwt_cfg.clock_skew)?,
+        &token,
+    )?;
+    ensure!(
+        subject == "self-test",
+        "token subject changed in round trip: {subject}"
+    );
+
+    Ok(())
+}
+
 async fn pre_check(
     state: &AppState,
     cfg: &ApiConfig,
     ctx: &mut Context,
     headers: &HeaderMap,
+    query_token: Option<&str>,
 ) -> Result<()> {
+    if cfg.infrastructure {
+        return Ok(());
+    }
+
+    if state.shutting_down.load(Ordering::SeqCst) {
+        return Err(Error::ServiceUnavailable("shutting down".to_string()).into());
+    }
+
+    let http_cfg = &state.core.repo.cfg.http;
+    if http_cfg
+        .disabled_endpoints
+        .iter()
+        .any(|operation_id| operation_id == cfg.operation_id)
+    {
+        return Err(Error::FeatureDisabled).wrap_err(format!("endpoint: {}", cfg.operation_id));
+    }
+
     if cfg.need_from_ipc && !state.is_ipc {
         return Err(Error::ApiMustRequestFromIPC.into());
     }
 
+    if state.is_ipc && !ipc_allows(&state.core.repo.cfg.ipc, cfg.operation_id) {
+        return Err(Error::ApiNotAllowedOverIPC)
+            .wrap_err(format!("endpoint: {}", cfg.operation_id));
+    }
+
+    let tenant_header = headers
+        .get(TENANT_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
     if cfg.need_from_ipc || !cfg.need_auth {
-        return Ok(());
+        return ctx.set_tenant(None, tenant_header);
     }
 
-    let Some(authorization) = headers
-        .get(header::AUTHORIZATION)
-        .and_then(|value| value.to_str().ok())
-    else {
-        return Err(Error::Unauthorized.into());
-    };
+    let (subject, claims) = authenticate(state, headers, query_token)?;
+    let user = resolve_subject(state, subject, ctx.deadline).await?;
+    ctx.user_id = user.id.clone();
+    // the row, not the claim, so a demoted user loses access before the token expires
+    ctx.entitlements = state
+        .core
+        .repo
+        .cfg
+        .auth
+        .entitlements
+        .get(&user.role)
+        .cloned()
+        .unwrap_or_default();
+    ctx.role = Some(user.role.clone());
+    // the row as well, a moved user leaves the old tenant and a token without the claim isn't
+    // left unscoped
+    let tenant_id = user.tenant_id.clone();
+    ctx.insert(AuthUser(user));
+    ctx.set_tenant(tenant_id, tenant_header)?;
+    if let Some(actor) = claims.act {
+        let actor = resolve_subject(state, actor.sub, ctx.deadline).await?;
+        // an admin demoted after impersonating can't keep acting as the user
+        if !actor.role.at_least(&user_model::Role::Admin) {
+            return Err(Error::Forbidden).wrap_err("the actor is no longer an admin");
+        }
+        ctx.add_log_field("actor", actor.id.clone());
+        ctx.actor_id = Some(actor.id);
+    }
 
-    let mut parts = authorization.split_whitespace();
-    let Some(scheme) = parts.next() else {
-        return Err(Error::Unauthorized.into());
-    };
-    let Some(token) = parts.next() else {
-        return Err(Error::Unauthorized.into());
-    };
+    Ok(())
+}
 
-    if !scheme.eq_ignore_ascii_case("bearer") {
-        return Err(Error::Unauthorized.into());
+/// Load the active user of the token subject, see `http.jwt.subject_source`. The token of a
+/// deleted user is `Unauthorized`
+async fn resolve_subject(
+    state: &AppState,
+    subject: String,
+    deadline: Option<tokio::time::Instant>,
+) -> Result<user_model::Model> {
+    let user = &state.core.service.user;
+    match state.core.repo.cfg.http.jwt.subject_source {
+        SubjectSource::UserId => user.info(subject, deadline).await,
+        SubjectSource::PublicId => user.info_by_public_id(subject, deadline).await,
     }
+    .map_err(|err| match err.downcast_ref::<Error>() {
+        Some(Error::UserNotFound) => err.wrap_err(Error::Unauthorized),
+        _ => err,
+    })
+}
 
-    let hmac_key = state.core.repo.cfg.http.jwt.token_hmac_key.clone();
-    let (user_id, _) = jwt::parse_with_hmac_key::<Value>(&hmac_key, token)
-        .map_err(|_| eyre!(Error::Unauthorized))?;
-    ctx.user_id = user_id;
+/// Resolve the token subject and claims from the bearer token in the authorization header, or
+/// from `query_token` of [`ApiConfig::with_query_token`] routes when the header is missing.
+/// Fails with `MissingToken` to prompt a login, `ExpiredToken` to refresh and `InvalidToken` for
+/// anything else
+fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+    query_token: Option<&str>,
+) -> Result<(String, AuthClaims)> {
+    let token = match headers.get(header::AUTHORIZATION) {
+        Some(authorization) => {
+            let Ok(authorization) = authorization.to_str() else {
+                return Err(Error::InvalidToken.into());
+            };
+            let mut parts = authorization.split_whitespace();
+            let Some(scheme) = parts.next() else {
+                return Err(Error::MissingToken.into());
+            };
+            let Some(token) = parts.next() else {
+                return Err(Error::MissingToken.into());
+            };
 
-    Ok(())
-}
+            if !scheme.eq_ignore_ascii_case("bearer") {
+                return Err(Error::InvalidToken.into());
+            }
+            token
+        }
+        None => query_token.ok_or(Error::MissingToken)?,
+    };
+
+    let jwt_cfg = &state.core.repo.cfg.http.jwt;
+    let clock_skew = chrono::Duration::from_std(jwt_cfg.clock_skew)?;
+    let (user_id, data) =
+        jwt::parse_with_hmac_key::<Value>(&jwt_cfg.token_hmac_key, clock_skew, token).map_err(
+            |err| match err.downcast_ref::<jsonwebtoken::errors::Error>() {
+                Some(jwt_err)
+                    if *jwt_err.kind() == jsonwebtoken::errors::ErrorKind::ExpiredSignature =>
+                {
+                    eyre!(Error::ExpiredToken)
+                }
+                _ => eyre!(Error::InvalidToken),
+            },
+        )?;
+    // tokens issued before claims carried data hold `null` here
+    let claims = serde_json::from_value::<AuthClaims>(data).unwrap_or_default();
 
-async fn snapshot_log_fields(
-    storage: &Arc<RwLock<Vec<(String, String)>>>,
-) -> BTreeMap<String, String> {
-    let guard = storage.read().await;
-    guard.iter().cloned().collect()
+    Ok((user_id, claims))
 }
 
-fn restore_error_from_report(report: &Report) -> Error {
-    report
-        .downcast_ref::<Error>()
-        .cloned()
-        .or_else(|| {
-            report
-                .chain()
-                .find_map(|cause| cause.downcast_ref::<Error>().cloned())
-        })
-        .unwrap_or_else(|| Error::Unknown(report.to_string()))
+/// Count a request without a valid identity against its client ip. Requests without a token are
+/// counted before pre_check so a flood is turned away early, requests whose token failed or wasn't
+/// needed once pre_check is done, so invalid-token floods are limited too
+fn check_anonymous_rate_limit(state: &AppState, cfg: &ApiConfig, client_ip: &str) -> Result<()> {
+    let Some(rate_limiter) = &state.rate_limiter else {
+        return Ok(());
+    };
+    if cfg.infrastructure {
+        return Ok(());
+    }
+
+    rate_limiter.check(&RateLimitKey::Anonymous(client_ip.to_string()))
 }
 
-pub fn one_line_error(err: &Report) -> String {
-    let mut out = String::new();
-    for (i, e) in err.chain().enumerate() {
-        if i > 0 {
-            out.push_str(": ");
-        }
-        let mut msg = e.to_string();
-        msg = msg.replace('\n', " ").replace('\r', " ");
-        out.push_str(&msg);
+/// Count the request once pre_check resolved it: an authenticated one against its user, an
+/// anonymous one against its client ip unless that was done before pre_check already, and any
+/// request of a strict endpoint against its strict bucket
+fn check_rate_limit(
+    state: &AppState,
+    cfg: &ApiConfig,
+    ctx: &Context,
+    client_ip: &str,
+    anonymous_counted: bool,
+) -> Result<()> {
+    let Some(rate_limiter) = &state.rate_limiter else {
+        return Ok(());
+    };
+    if cfg.infrastructure {
+        return Ok(());
     }
-    out
+
+    let key = RateLimitKey::resolve(&ctx.user_id, client_ip);
+    if matches!(key, RateLimitKey::Authenticated(_)) || !anonymous_counted {
+        rate_limiter.check(&key)?;
+    }
+    if cfg.strict_rate_limit {
+        rate_limiter.check_strict(&key)?;
+    }
+    Ok(())
 }
 
-fn extract_location_from_debug(err: &Report) -> Option<String> {
-    let debug = format!("{:?}", err);
-    let mut lines = debug.lines();
-    while let Some(line) = lines.next() {
-        if line.trim() == "Location:" {
-            if let Some(location_line) = lines.next() {
-                let cleaned = strip_str(location_line);
-                let trimmed = cleaned.trim();
-                if !trimmed.is_empty() {
-                    return Some(trimmed.to_string());
-                }
-            }
-        }
+/// Reject a login while the client's last failure is still held back, checked before the
+/// credentials so parallel guesses are slowed down too
+fn check_login_backoff(state: &AppState, cfg: &ApiConfig, client_ip: &str) -> Result<()> {
+    let Some(login_backoff) = state.login_backoff.as_ref().filter(|_| cfg.login_backoff) else {
+        return Ok(());
+    };
+    if let Some(remaining) = login_backoff.held_back(client_ip) {
+        return Err(Error::TooManyRequests)
+            .wrap_err(format!("login held back for another {remaining:?}"));
     }
-    None
+    Ok(())
 }
 
-async fn wrap_handler<Res, Fut, F>(
-    state: AppState,
-    cfg: ApiConfig,
-    client_ip: String,
+/// How long to hold back a failed login by the client's current backoff, a successful one clears
+/// it. Only wrong credentials count, a malformed or rate limited request says nothing about
+/// guessing
+fn login_backoff_delay<T>(
+    state: &AppState,
+    cfg: &ApiConfig,
+    client_ip: &str,
+    result: &Result<T>,
+) -> Option<Duration> {
+    let login_backoff = state.login_backoff.as_ref()?;
+    if !cfg.login_backoff {
+        return None;
+    }
+    match result {
+        Ok(_) => {
+            login_backoff.succeeded(client_ip);
+            None
+        }
+        Err(err) => match restore_error_from_report(err) {
+            Error::UserNotFound | Error::UserInvalidPassword => {
+                Some(login_backoff.failed(client_ip))
+            }
+            _ => None,
+        },
+    }
+}
+
+/// A handler slot of `http.concurrency`, probes never queue behind api traffic
+async fn acquire_slot<'a>(
+    state: &'a AppState,
+    cfg: &ApiConfig,
+    deadline: tokio::time::Instant,
+) -> Result<Option<Slot<'a>>> {
+    let Some(scheduler) = &state.scheduler else {
+        return Ok(None);
+    };
+    if cfg.infrastructure {
+        return Ok(None);
+    }
+    scheduler.acquire(state.is_ipc, deadline).await.map(Some)
+}
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Tenant of the request when the user has none, see [`Context::set_tenant`]
+pub const TENANT_ID_HEADER: &str = "X-Tenant-Id";
+
+/// Query parameter of the bearer token on [`ApiConfig::with_query_token`] routes
+pub const ACCESS_TOKEN_QUERY_PARAM: &str = "access_token";
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Request attributes shared by the checks and access logs around every handler
+struct RequestMeta {
+    request_id: String,
     method: &'static str,
     uri_path: String,
+    /// Route template, e.g. `/api/v1/user/{id}`, a low cardinality label unlike `uri_path`
+    route: String,
+    client_ip: String,
+    /// Only captured for endpoints with [`ApiConfig::with_params_on_error`]
+    params: Option<RequestParams>,
+    /// Only captured for endpoints with [`ApiConfig::with_query_token`]
+    query_token: Option<String>,
+    path_params: BTreeMap<String, String>,
+}
+
+impl RequestMeta {
+    fn new(
+        state: &AppState,
+        method: &'static str,
+        uri_path: String,
+        matched_path: Option<MatchedPath>,
+        client_ip: IpAddr,
+        peer_ip: Option<IpAddr>,
+        headers: &HeaderMap,
+    ) -> Self {
+        Self {
+            request_id: resolve_request_id(state, peer_ip, headers),
+            method,
+            route: matched_path.map_or_else(|| uri_path.clone(), |path| path.as_str().to_string()),
+            uri_path,
+            client_ip: client_ip.to_string(),
+            params: None,
+            query_token: None,
+            path_params: BTreeMap::new(),
+        }
+    }
+
+    /// Values of the `{name}` segments of the route, e.g. `id` of `/api/v1/user/{id}/impersonate`
+    fn with_path_params(mut self, path_params: Option<RawPathParams>) -> Self {
+        self.path_params = path_params
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self
+    }
+
+    /// The logged uri is the path only and `access_token` is redacted from `request_params`, so
+    /// the token is kept here and nowhere else
+    fn with_query_token(mut self, cfg: &ApiConfig, query: Option<&str>) -> Self {
+        if cfg.query_token {
+            self.query_token = url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .find(|(key, _)| key == ACCESS_TOKEN_QUERY_PARAM)
+                .map(|(_, token)| token.into_owned());
+        }
+        self
+    }
+
+    fn with_params(mut self, cfg: &ApiConfig, params: impl FnOnce() -> RequestParams) -> Self {
+        if cfg.params_on_error {
+            self.params = Some(params());
+        }
+        self
+    }
+}
+
+/// Reuse the caller's request id so logs on both sides can be correlated, otherwise generate one.
+/// Only ids from the ipc socket or a trusted proxy are honored, so clients cannot pollute the id
+/// space
+fn resolve_request_id(state: &AppState, peer_ip: Option<IpAddr>, headers: &HeaderMap) -> String {
+    let http_cfg = &state.core.repo.cfg.http;
+    let trusted = is_trusted_peer(state, peer_ip);
+
+    headers
+        .get(http_cfg.request_id_header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| trusted && is_valid_request_id(value))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string())
+}
+
+/// The ipc socket or a peer in `http.trusted_proxies`, whose forwarded headers are honored
+fn is_trusted_peer(state: &AppState, peer_ip: Option<IpAddr>) -> bool {
+    state.is_ipc
+        || peer_ip.is_some_and(|peer_ip| {
+            state
+                .core
+                .repo
+                .cfg
+                .http
+                .trusted_proxies
+                .iter()
+                .any(|proxy| proxy.parse::<IpAddr>() == Ok(peer_ip))
+        })
+}
+
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+/// Also adds the instance id when `http.instance_id_header` is set
+fn with_request_id(state: &AppState, mut response: AxumResponse, request_id: &str) -> AxumResponse {
+    let http_cfg = &state.core.repo.cfg.http;
+    let header_name = HeaderName::try_from(http_cfg.request_id_header.as_str());
+    if let (Ok(name), Ok(value)) = (header_name, HeaderValue::from_str(request_id)) {
+        response.headers_mut().insert(name, value);
+    }
+    if let Some(header_name) = &http_cfg.instance_id_header
+        && let (Ok(name), Ok(value)) = (
+            HeaderName::try_from(header_name.as_str()),
+            HeaderValue::from_str(&state.core.instance_id),
+        )
+    {
+        response.headers_mut().insert(name, value);
+    }
+    response
+}
+
+fn restore_error_from_report(report: &Report) -> Error {
+    report
+        .downcast_ref::<Error>()
+        .cloned()
+        .or_else(|| {
+            report
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<Error>().cloned())
+        })
+        .or_else(|| {
+            report
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<DbErr>().map(Error::from))
+        })
+        .unwrap_or_else(|| Error::Unknown(report.to_string()))
+}
+
+pub fn one_line_error(err: &Report) -> String {
+    let mut out = String::new();
+    for (i, e) in err.chain().enumerate() {
+        if i > 0 {
+            out.push_str(": ");
+        }
+        let mut msg = e.to_string();
+        msg = msg.replace(['\n', '\r'], " ");
+        out.push_str(&msg);
+    }
+    out
+}
+
+fn extract_location_from_debug(err: &Report) -> Option<String> {
+    let debug = format!("{:?}", err);
+    let mut lines = debug.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == "Location:"
+            && let Some(location_line) = lines.next()
+        {
+            let cleaned = strip_str(location_line);
+            let trimmed = cleaned.trim();
+            if !trimmed.is_empty() {
+                return Some(trimmed.to_string());
+            }
+        }
+    }
+    None
+}
+
+async fn wrap_handler<Res, Fut, F>(
+    state: AppState,
+    cfg: ApiConfig,
+    meta: RequestMeta,
     headers: HeaderMap,
+    render: fn(Res) -> AxumResponse,
     fut_factory: F,
 ) -> AxumResponse
 where
-    Res: Serialize + Send + 'static,
+    Res: Send + 'static,
     Fut: Future<Output = Result<Res>> + Send,
     F: FnOnce(Arc<Core>, Context, HeaderMap) -> Fut,
 {
-    let mut ctx = Context::default();
+    let request_timeout = state.core.repo.cfg.http.request_timeout;
+    let deadline = tokio::time::Instant::now() + request_timeout;
+    let mut ctx = Context {
+        request_id: meta.request_id.clone(),
+        is_ipc: state.is_ipc,
+        route: meta.route.clone(),
+        path_params: meta.path_params.clone(),
+        deadline: Some(deadline),
+        ..Default::default()
+    };
     let start = Instant::now();
+    // a request with a token may be authenticated, it isn't counted as anonymous before pre_check
+    let anonymous_counted =
+        !headers.contains_key(header::AUTHORIZATION) && meta.query_token.is_none();
     let result = {
-        if let Err(err) = pre_check(&state, &cfg, &mut ctx, &headers).await {
+        if anonymous_counted
+            && let Err(err) = check_anonymous_rate_limit(&state, &cfg, &meta.client_ip)
+        {
+            Err(err)
+        } else if let Err(err) = pre_check(
+            &state,
+            &cfg,
+            &mut ctx,
+            &headers,
+            meta.query_token.as_deref(),
+        )
+        .await
+        {
+            match anonymous_counted {
+                true => Err(err),
+                // the token didn't authenticate, once limited the flood is told so
+                false => Err(check_anonymous_rate_limit(&state, &cfg, &meta.client_ip)
+                    .err()
+                    .unwrap_or(err)),
+            }
+        } else if let Err(err) =
+            check_rate_limit(&state, &cfg, &ctx, &meta.client_ip, anonymous_counted)
+        {
+            Err(err)
+        } else if let Err(err) = check_login_backoff(&state, &cfg, &meta.client_ip) {
             Err(err)
         } else {
-            fut_factory(state.core, ctx.clone(), headers).await
+            match acquire_slot(&state, &cfg, deadline).await {
+                Err(err) => Err(err),
+                Ok(_slot) => {
+                    let fut = fut_factory(state.core.clone(), ctx.clone(), headers);
+                    match tokio::time::timeout_at(deadline, fut).await {
+                        Ok(result) => result,
+                        Err(_) => Err(Error::RequestTimeout)
+                            .wrap_err(format!("handler exceeded {request_timeout:?}")),
+                    }
+                }
+            }
         }
     };
     let elapsed = start.elapsed();
+    if let Some(delay) = login_backoff_delay(&state, &cfg, &meta.client_ip, &result) {
+        tokio::time::sleep(delay).await;
+    }
 
     match result {
         Ok(data) => {
-            let log_fields = snapshot_log_fields(&ctx.log_fields).await;
-            info!(
-                user = ctx.user_id,
-                method = method,
-                uri = uri_path,
-                client_ip = client_ip,
-                log_fields = debug(&log_fields),
-                elapsed = ?elapsed,
-                "api request"
-            );
-            Response::ok(data).into_response()
+            let log_fields = ctx.log_fields.snapshot();
+            if cfg.infrastructure {
+                debug!(
+                    request_id = ctx.request_id,
+                    route = meta.route,
+                    client_ip = meta.client_ip,
+                    elapsed = ?elapsed,
+                    "probe request"
+                );
+            } else {
+                info!(
+                    request_id = ctx.request_id,
+                    user = ctx.user_id,
+                    method = meta.method,
+                    route = meta.route,
+                    uri = meta.uri_path,
+                    client_ip = meta.client_ip,
+                    log_fields = debug(&log_fields),
+                    elapsed = ?elapsed,
+                    "api request"
+                );
+            }
+            let mut response =
+                bigint::render_with(state.core.repo.cfg.http.bigint_as_string, || render(data));
+            if response.status().is_success()
+                && let Some(cache_control) = cfg.cache_control()
+            {
+                response
+                    .headers_mut()
+                    .insert(header::CACHE_CONTROL, cache_control);
+            }
+            response.extensions_mut().insert(ctx.extensions.clone());
+            with_request_id(&state, response, &ctx.request_id)
         }
         Err(err) => {
             let code_err = restore_error_from_report(&err);
 
-            let log_fields = snapshot_log_fields(&ctx.log_fields).await;
-            let log_fields_on_error = snapshot_log_fields(&ctx.log_fields_on_error).await;
+            if let Some(params) = &meta.params {
+                ctx.add_log_field_on_error("request_params", params.render());
+            }
+            let log_fields = ctx.log_fields.snapshot();
+            let log_fields_on_error = ctx.log_fields_on_error.snapshot();
 
             warn!(
+                request_id = ctx.request_id,
                 user = ctx.user_id,
-                method = method,
-                uri = uri_path,
+                method = meta.method,
+                route = meta.route,
+                uri = meta.uri_path,
                 err_code = code_err.code(),
                 err = one_line_error(&err),
                 err_location = extract_location_from_debug(&err),
-                client_ip = client_ip,
+                client_ip = meta.client_ip,
                 log_fields = debug(&log_fields),
                 log_fields_on_error = debug(&log_fields_on_error),
                 elapsed = ?elapsed,
                 "api request failed"
             );
 
-            Response::<Res> {
+            // the per-field messages of a failed validation
+            let mut response = Response::<FieldErrors> {
                 code: code_err.code(),
                 msg: one_line_error(&err).to_string(),
-                data: None,
+                data: ctx.get::<FieldErrors>(),
+            }
+            .into_response();
+            // load balancers and retrying clients only look at the status
+            if code_err.is_retryable() {
+                *response.status_mut() = match code_err {
+                    Error::TooManyRequests => axum::http::StatusCode::TOO_MANY_REQUESTS,
+                    _ => axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                };
             }
-            .into_response()
+            response.extensions_mut().insert(ctx.extensions.clone());
+            with_request_id(&state, no_store(response), &ctx.request_id)
         }
     }
 }
 
-async fn handle_param_error<Res>(
-    method: &'static str,
-    uri_path: String,
-    client_ip: String,
-    rejection_msg: String,
-) -> AxumResponse
-where
-    Res: Serialize + Send + 'static,
-{
-    let err = Error::InvidRequestParameter(rejection_msg);
-
-    warn!(
-        method = method,
-        uri = uri_path,
-        err_code = err.code(),
-        err = ?err,
-        client_ip = client_ip,
-        elapsed = 0,
-        "api request failed"
-    );
-
-    Response::<Res>::err(&err).into_response()
+/// Errors depend on the moment (rate limits, auth, db state), they must never be cached
+fn no_store(mut response: AxumResponse) -> AxumResponse {
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
 }
 
-async fn handle_request<Req, Res, Rej, MapRejection, H, Fut>(
+/// `request` carries the rejection message when the request could not be extracted. Extraction
+/// and validation errors are answered only once the request passed [`pre_check`] and the limits
+async fn handle_request<Req, Res, H, Fut>(
     state: AppState,
     cfg: ApiConfig,
-    client_ip: String,
-    method: &'static str,
-    uri_path: String,
+    meta: RequestMeta,
     headers: HeaderMap,
-    request: Result<Req, Rej>,
-    map_rejection: MapRejection,
+    request: Result<Req, String>,
+    render: fn(Res) -> AxumResponse,
     handler: H,
 ) -> AxumResponse
 where
-    Req: Send + 'static,
-    Res: Serialize + Send + 'static,
-    Rej: Send,
-    MapRejection: FnOnce(Rej) -> String + Send,
+    Req: ValidateRequest + Send + 'static,
+    Res: Send + 'static,
     H: FnOnce(Arc<Core>, Context, HeaderMap, Req) -> Fut + Send,
     Fut: Future<Output = Result<Res>> + Send,
 {
-    match request {
-        Ok(req) => {
-            wrap_handler::<Res, _, _>(
-                state,
-                cfg,
-                client_ip,
-                method,
-                uri_path,
-                headers,
-                |state, ctx, headers| handler(state, ctx, headers, req),
-            )
-            .await
-        }
-        Err(rejection) => {
-            let message = map_rejection(rejection);
-            handle_param_error::<Res>(method, uri_path, client_ip, message).await
-        }
-    }
+    // checked after auth and rate limits, so only admitted callers learn about the schema
+    wrap_handler::<Res, _, _>(
+        state,
+        cfg,
+        meta,
+        headers,
+        render,
+        |state, ctx, headers| async move {
+            let req = request.map_err(Error::InvidRequestParameter)?;
+            if let Err(errors) = req.validate_request() {
+                let fields = validation::field_errors(&errors);
+                let err = Error::ValidationFailed(validation::summary(&fields));
+                ctx.insert(fields);
+                return Err(err.into());
+            }
+            handler(state, ctx, headers, req).await
+        },
+    )
+    .await
+}
+
+fn render_envelope<Res: Serialize>(data: Res) -> AxumResponse {
+    Response::ok(data).into_response()
 }
 
 pub fn wrap_get_handler<Q, Res, H, Fut>(handler: H, cfg: ApiConfig) -> MethodRouter<AppState>
 where
-    Q: DeserializeOwned + Send + 'static,
+    Q: DeserializeOwned + ValidateRequest + Send + 'static,
     Res: Serialize + Send + 'static,
     H: Clone + Send + Sync + 'static,
     H: Fn(Arc<Core>, Context, HeaderMap, Q) -> Fut,
     Fut: Future<Output = Result<Res>> + Send + 'static,
+{
+    get_with_render(handler, cfg, render_envelope::<Res>)
+}
+
+/// Like [`wrap_get_handler`] but the handler builds the response itself, e.g. a streaming body.
+/// Errors still use the json envelope
+pub fn wrap_get_raw_handler<Q, H, Fut>(handler: H, cfg: ApiConfig) -> MethodRouter<AppState>
+where
+    Q: DeserializeOwned + ValidateRequest + Send + 'static,
+    H: Clone + Send + Sync + 'static,
+    H: Fn(Arc<Core>, Context, HeaderMap, Q) -> Fut,
+    Fut: Future<Output = Result<AxumResponse>> + Send + 'static,
+{
+    get_with_render(handler, cfg, |response| response)
+}
+
+fn get_with_render<Q, Res, H, Fut>(
+    handler: H,
+    cfg: ApiConfig,
+    render: fn(Res) -> AxumResponse,
+) -> MethodRouter<AppState>
+where
+    Q: DeserializeOwned + ValidateRequest + Send + 'static,
+    Res: Send + 'static,
+    H: Clone + Send + Sync + 'static,
+    H: Fn(Arc<Core>, Context, HeaderMap, Q) -> Fut,
+    Fut: Future<Output = Result<Res>> + Send + 'static,
 {
     get(
         move |State(state): State<AppState>,
               ClientIp(client_ip): ClientIp,
+              PeerIp(peer_ip): PeerIp,
               OriginalUri(uri): OriginalUri,
+              matched_path: Option<MatchedPath>,
+              path_params: Result<RawPathParams, RawPathParamsRejection>,
               headers,
               query: Result<Query<Q>, QueryRejection>| {
             let handler = handler.clone();
             let uri_path = uri.path().to_string();
             let cfg = cfg.clone();
             async move {
-                let client_ip = client_ip.to_string();
+                let meta = RequestMeta::new(
+                    &state,
+                    "get",
+                    uri_path,
+                    matched_path,
+                    client_ip,
+                    peer_ip,
+                    &headers,
+                )
+                .with_path_params(path_params.ok())
+                .with_query_token(&cfg, uri.query())
+                .with_params(&cfg, || {
+                    RequestParams::Query(uri.query().unwrap_or_default().to_string())
+                });
                 handle_request(
                     state,
                     cfg,
-                    client_ip,
+                    meta,
+                    headers,
+                    query
+                        .map(|Query(query)| query)
+                        .map_err(|rejection| rejection.body_text()),
+                    render,
+                    handler,
+                )
+                .await
+            }
+        },
+    )
+}
+
+/// Like [`wrap_get_handler`] for list endpoints: besides the query `Q`, paging and sorting are
+/// extracted and validated against `spec`, bad values are answered as `InvidRequestParameter`
+pub fn wrap_list_handler<Q, Res, H, Fut>(
+    handler: H,
+    cfg: ApiConfig,
+    spec: ListSpec,
+) -> MethodRouter<AppState>
+where
+    Q: DeserializeOwned + ValidateRequest + Send + 'static,
+    Res: Serialize + Send + 'static,
+    H: Clone + Send + Sync + 'static,
+    H: Fn(Arc<Core>, Context, HeaderMap, ListReq<Q>) -> Fut,
+    Fut: Future<Output = Result<Res>> + Send + 'static,
+{
+    // sorting is read by the extractor, paging here so `http.page_limits` can apply
+    let spec_layer = spec.clone();
+    get(
+        move |State(state): State<AppState>,
+              ClientIp(client_ip): ClientIp,
+              PeerIp(peer_ip): PeerIp,
+              OriginalUri(uri): OriginalUri,
+              matched_path: Option<MatchedPath>,
+              path_params: Result<RawPathParams, RawPathParamsRejection>,
+              headers,
+              query: Result<Query<Q>, QueryRejection>,
+              sort: Result<SortParams, ListParamsRejection>| {
+            let handler = handler.clone();
+            let uri_path = uri.path().to_string();
+            let cfg = cfg.clone();
+            let spec = spec.clone();
+            async move {
+                let spec = spec
+                    .with_page_limits(state.core.repo.cfg.http.page_limits.get(cfg.operation_id));
+                let page = PageParams::from_uri(&uri, &spec);
+                let meta = RequestMeta::new(
+                    &state,
                     "get",
                     uri_path,
+                    matched_path,
+                    client_ip,
+                    peer_ip,
+                    &headers,
+                )
+                .with_path_params(path_params.ok())
+                .with_query_token(&cfg, uri.query())
+                .with_params(&cfg, || {
+                    RequestParams::Query(uri.query().unwrap_or_default().to_string())
+                });
+                let request =
+                    query
+                        .map_err(|rejection| rejection.body_text())
+                        .and_then(|Query(filter)| {
+                            Ok(ListReq {
+                                filter,
+                                page: page.map_err(|rejection| rejection.0)?,
+                                sort: sort.map_err(|rejection| rejection.0)?,
+                            })
+                        });
+                handle_request(
+                    state,
+                    cfg,
+                    meta,
                     headers,
-                    query.map(|Query(query)| query),
-                    |rejection| rejection.body_text(),
-                    move |state, ctx, headers, query| handler(state, ctx, headers, query),
+                    request,
+                    render_envelope::<Res>,
+                    handler,
                 )
                 .await
             }
         },
     )
+    .layer(Extension(spec_layer))
+}
+
+/// Like the `Json` extractor, but the body must also fit `http.json_limit` before it's deserialized
+/// and the content type check can be relaxed with [`ApiConfig::accept_any_content_type`]
+fn parse_json_body<Req: DeserializeOwned>(
+    state: &AppState,
+    cfg: &ApiConfig,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> std::result::Result<Req, String> {
+    if !cfg.any_content_type && !json_limit::is_json_content_type(headers) {
+        return Err(MissingJsonContentType::default().body_text());
+    }
+    json_limit::check(body, &state.core.repo.cfg.http.json_limit).map_err(|err| err.to_string())?;
+    Json::<Req>::from_bytes(body)
+        .map(|Json(json)| json)
+        .map_err(|rejection| rejection.body_text())
 }
 
 pub fn wrap_post_handler<Req, Res, H, Fut>(handler: H, cfg: ApiConfig) -> MethodRouter<AppState>
 where
-    Req: DeserializeOwned + Send + 'static,
+    Req: DeserializeOwned + ValidateRequest + Send + 'static,
     Res: Serialize + Send + 'static,
     H: Clone + Send + Sync + 'static,
     H: Fn(Arc<Core>, Context, HeaderMap, Req) -> Fut,
@@ -573,24 +1770,36 @@ where
     post(
         move |State(state): State<AppState>,
               ClientIp(client_ip): ClientIp,
+              PeerIp(peer_ip): PeerIp,
               OriginalUri(uri): OriginalUri,
+              matched_path: Option<MatchedPath>,
+              path_params: Result<RawPathParams, RawPathParamsRejection>,
               headers,
-              json: Result<Json<Req>, JsonRejection>| {
+              body: Bytes| {
             let handler = handler.clone();
             let uri_path = uri.path().to_string();
             let cfg = cfg.clone();
             async move {
-                let client_ip = client_ip.to_string();
+                let json = parse_json_body(&state, &cfg, &headers, &body);
+                let meta = RequestMeta::new(
+                    &state,
+                    "post",
+                    uri_path,
+                    matched_path,
+                    client_ip,
+                    peer_ip,
+                    &headers,
+                )
+                .with_path_params(path_params.ok())
+                .with_params(&cfg, || RequestParams::Json(body.clone()));
                 handle_request(
                     state,
                     cfg,
-                    client_ip,
-                    "post",
-                    uri_path,
+                    meta,
                     headers,
-                    json.map(|Json(json)| json),
-                    |rejection| rejection.body_text(),
-                    move |state, ctx, headers, json| handler(state, ctx, headers, json),
+                    json,
+                    render_envelope::<Res>,
+                    handler,
                 )
                 .await
             }
@@ -598,85 +1807,2283 @@ where
     )
 }
 
-pub struct ClientIp(pub IpAddr);
+/// Address of the directly connected peer, which is a proxy when the service sits behind one
+pub struct PeerIp(pub Option<IpAddr>);
 
-impl<S> FromRequestParts<S> for ClientIp
+impl<S> FromRequestParts<S> for PeerIp
 where
     S: Send + Sync,
 {
-    type Rejection = (axum::http::StatusCode, String);
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(PeerIp(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        ))
+    }
+}
+
+/// Address of the client. Forwarded headers are only honored from the ipc socket and peers in
+/// `http.trusted_proxies`, anyone else could pick their own address. With `http.proxy_protocol`
+/// the peer address is already the client's and the headers are ignored
+pub struct ClientIp(pub IpAddr);
+
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
 
-    fn from_request_parts(
+    async fn from_request_parts(
         parts: &mut Parts,
-        state: &S,
-    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send {
-        async move {
-            if let Ok(RightmostXForwardedFor(ip)) =
-                RightmostXForwardedFor::from_request_parts(parts, state).await
-            {
-                return Ok(ClientIp(ip));
-            }
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let PeerIp(peer_ip) = PeerIp::from_request_parts(parts, state).await?;
+        if !state.core.repo.cfg.http.proxy_protocol
+            && is_trusted_peer(state, peer_ip)
+            && let Some(ip) = forwarded_ip(parts).await
+        {
+            return Ok(ClientIp(ip));
+        }
 
-            if let Ok(RightmostForwarded(ip)) =
-                RightmostForwarded::from_request_parts(parts, state).await
-            {
-                return Ok(ClientIp(ip));
-            }
+        Ok(ClientIp(
+            peer_ip.unwrap_or(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+        ))
+    }
+}
 
-            if let Ok(TrueClientIp(ip)) = TrueClientIp::from_request_parts(parts, state).await {
-                return Ok(ClientIp(ip));
-            }
+async fn forwarded_ip(parts: &mut Parts) -> Option<IpAddr> {
+    if let Ok(RightmostXForwardedFor(ip)) =
+        RightmostXForwardedFor::from_request_parts(parts, &()).await
+    {
+        return Some(ip);
+    }
 
-            if let Ok(CloudFrontViewerAddress(ip)) =
-                CloudFrontViewerAddress::from_request_parts(parts, state).await
-            {
-                return Ok(ClientIp(ip));
-            }
+    if let Ok(RightmostForwarded(ip)) = RightmostForwarded::from_request_parts(parts, &()).await {
+        return Some(ip);
+    }
 
-            if let Ok(FlyClientIp(ip)) = FlyClientIp::from_request_parts(parts, state).await {
-                return Ok(ClientIp(ip));
-            }
+    if let Ok(TrueClientIp(ip)) = TrueClientIp::from_request_parts(parts, &()).await {
+        return Some(ip);
+    }
 
-            if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
-                return Ok(ClientIp(addr.ip()));
-            }
+    if let Ok(CloudFrontViewerAddress(ip)) =
+        CloudFrontViewerAddress::from_request_parts(parts, &()).await
+    {
+        return Some(ip);
+    }
 
-            Ok(ClientIp(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))))
-        }
+    if let Ok(FlyClientIp(ip)) = FlyClientIp::from_request_parts(parts, &()).await {
+        return Some(ip);
+    }
+
+    None
+}
+
+/// The authenticated user `pre_check` loaded, handlers of `with_auth` routes get it with
+/// [`AuthUser::from_context`]
+#[derive(Clone)]
+pub struct AuthUser(pub user_model::Model);
+
+impl AuthUser {
+    pub fn from_context(ctx: &Context) -> Result<Self> {
+        ctx.get::<Self>()
+            .ok_or(Error::Unauthorized)
+            .wrap_err("the route does not authenticate")
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
     use std::io;
+    use std::sync::Mutex;
 
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use sea_orm::{DatabaseBackend, MockDatabase, TryIntoModel};
     use sidecar::prelude::{Report, WrapErr};
+    use tempfile::{TempDir, tempdir};
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+    use tracing::subscriber::DefaultGuard;
+    use tracing_subscriber::fmt::MakeWriter;
 
     use super::*;
+    use crate::core::db::DB;
+    use crate::core::model::config_kv as config_kv_model;
+    use crate::core::model::user::Role;
+    use crate::core::service::config_kv;
+    use crate::kit::config::{PageLimits, REDACTED};
+    use crate::kit::context::Extensions;
 
-    #[test]
-    fn restore_error_returns_known_variant() {
-        let report: Report = Error::ApiMustRequestFromIPC.into();
-        let restored = restore_error_from_report(&report);
-        assert!(matches!(restored, Error::ApiMustRequestFromIPC));
+    async fn test_state(is_ipc: bool) -> Result<(AppState, TempDir)> {
+        test_state_with(is_ipc, |_| {}).await
     }
 
-    #[test]
-    fn restore_error_falls_back_to_unknown() {
-        let report: Report = io::Error::new(io::ErrorKind::Other, "boom").into();
-        let restored = restore_error_from_report(&report);
-        match restored {
-            Error::Unknown(msg) => assert!(msg.contains("boom")),
-            other => panic!("expected unknown fallback, got {other:?}"),
+    async fn test_state_with(
+        is_ipc: bool,
+        configure: impl FnOnce(&mut Config),
+    ) -> Result<(AppState, TempDir)> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<Config>::new(tmp.path(), "server-test").await?;
+        configure(&mut repo.cfg);
+        let core = Core::new(Sidecar::new(), repo).await?;
+        Ok((
+            AppState {
+                core,
+                is_ipc,
+                rate_limiter: None,
+                login_backoff: None,
+                scheduler: None,
+                shutting_down: Arc::default(),
+            },
+            tmp,
+        ))
+    }
+
+    async fn body_json(response: AxumResponse) -> Result<Value> {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl CapturedLogs {
+        fn content(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
         }
     }
 
-    #[test]
-    fn restore_error_handles_nested_reports() {
-        let report: Report = Error::ApiMustRequestFromIPC.into();
-        let report = report.wrap_err("layer one").wrap_err("layer two");
-        let restored = restore_error_from_report(&report);
-        assert!(matches!(restored, Error::ApiMustRequestFromIPC));
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for CapturedLogs {
+        type Writer = CapturedLogs;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Routes the logs of the current thread into the returned buffer until the guard drops
+    fn capture_logs() -> (DefaultGuard, CapturedLogs) {
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(logs.clone())
+            .finish();
+        (tracing::subscriber::set_default(subscriber), logs)
+    }
+
+    /// Clients of `cmd::ipc` are generated from this spec, a duplicate or missing operation id
+    /// would only show up at codegen time
+    #[test]
+    fn openapi_operations_have_unique_ids_and_tags() {
+        let doc = base_openapi_doc();
+        let mut seen = HashSet::new();
+        for (path, item) in &doc.paths.paths {
+            let operations = [
+                &item.get,
+                &item.put,
+                &item.post,
+                &item.delete,
+                &item.options,
+                &item.head,
+                &item.patch,
+                &item.trace,
+            ];
+            for operation in operations.into_iter().flatten() {
+                let operation_id = operation.operation_id.clone().unwrap_or_default();
+                assert!(!operation_id.is_empty(), "{path} has no operation id");
+                assert!(
+                    seen.insert(operation_id.clone()),
+                    "{path} reuses operation id {operation_id}"
+                );
+                assert!(
+                    operation.tags.as_ref().is_some_and(|tags| !tags.is_empty()),
+                    "{path} ({operation_id}) has no tag"
+                );
+            }
+        }
+        assert!(!seen.is_empty());
+    }
+
+    #[tokio::test]
+    async fn disabled_swagger_answers_with_configured_response() -> Result<()> {
+        let response = swagger_disabled_router::<()>("", None)
+            .oneshot(Request::get("/swagger-ui/index.html").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        assert_eq!(&body[..], b"Not Found");
+
+        let response = swagger_disabled_router::<()>("", Some("/".to_string()))
+            .oneshot(Request::get("/swagger-ui").body(Body::empty())?)
+            .await?;
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::TEMPORARY_REDIRECT
+        );
+        assert_eq!(response.headers()[header::LOCATION], "/");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn base_path_prefixes_routes_but_keeps_probes_at_root() -> Result<()> {
+        let (state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.base_path = "/myapp/".to_string();
+        })
+        .await?;
+        let router =
+            Server::router_with_base_path(state.core.repo.cfg.http.base_path()?).with_state(state);
+
+        for (uri, status) in [
+            ("/myapp/ping?content=hi", 200),
+            ("/ping?content=hi", 404),
+            ("/myapp/healthz", 200),
+            ("/healthz", 200),
+            ("/myapp/api/v1/user/check-availability", 200),
+            ("/api/v1/user/check-availability", 404),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty())?)
+                .await?;
+            assert_eq!(response.status().as_u16(), status, "{uri}");
+        }
+
+        let response = router
+            .oneshot(Request::get("/myapp").body(Body::empty())?)
+            .await?;
+        assert_eq!(
+            body_json(response).await?["data"]["links"]["ping"],
+            "/myapp/ping"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn instance_id_header_is_opt_in() -> Result<()> {
+        let (state, _tmp) = test_state(true).await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(Request::get("/ping?content=hi").body(Body::empty())?)
+            .await?;
+        assert!(!response.headers().contains_key("x-instance-id"));
+
+        let (state, _tmp) = test_state_with(true, |cfg| {
+            cfg.http.instance_id_header = Some("X-Instance-Id".to_string());
+        })
+        .await?;
+        let instance_id = state.core.instance_id.clone();
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(Request::get("/ping?content=hi").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.headers()["x-instance-id"], instance_id.as_str());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_id_flows_from_client_header_to_server_log() -> Result<()> {
+        let (_log_guard, logs) = capture_logs();
+
+        let (state, _tmp) = test_state(true).await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get("/ping?content=hi")
+                    .header(REQUEST_ID_HEADER, "cli-request-1")
+                    .body(Body::empty())?,
+            )
+            .await?;
+
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "cli-request-1");
+        assert_eq!(body_json(response).await?["data"], "hi");
+        assert!(logs.content().contains("request_id=\"cli-request-1\""));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_params_are_logged_redacted_on_failure_only() -> Result<()> {
+        let (_log_guard, logs) = capture_logs();
+
+        let (state, _tmp) = test_state(true).await?;
+        let response = Router::new()
+            .route(
+                "/ping",
+                wrap_get_handler(ping, ApiConfig::new("ping").with_params_on_error()),
+            )
+            .with_state(state.clone())
+            .oneshot(Request::get("/ping?content=hi").body(Body::empty())?)
+            .await?;
+        assert_eq!(body_json(response).await?["code"], 0);
+        assert!(!logs.content().contains("request_params"));
+
+        // db is disabled, so the login fails
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get(
+                    "/api/v1/user/login?auth_type=Username&auth_id=alice&auth_token=s3cret",
+                )
+                .body(Body::empty())?,
+            )
+            .await?;
+        assert_ne!(body_json(response).await?["code"], 0);
+
+        let content = logs.content();
+        assert!(content.contains("request_params"), "{content}");
+        assert!(content.contains("alice"), "{content}");
+        assert!(content.contains(REDACTED), "{content}");
+        assert!(!content.contains("s3cret"), "{content}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_logins_are_held_back_progressively() -> Result<()> {
+        let (mut state, _tmp) = test_state(false).await?;
+        state.login_backoff = Some(Arc::new(LoginBackoff::new(
+            &crate::kit::config::LoginBackoff {
+                enable: true,
+                base_delay: Duration::from_millis(50),
+                max_delay: Duration::from_secs(1),
+                reset_after: Duration::from_secs(60),
+            },
+        )));
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([
+                        Vec::<crate::core::model::user_auth::Model>::new(),
+                        Vec::new(),
+                    ])
+                    .into_connection(),
+            )
+            .await;
+        let router = Server::router().with_state(state);
+
+        let mut elapsed = vec![];
+        for _ in 0..2 {
+            let start = Instant::now();
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get(
+                        "/api/v1/user/login?auth_type=Username&auth_id=alice&auth_token=x",
+                    )
+                    .body(Body::empty())?,
+                )
+                .await?;
+            assert_eq!(
+                body_json(response).await?["code"],
+                Error::UserNotFound.code()
+            );
+            elapsed.push(start.elapsed());
+        }
+        assert!(elapsed[0] >= Duration::from_millis(50), "{elapsed:?}");
+        assert!(elapsed[1] >= Duration::from_millis(100), "{elapsed:?}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logins_are_rejected_while_held_back() -> Result<()> {
+        let (mut state, _tmp) = test_state(false).await?;
+        let login_backoff = Arc::new(LoginBackoff::new(&crate::kit::config::LoginBackoff {
+            enable: true,
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(60),
+            reset_after: Duration::from_secs(60),
+        }));
+        // a guess of the same client still waiting out its failure
+        login_backoff.failed("10.0.0.9");
+        state.login_backoff = Some(login_backoff);
+        let router = Server::router().with_state(state);
+
+        let mut request =
+            Request::get("/api/v1/user/login?auth_type=Username&auth_id=alice&auth_token=x")
+                .header("X-Forwarded-For", "198.51.100.1")
+                .body(Body::empty())?;
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new("10.0.0.9".parse()?, 40000)));
+        let response = router.oneshot(request).await?;
+        assert_eq!(response.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            body_json(response).await?["code"],
+            Error::TooManyRequests.code()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn access_log_records_route_template() -> Result<()> {
+        let (_log_guard, logs) = capture_logs();
+
+        let (state, _tmp) = test_state(true).await?;
+        let item_router =
+            Router::new().route("/{id}/info", wrap_get_handler(ping, ApiConfig::new("ping")));
+        let response = Router::new()
+            .nest("/api/v1/item", item_router)
+            .with_state(state)
+            .oneshot(Request::get("/api/v1/item/123/info").body(Body::empty())?)
+            .await?;
+        assert_eq!(body_json(response).await?["code"], 0);
+
+        let content = logs.content();
+        assert!(
+            content.contains("route=\"/api/v1/item/{id}/info\""),
+            "{content}"
+        );
+        assert!(
+            content.contains("uri=\"/api/v1/item/123/info\""),
+            "{content}"
+        );
+
+        Ok(())
+    }
+
+    async fn slow(_state: Arc<Core>, ctx: Context, _headers: HeaderMap, _req: ()) -> Result<()> {
+        assert!(ctx.deadline.is_some());
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handler_past_request_timeout_returns_request_timeout() -> Result<()> {
+        let (state, _tmp) = test_state_with(true, |cfg| {
+            cfg.http.request_timeout = Duration::from_millis(20);
+        })
+        .await?;
+        let response = Router::new()
+            .route("/slow", wrap_get_handler(slow, ApiConfig::new("slow")))
+            .with_state(state)
+            .oneshot(Request::get("/slow").body(Body::empty())?)
+            .await?;
+
+        // retrying clients only look at the status
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            body_json(response).await?["code"],
+            Error::RequestTimeout.code()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cache_control_is_set_on_success_and_no_store_on_error() -> Result<()> {
+        let router = || {
+            Router::new().route(
+                "/ping",
+                wrap_get_handler(
+                    ping,
+                    ApiConfig::new("ping").with_cache_control(Duration::from_secs(300)),
+                ),
+            )
+        };
+
+        let (state, _tmp) = test_state(true).await?;
+        let response = router()
+            .with_state(state)
+            .oneshot(Request::get("/ping").body(Body::empty())?)
+            .await?;
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=300"
+        );
+
+        let (state, _tmp) = test_state_with(true, |cfg| {
+            cfg.http.disabled_endpoints = vec!["ping".to_string()];
+        })
+        .await?;
+        let response = router()
+            .with_state(state)
+            .oneshot(Request::get("/ping").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert_eq!(
+            body_json(response).await?["code"],
+            Error::FeatureDisabled.code()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn post_rejects_deeply_nested_json() -> Result<()> {
+        let (state, _tmp) = test_state(true).await?;
+        let nested = format!("{{\"auth_id\":{}{}}}", "[".repeat(100), "]".repeat(100));
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::post("/api/v1/user/register")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(nested))?,
+            )
+            .await?;
+
+        let body = body_json(response).await?;
+        assert_eq!(
+            body["code"],
+            Error::InvidRequestParameter(String::new()).code()
+        );
+        assert!(
+            body["msg"].as_str().unwrap_or_default().contains("nested"),
+            "{body}"
+        );
+
+        Ok(())
+    }
+
+    #[derive(Deserialize)]
+    struct EchoReq {
+        name: String,
+    }
+
+    impl ValidateRequest for EchoReq {}
+
+    async fn echo(
+        _state: Arc<Core>,
+        _ctx: Context,
+        _headers: HeaderMap,
+        req: EchoReq,
+    ) -> Result<String> {
+        Ok(req.name)
+    }
+
+    async fn post_echo(cfg: ApiConfig, content_type: Option<&str>, body: &str) -> Result<Value> {
+        let (state, _tmp) = test_state(false).await?;
+        let mut request = Request::post("/echo");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let response = Router::new()
+            .route("/echo", wrap_post_handler(echo, cfg))
+            .with_state(state)
+            .oneshot(request.body(Body::from(body.to_string()))?)
+            .await?;
+        body_json(response).await
+    }
+
+    #[tokio::test]
+    async fn post_content_type_is_strict_unless_relaxed() -> Result<()> {
+        let invalid_param = Error::InvidRequestParameter(String::new()).code();
+        let body = r#"{"name":"alice"}"#;
+
+        let strict = || ApiConfig::new("echo");
+        let resp = post_echo(strict(), Some("application/json"), body).await?;
+        assert_eq!(resp["data"], "alice", "{resp}");
+        for content_type in [Some("text/plain"), None] {
+            let resp = post_echo(strict(), content_type, body).await?;
+            assert_eq!(resp["code"], invalid_param, "{content_type:?}: {resp}");
+        }
+
+        let relaxed = || ApiConfig::new("echo").accept_any_content_type();
+        for content_type in [Some("application/json"), Some("text/plain"), None] {
+            let resp = post_echo(relaxed(), content_type, body).await?;
+            assert_eq!(resp["data"], "alice", "{content_type:?}: {resp}");
+        }
+        // the body itself must still be json
+        let resp = post_echo(relaxed(), Some("text/plain"), "name=alice").await?;
+        assert_eq!(resp["code"], invalid_param, "{resp}");
+
+        Ok(())
+    }
+
+    #[derive(Deserialize)]
+    struct NoFilter {}
+
+    impl ValidateRequest for NoFilter {}
+
+    async fn page_limit(
+        _state: Arc<Core>,
+        _ctx: Context,
+        _headers: HeaderMap,
+        req: ListReq<NoFilter>,
+    ) -> Result<u64> {
+        Ok(req.page.limit)
+    }
+
+    #[tokio::test]
+    async fn list_page_limits_are_overridden_per_operation() -> Result<()> {
+        let (state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http
+                .page_limits
+                .insert("narrow_list".to_string(), PageLimits {
+                    default_limit: Some(5),
+                    max_limit: Some(10),
+                });
+        })
+        .await?;
+        let spec = || ListSpec::new().default_limit(20).max_limit(100);
+        let router = Router::new()
+            .route(
+                "/narrow",
+                wrap_list_handler(page_limit, ApiConfig::new("narrow_list"), spec()),
+            )
+            .route(
+                "/wide",
+                wrap_list_handler(page_limit, ApiConfig::new("wide_list"), spec()),
+            )
+            .with_state(state);
+
+        for (uri, limit) in [
+            ("/narrow", 5),
+            ("/narrow?limit=50", 10),
+            ("/wide", 20),
+            ("/wide?limit=50", 50),
+            ("/wide?limit=500", 100),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty())?)
+                .await?;
+            let body = body_json(response).await?;
+            assert_eq!(body["data"], limit, "{uri}: {body}");
+        }
+
+        Ok(())
+    }
+
+    /// Rule violations are answered per field before the handler (and the disabled db) is reached
+    #[tokio::test]
+    async fn register_rejects_empty_auth_id_and_long_nickname() -> Result<()> {
+        let (state, _tmp) = test_state(true).await?;
+        let body = serde_json::json!({
+            "auth_type": "Username",
+            "auth_id": "",
+            "auth_token": "s3cret",
+            "role": "User",
+            "nickname": "n".repeat(256),
+        });
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::post("/api/v1/user/register")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))?,
+            )
+            .await?;
+
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], Error::ValidationFailed(String::new()).code());
+        assert_eq!(
+            body["data"],
+            serde_json::json!({
+                "auth_id": ["must be 1 to 255 characters"],
+                "nickname": ["must be at most 255 characters"],
+            })
+        );
+        assert_eq!(
+            body["msg"],
+            "Validation failed: auth_id: must be 1 to 255 characters; nickname: must be at most \
+             255 characters"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn login_rejects_empty_auth_id() -> Result<()> {
+        let (state, _tmp) = test_state(true).await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get("/api/v1/user/login?auth_type=Username&auth_id=&auth_token=x")
+                    .body(Body::empty())?,
+            )
+            .await?;
+
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], Error::ValidationFailed(String::new()).code());
+        assert!(body["data"]["auth_id"].is_array(), "{body}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_rejects_sort_field_outside_allowlist() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![user_with("admin-1", Role::Admin)?]])
+                    .into_connection(),
+            )
+            .await;
+        let authorization = bearer_token(&state.core.repo.cfg, "admin-1")?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get("/api/v1/user/list?sort=password")
+                    .header(header::AUTHORIZATION, authorization)
+                    .body(Body::empty())?,
+            )
+            .await?;
+
+        let body = body_json(response).await?;
+        assert_eq!(
+            body["code"],
+            Error::InvidRequestParameter(String::new()).code()
+        );
+        assert!(
+            body["msg"]
+                .as_str()
+                .unwrap_or_default()
+                .contains("password"),
+            "{body}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_request_without_token_is_rejected_as_unauthenticated() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        let router = Server::router().with_state(state);
+
+        for request in [
+            Request::get("/api/v1/user/list?sort=password").body(Body::empty())?,
+            Request::post("/api/v1/user/user-2/impersonate")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{\"reason\": 1"))?,
+        ] {
+            let body = body_json(router.clone().oneshot(request).await?).await?;
+            assert_eq!(body["code"], Error::MissingToken.code(), "{body}");
+            assert!(body["data"].is_null(), "{body}");
+        }
+
+        Ok(())
+    }
+
+    /// List users as `admin-1` once per `(row tenant, claim tenant, tenant header)`, returns the
+    /// response bodies and the tenants the list queries were filtered by
+    async fn list_as_tenant_admin(
+        requests: &[(Option<&str>, Option<&str>, Option<&str>)],
+    ) -> Result<(Vec<Value>, Vec<String>)> {
+        let count_row = || BTreeMap::from([("num_items", sea_orm::Value::BigInt(Some(0)))]);
+        let (state, _tmp) = test_state(false).await?;
+        let mut db = MockDatabase::new(DatabaseBackend::Postgres);
+        for (row_tenant, _, _) in requests {
+            let mut admin = user_with("admin-1", Role::Admin)?;
+            admin.tenant_id = row_tenant.map(str::to_string);
+            db = db
+                .append_query_results([vec![admin]])
+                .append_query_results([vec![count_row()]])
+                .append_query_results([Vec::<user_model::Model>::new()]);
+        }
+        state.core.db.set_connection(db.into_connection()).await;
+
+        let mut bodies = Vec::new();
+        for (_, claim_tenant, tenant_header) in requests {
+            let (token, _) = jwt::generate_with_hmac_key(
+                &state.core.repo.cfg.http.jwt.token_hmac_key,
+                chrono::Duration::minutes(5),
+                chrono::Duration::zero(),
+                "admin-1",
+                AuthClaims {
+                    role: Some(Role::Admin),
+                    act: None,
+                    tenant_id: claim_tenant.map(str::to_string),
+                },
+            )?;
+            let mut request = Request::get("/api/v1/user/list")
+                .header(header::AUTHORIZATION, format!("Bearer {token}"));
+            if let Some(tenant_id) = tenant_header {
+                request = request.header(TENANT_ID_HEADER, *tenant_id);
+            }
+            let response = Server::router()
+                .with_state(state.clone())
+                .oneshot(request.body(Body::empty())?)
+                .await?;
+            bodies.push(body_json(response).await?);
+        }
+
+        let log = state.core.db.get_connection().await?.into_transaction_log();
+        let tenants = log
+            .iter()
+            .map(|transaction| transaction.statements()[0].to_string())
+            // the token user lookups
+            .filter(|statement| !statement.contains(r#""user"."id" = 'admin-1'"#))
+            .map(|statement| {
+                statement
+                    .split(r#""user"."tenant_id" = '"#)
+                    .nth(1)
+                    .and_then(|rest| rest.split('\'').next())
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect();
+        Ok((bodies, tenants))
+    }
+
+    #[tokio::test]
+    async fn tenant_flows_from_user_row_or_header_into_list_filter() -> Result<()> {
+        let (bodies, tenants) = list_as_tenant_admin(&[
+            (Some("acme"), Some("acme"), None),
+            (None, None, Some("globex")),
+            // a header can't move a tenant bound user to another tenant
+            (Some("acme"), Some("acme"), Some("globex")),
+        ])
+        .await?;
+
+        assert_eq!(bodies[0]["code"], 0, "{}", bodies[0]);
+        assert_eq!(bodies[1]["code"], 0, "{}", bodies[1]);
+        assert_eq!(bodies[2]["code"], Error::Forbidden.code(), "{}", bodies[2]);
+        // count and page of each allowed request
+        assert_eq!(tenants, ["acme", "acme", "globex", "globex"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tenant_of_the_user_row_wins_over_the_claim() -> Result<()> {
+        let (bodies, tenants) = list_as_tenant_admin(&[
+            // moved to acme after the token was issued
+            (Some("acme"), Some("globex"), None),
+            // issued before tenants were claimed
+            (Some("acme"), None, None),
+        ])
+        .await?;
+
+        for body in &bodies {
+            assert_eq!(body["code"], 0, "{body}");
+        }
+        assert_eq!(tenants, ["acme", "acme", "acme", "acme"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn probes_skip_auth_rate_limit_and_access_log() -> Result<()> {
+        let (_log_guard, logs) = capture_logs();
+
+        let (mut state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.disabled_endpoints = vec!["healthz".to_string()];
+        })
+        .await?;
+        let mut rate_limit = state.core.repo.cfg.http.rate_limit.clone();
+        rate_limit.enable = true;
+        rate_limit.anonymous = 1;
+        state.rate_limiter = Some(Arc::new(RateLimiter::new(&rate_limit)));
+        let router = Server::router().with_state(state);
+
+        for path in [
+            "/healthz", "/healthz", "/readyz", "/readyz", "/metrics", "/metrics",
+        ] {
+            let response = router
+                .clone()
+                .oneshot(Request::get(path).body(Body::empty())?)
+                .await?;
+            assert_eq!(response.status(), axum::http::StatusCode::OK);
+            assert_eq!(body_json(response).await?["code"], 0, "{path}");
+        }
+        assert!(
+            !logs.content().contains("api request"),
+            "{}",
+            logs.content()
+        );
+
+        // regular endpoints share the limit the probes didn't consume
+        for expected in [0, Error::TooManyRequests.code()] {
+            let response = router
+                .clone()
+                .oneshot(Request::get("/ping").body(Body::empty())?)
+                .await?;
+            assert_eq!(body_json(response).await?["code"], expected);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejected_tokens_count_against_the_client_ip() -> Result<()> {
+        let (mut state, _tmp) = test_state(false).await?;
+        let mut rate_limit = state.core.repo.cfg.http.rate_limit.clone();
+        rate_limit.enable = true;
+        rate_limit.anonymous = 2;
+        state.rate_limiter = Some(Arc::new(RateLimiter::new(&rate_limit)));
+        let router = Server::router().with_state(state);
+
+        let mut codes = Vec::new();
+        for _ in 0..3 {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get("/api/v1/user/refresh-token")
+                        .header(header::AUTHORIZATION, "Bearer not-a-token")
+                        .body(Body::empty())?,
+                )
+                .await?;
+            codes.push(body_json(response).await?["code"].clone());
+        }
+        assert_ne!(codes[1], Error::TooManyRequests.code());
+        assert_eq!(codes[2], Error::TooManyRequests.code());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authenticated_requests_skip_the_anonymous_limit() -> Result<()> {
+        let (mut state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results(vec![vec![user_with("alice", Role::User)?]; 3])
+                    .into_connection(),
+            )
+            .await;
+        let mut rate_limit = state.core.repo.cfg.http.rate_limit.clone();
+        rate_limit.enable = true;
+        rate_limit.anonymous = 1;
+        rate_limit.authenticated = 10;
+        state.rate_limiter = Some(Arc::new(RateLimiter::new(&rate_limit)));
+        let authorization = bearer_token(&state.core.repo.cfg, "alice")?;
+        let router = Router::new()
+            .route(
+                "/whoami",
+                wrap_get_handler(whoami, ApiConfig::new("whoami").with_auth()),
+            )
+            .route("/ping", wrap_get_handler(ping, ApiConfig::new("ping")))
+            .with_state(state);
+
+        for _ in 0..3 {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get("/whoami")
+                        .header(header::AUTHORIZATION, &authorization)
+                        .body(Body::empty())?,
+                )
+                .await?;
+            assert_eq!(response.status(), axum::http::StatusCode::OK);
+            let body = body_json(response).await?;
+            assert_eq!(body["data"], "alice", "{body}");
+        }
+
+        // the anonymous bucket of the same ip is untouched
+        for expected in [0, Error::TooManyRequests.code()] {
+            let response = router
+                .clone()
+                .oneshot(Request::get("/ping").body(Body::empty())?)
+                .await?;
+            assert_eq!(body_json(response).await?["code"], expected);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn strict_rate_limit_applies_with_the_limiter_disabled() -> Result<()> {
+        let (mut state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.rate_limit.strict = 2;
+        })
+        .await?;
+        assert!(!state.core.repo.cfg.http.rate_limit.enable);
+        state.rate_limiter = Some(Arc::new(RateLimiter::new(
+            &state.core.repo.cfg.http.rate_limit,
+        )));
+        let router = Server::router().with_state(state);
+
+        // a fresh forwarded address per request doesn't earn a fresh bucket
+        let mut codes = Vec::new();
+        for i in 0..3 {
+            let mut request =
+                Request::get("/api/v1/user/check-availability?auth_type=Username&auth_id=bob")
+                    .header("X-Forwarded-For", format!("198.51.100.{i}"))
+                    .body(Body::empty())?;
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new("10.0.0.9".parse()?, 40000)));
+            let response = router.clone().oneshot(request).await?;
+            codes.push(body_json(response).await?["code"].clone());
+        }
+        assert_ne!(codes[1], Error::TooManyRequests.code());
+        assert_eq!(codes[2], Error::TooManyRequests.code());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_id_is_generated_when_missing() -> Result<()> {
+        let (state, _tmp) = test_state(true).await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(Request::get("/ping").body(Body::empty())?)
+            .await?;
+
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str()?;
+        assert_eq!(request_id.len(), 32);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn internal_config_redacts_secrets_by_default() -> Result<()> {
+        let (state, _tmp) = test_state(true).await?;
+        let router = Server::router().with_state(state);
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/internal/config").body(Body::empty())?)
+            .await?;
+        let data = body_json(response).await?["data"].clone();
+        assert_eq!(data["db"]["password"], REDACTED);
+        assert_eq!(data["http"]["jwt"]["token_hmac_key"], REDACTED);
+        assert_eq!(data["db"]["username"], Config::default().db.username);
+
+        let response = router
+            .oneshot(Request::get("/internal/config?show_secrets=true").body(Body::empty())?)
+            .await?;
+        let data = body_json(response).await?["data"].clone();
+        assert_eq!(data["db"]["password"], Config::default().db.password);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn internal_config_is_ipc_only() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(Request::get("/internal/config").body(Body::empty())?)
+            .await?;
+        assert_eq!(
+            body_json(response).await?["code"],
+            Error::ApiMustRequestFromIPC.code()
+        );
+
+        Ok(())
+    }
+
+    fn ping_from(peer: &str, request_id: &str) -> Result<Request<Body>> {
+        let mut request = Request::get("/ping")
+            .header(REQUEST_ID_HEADER, request_id)
+            .body(Body::empty())?;
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(peer.parse()?, 40000)));
+        Ok(request)
+    }
+
+    #[tokio::test]
+    async fn request_id_from_untrusted_peer_is_replaced() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(ping_from("10.0.0.9", "client-chosen")?)
+            .await?;
+
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str()?;
+        assert_ne!(request_id, "client-chosen");
+        assert_eq!(request_id.len(), 32);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_id_from_trusted_proxy_is_honored() -> Result<()> {
+        let (state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.trusted_proxies = vec!["10.0.0.1".to_string()];
+        })
+        .await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(ping_from("10.0.0.1", "proxy-req:1")?)
+            .await?;
+
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "proxy-req:1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_id_validation_rejects_unsafe_values() {
+        assert!(is_valid_request_id("abc-123_x.y:z"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("has space"));
+        assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+
+    #[tokio::test]
+    async fn ipc_allow_list_rejects_other_operations() -> Result<()> {
+        let configure = |cfg: &mut Config| {
+            cfg.ipc.allowed_operations = vec!["user_check_availability".to_string()];
+        };
+        let (ipc_state, _ipc_tmp) = test_state_with(true, configure).await?;
+        let router = Server::router().with_state(ipc_state);
+
+        let rejected = router
+            .clone()
+            .oneshot(Request::get("/internal/config").body(Body::empty())?)
+            .await?;
+        assert_eq!(
+            body_json(rejected).await?["code"],
+            Error::ApiNotAllowedOverIPC.code()
+        );
+
+        let ping = router
+            .oneshot(Request::get("/ping?content=hi").body(Body::empty())?)
+            .await?;
+        assert_eq!(body_json(ping).await?["code"], 0);
+
+        // the allow-list only restricts the ipc socket
+        let (tcp_state, _tcp_tmp) = test_state_with(false, configure).await?;
+        let response = Server::router()
+            .with_state(tcp_state)
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        assert_eq!(body_json(response).await?["code"], 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_during_drain_get_service_unavailable() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        let router = Server::router().with_state(state.clone());
+
+        let sidecar = Sidecar::new();
+        sidecar.cancel().await?;
+        shutdown_signal(sidecar, state.shutting_down.clone(), "http").await;
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/ping?content=hi").body(Body::empty())?)
+            .await?;
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        let body = body_json(response).await?;
+        assert_eq!(
+            body["code"],
+            Error::ServiceUnavailable(String::new()).code()
+        );
+        assert!(
+            body["msg"]
+                .as_str()
+                .unwrap_or_default()
+                .contains("shutting down"),
+            "{body}"
+        );
+
+        // liveness keeps answering while in-flight requests drain
+        let response = router
+            .oneshot(Request::get("/healthz").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disabled_endpoint_returns_feature_disabled() -> Result<()> {
+        let (state, _tmp) = test_state_with(true, |cfg| {
+            cfg.http.disabled_endpoints = vec!["user_refresh_token".to_string()];
+        })
+        .await?;
+        let router = Server::router().with_state(state);
+
+        let disabled = router
+            .clone()
+            .oneshot(Request::get("/api/v1/user/refresh-token").body(Body::empty())?)
+            .await?;
+        assert_eq!(
+            body_json(disabled).await?["code"],
+            Error::FeatureDisabled.code()
+        );
+
+        let enabled = router
+            .oneshot(Request::get("/ping?content=hi").body(Body::empty())?)
+            .await?;
+        let enabled = body_json(enabled).await?;
+        assert_eq!(enabled["code"], 0);
+        assert_eq!(enabled["data"], "hi");
+
+        Ok(())
+    }
+
+    fn bearer_token(cfg: &Config, subject: &str) -> Result<String> {
+        let (token, _) = jwt::generate_with_hmac_key(
+            &cfg.http.jwt.token_hmac_key,
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            subject,
+            AuthClaims {
+                role: Some(Role::User),
+                act: None,
+                tenant_id: None,
+            },
+        )?;
+        Ok(format!("Bearer {token}"))
+    }
+
+    /// An active user as loaded for the token subject `id`
+    fn user_with(id: &str, role: Role) -> Result<user_model::Model> {
+        let mut user = user_model::ActiveModel::create().try_into_model()?;
+        user.id = id.to_string();
+        user.role = role;
+        Ok(user)
+    }
+
+    /// A struct rather than `()`, so the query may carry `access_token` like a real request
+    #[derive(Deserialize)]
+    struct WhoamiReq {}
+
+    impl ValidateRequest for WhoamiReq {}
+
+    async fn whoami(
+        _state: Arc<Core>,
+        ctx: Context,
+        _headers: HeaderMap,
+        _req: WhoamiReq,
+    ) -> Result<String> {
+        Ok(ctx.user_id)
+    }
+
+    #[tokio::test]
+    async fn query_token_is_accepted_only_on_flagged_routes_and_never_logged() -> Result<()> {
+        let (_log_guard, logs) = capture_logs();
+
+        let alice = user_with("alice", Role::User)?;
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![alice.clone()], vec![alice]])
+                    .into_connection(),
+            )
+            .await;
+        let authorization = bearer_token(&state.core.repo.cfg, "alice")?;
+        let token = authorization.trim_start_matches("Bearer ").to_string();
+        let router = Router::new()
+            .route(
+                "/download",
+                wrap_get_handler(
+                    whoami,
+                    ApiConfig::new("download")
+                        .with_auth()
+                        .with_query_token()
+                        .with_params_on_error(),
+                ),
+            )
+            .route(
+                "/normal",
+                wrap_get_handler(whoami, ApiConfig::new("normal").with_auth()),
+            )
+            .with_state(state);
+        let get = |uri: String| async {
+            let response = router
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty())?)
+                .await?;
+            body_json(response).await
+        };
+
+        let resp = get(format!("/download?access_token={token}")).await?;
+        assert_eq!(resp["data"], "alice", "{resp}");
+
+        let resp = get(format!("/normal?access_token={token}")).await?;
+        assert_eq!(resp["code"], Error::MissingToken.code(), "{resp}");
+
+        let resp = get("/download?access_token=not-a-jwt".to_string()).await?;
+        assert_eq!(resp["code"], Error::InvalidToken.code(), "{resp}");
+
+        // the header still wins when both are sent
+        let response = router
+            .clone()
+            .oneshot(
+                Request::get("/download?access_token=not-a-jwt")
+                    .header(header::AUTHORIZATION, &authorization)
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(body_json(response).await?["data"], "alice");
+
+        let content = logs.content();
+        assert!(content.contains("uri=\"/download\""), "{content}");
+        assert!(content.contains(REDACTED), "{content}");
+        assert!(!content.contains(&token), "{content}");
+        assert!(!content.contains("not-a-jwt"), "{content}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token_errors_tell_missing_malformed_and_expired_apart() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        let (expired, _) = jwt::generate_with_hmac_key(
+            &state.core.repo.cfg.http.jwt.token_hmac_key,
+            chrono::Duration::hours(-1),
+            chrono::Duration::zero(),
+            "alice",
+            AuthClaims::default(),
+        )?;
+        let (foreign, _) = jwt::generate_with_hmac_key(
+            "another-key",
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            "alice",
+            AuthClaims::default(),
+        )?;
+        let router = Router::new()
+            .route(
+                "/me",
+                wrap_get_handler(whoami, ApiConfig::new("me").with_auth()),
+            )
+            .with_state(state);
+
+        for (authorization, expected) in [
+            (None, Error::MissingToken),
+            (Some("Bearer".to_string()), Error::MissingToken),
+            (Some("Bearer not-a-jwt".to_string()), Error::InvalidToken),
+            (Some(format!("Basic {foreign}")), Error::InvalidToken),
+            (Some(format!("Bearer {foreign}")), Error::InvalidToken),
+            (Some(format!("Bearer {expired}")), Error::ExpiredToken),
+        ] {
+            let mut request = Request::get("/me");
+            if let Some(authorization) = &authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            let response = router.clone().oneshot(request.body(Body::empty())?).await?;
+            let resp = body_json(response).await?;
+            assert_eq!(resp["code"], expected.code(), "{authorization:?}: {resp}");
+        }
+
+        Ok(())
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct AuditAction(&'static str);
+
+    async fn audited(
+        _state: Arc<Core>,
+        ctx: Context,
+        _headers: HeaderMap,
+        _req: WhoamiReq,
+    ) -> Result<String> {
+        ctx.insert(AuditAction("purge"));
+        Err(Error::Forbidden.into())
+    }
+
+    #[tokio::test]
+    async fn handler_extensions_reach_outer_layers() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        let seen = Arc::new(Mutex::new(None));
+        let response = Router::new()
+            .route("/purge", wrap_get_handler(audited, ApiConfig::new("purge")))
+            .layer(axum::middleware::map_response({
+                let seen = seen.clone();
+                move |response: AxumResponse| {
+                    let action = response
+                        .extensions()
+                        .get::<Extensions>()
+                        .and_then(Extensions::get::<AuditAction>);
+                    *seen.lock().unwrap() = action;
+                    async move { response }
+                }
+            }))
+            .with_state(state)
+            .oneshot(Request::get("/purge").body(Body::empty())?)
+            .await?;
+
+        assert_eq!(body_json(response).await?["code"], Error::Forbidden.code());
+        assert_eq!(*seen.lock().unwrap(), Some(AuditAction("purge")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_lacking_entitlement_is_rejected() -> Result<()> {
+        let tmp = tempdir()?;
+        std::fs::write(
+            tmp.path().join("config.toml"),
+            "[auth.entitlements]\nAdmin = [\"user_export\"]\nManager = [\"user_bulk_delete\"]\n",
+        )?;
+        let repo = Repo::<Config>::new(tmp.path(), "server-test").await?;
+        assert_eq!(repo.cfg.auth.entitlements[&Role::Manager], [
+            "user_bulk_delete"
+        ]);
+        let state = AppState {
+            core: Core::new(Sidecar::new(), repo).await?,
+            is_ipc: false,
+            rate_limiter: None,
+            login_backoff: None,
+            scheduler: None,
+            shutting_down: Arc::default(),
+        };
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![user_with("admin", Role::Admin)?]])
+                    .into_connection(),
+            )
+            .await;
+        let (admin_token, _) = jwt::generate_with_hmac_key(
+            &state.core.repo.cfg.http.jwt.token_hmac_key,
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            "admin",
+            AuthClaims {
+                role: Some(Role::Admin),
+                act: None,
+                tenant_id: None,
+            },
+        )?;
+
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::post("/api/v1/user/bulk-delete")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::AUTHORIZATION, format!("Bearer {admin_token}"))
+                    .body(Body::from("{}"))?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(
+            body["code"],
+            Error::FeatureNotEntitled(String::new()).code(),
+            "{body}"
+        );
+
+        Ok(())
+    }
+
+    async fn refresh_token_as(state: AppState, subject: &str) -> Result<Value> {
+        let authorization = bearer_token(&state.core.repo.cfg, subject)?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get("/api/v1/user/refresh-token")
+                    .header(header::AUTHORIZATION, authorization)
+                    .body(Body::empty())?,
+            )
+            .await?;
+        body_json(response).await
+    }
+
+    #[tokio::test]
+    async fn subject_source_user_id_uses_sub_as_user_id() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![user_with("user-1", Role::User)?]])
+                    .into_connection(),
+            )
+            .await;
+
+        let body = refresh_token_as(state, "user-1").await?;
+        assert_eq!(body["code"], 0, "{body}");
+        assert_eq!(body["data"]["user_id"], "user-1");
+        let token = body["data"]["jwt_token"].as_str().unwrap_or_default();
+        assert_eq!(jwt::decode_unverified::<Value>(token)?.sub, "user-1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn role_comes_from_the_user_row_not_the_token() -> Result<()> {
+        let (state, _tmp) = test_state(false).await?;
+        // demoted after the admin token was issued
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![user_with("admin-1", Role::User)?]])
+                    .into_connection(),
+            )
+            .await;
+        let (admin_token, _) = jwt::generate_with_hmac_key(
+            &state.core.repo.cfg.http.jwt.token_hmac_key,
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            "admin-1",
+            AuthClaims {
+                role: Some(Role::Admin),
+                act: None,
+                tenant_id: None,
+            },
+        )?;
+
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get("/api/v1/user/list")
+                    .header(header::AUTHORIZATION, format!("Bearer {admin_token}"))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], Error::Forbidden.code(), "{body}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refreshed_token_carries_the_current_role_and_tenant() -> Result<()> {
+        let mut user = user_with("user-1", Role::User)?;
+        user.tenant_id = Some("acme".to_string());
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![user]])
+                    .into_connection(),
+            )
+            .await;
+        // issued while the user was a tenantless admin
+        let (token, _) = jwt::generate_with_hmac_key(
+            &state.core.repo.cfg.http.jwt.token_hmac_key,
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            "user-1",
+            AuthClaims {
+                role: Some(Role::Admin),
+                act: None,
+                tenant_id: None,
+            },
+        )?;
+
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get("/api/v1/user/refresh-token")
+                    .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], 0, "{body}");
+        let token = body["data"]["jwt_token"].as_str().unwrap_or_default();
+        let claims = jwt::decode_unverified::<AuthClaims>(token)?;
+        assert_eq!(claims.data.role, Some(Role::User));
+        assert_eq!(claims.data.tenant_id.as_deref(), Some("acme"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subject_source_public_id_maps_sub_to_user_id() -> Result<()> {
+        let user = user_model::ActiveModel::create().try_into_model()?;
+        let (state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.jwt.subject_source = SubjectSource::PublicId;
+        })
+        .await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![user.clone()]])
+                    .append_query_results([Vec::<user_model::Model>::new()])
+                    .into_connection(),
+            )
+            .await;
+
+        let body = refresh_token_as(state.clone(), &user.public_id).await?;
+        assert_eq!(body["code"], 0, "{body}");
+        assert_eq!(body["data"]["user_id"], user.id);
+        let token = body["data"]["jwt_token"].as_str().unwrap_or_default();
+        assert_eq!(jwt::decode_unverified::<Value>(token)?.sub, user.public_id);
+
+        // an internal id is not a valid subject
+        let body = refresh_token_as(state, &user.id).await?;
+        assert_eq!(body["code"], Error::Unauthorized.code());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn impersonate_issues_act_token_and_audits() -> Result<()> {
+        let (_log_guard, logs) = capture_logs();
+
+        let target = user_model::ActiveModel::create().try_into_model()?;
+        let mut admin = user_model::ActiveModel::create().try_into_model()?;
+        admin.role = Role::Admin;
+        let demoted = user_model::Model {
+            role: Role::User,
+            ..admin.clone()
+        };
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([
+                        vec![admin.clone()],
+                        vec![target.clone()],
+                        vec![target.clone()],
+                        vec![admin.clone()],
+                        vec![target.clone()],
+                        vec![admin.clone()],
+                        vec![target.clone()],
+                        vec![demoted.clone()],
+                    ])
+                    .into_connection(),
+            )
+            .await;
+        let (admin_token, _) = jwt::generate_with_hmac_key(
+            &state.core.repo.cfg.http.jwt.token_hmac_key,
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            &admin.id,
+            AuthClaims {
+                role: Some(Role::Admin),
+                act: None,
+                tenant_id: None,
+            },
+        )?;
+
+        let response = Server::router()
+            .with_state(state.clone())
+            .oneshot(
+                Request::post(format!("/api/v1/user/{}/impersonate", target.id))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::AUTHORIZATION, format!("Bearer {admin_token}"))
+                    .body(Body::from(r#"{"reason":"ticket 1234"}"#))?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], 0, "{body}");
+        assert_eq!(body["data"]["user_id"], target.id);
+
+        let token = body["data"]["jwt_token"].as_str().unwrap_or_default();
+        let claims = jwt::decode_unverified::<AuthClaims>(token)?;
+        assert_eq!(claims.sub, target.id);
+        assert_eq!(claims.data.role, Some(target.role.clone()));
+        assert_eq!(claims.data.act.map(|act| act.sub), Some(admin.id.clone()));
+
+        let content = logs.content();
+        let audit = content
+            .lines()
+            .find(|line| line.contains("audit:"))
+            .unwrap_or_default();
+        assert!(audit.contains("admin impersonates user"), "{content}");
+        assert!(audit.contains(&admin.id), "{audit}");
+        assert!(audit.contains(&target.id), "{audit}");
+        assert!(audit.contains("ticket 1234"), "{audit}");
+
+        // the token acts as the user, with the admin as actor, and can't be extended
+        let response = Server::router()
+            .with_state(state.clone())
+            .oneshot(
+                Request::get("/api/v1/user/refresh-token")
+                    .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(body_json(response).await?["code"], Error::Forbidden.code());
+        let content = logs.content();
+        assert!(
+            content.contains(&format!(r#""actor": "{}""#, admin.id)),
+            "{content}"
+        );
+
+        // the token is only good while the actor is still an admin
+        let router = Router::new()
+            .route(
+                "/whoami",
+                wrap_get_handler(whoami, ApiConfig::new("whoami").with_auth()),
+            )
+            .with_state(state);
+        for expected in [0, Error::Forbidden.code()] {
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::get("/whoami")
+                        .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                        .body(Body::empty())?,
+                )
+                .await?;
+            let body = body_json(response).await?;
+            assert_eq!(body["code"], expected, "{body}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn impersonate_rejects_a_stale_admin_token() -> Result<()> {
+        // demoted after the admin token was issued
+        let demoted = user_with("admin-1", Role::User)?;
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![demoted]])
+                    .into_connection(),
+            )
+            .await;
+        let (admin_token, _) = jwt::generate_with_hmac_key(
+            &state.core.repo.cfg.http.jwt.token_hmac_key,
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            "admin-1",
+            AuthClaims {
+                role: Some(Role::Admin),
+                act: None,
+                tenant_id: None,
+            },
+        )?;
+
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::post("/api/v1/user/user-2/impersonate")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::AUTHORIZATION, format!("Bearer {admin_token}"))
+                    .body(Body::from("{}"))?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], Error::Forbidden.code(), "{body}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn impersonate_rejects_users_of_other_tenants() -> Result<()> {
+        let mut admin = user_with("admin-1", Role::Admin)?;
+        admin.tenant_id = Some("acme".to_string());
+        let mut target = user_with("user-2", Role::User)?;
+        target.tenant_id = Some("globex".to_string());
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![admin], vec![target]])
+                    .into_connection(),
+            )
+            .await;
+        let (admin_token, _) = jwt::generate_with_hmac_key(
+            &state.core.repo.cfg.http.jwt.token_hmac_key,
+            chrono::Duration::minutes(5),
+            chrono::Duration::zero(),
+            "admin-1",
+            AuthClaims {
+                role: Some(Role::Admin),
+                act: None,
+                tenant_id: Some("acme".to_string()),
+            },
+        )?;
+
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::post("/api/v1/user/user-2/impersonate")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::AUTHORIZATION, format!("Bearer {admin_token}"))
+                    .body(Body::from("{}"))?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], Error::Forbidden.code(), "{body}");
+        assert!(
+            body["msg"]
+                .as_str()
+                .is_some_and(|msg| msg.contains("outside of request tenant acme")),
+            "{body}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn root_info_lists_links_unless_disabled() -> Result<()> {
+        let (state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.enable = true;
+        })
+        .await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["data"]["app_name"], version::current().app_name);
+        assert_eq!(body["data"]["links"]["ping"], "/ping");
+        assert_eq!(body["data"]["links"]["swagger_ui"], "/swagger-ui");
+
+        // the ipc router is never nested under base_path
+        for (is_ipc, ping) in [(false, "/api/ping"), (true, "/ping")] {
+            let (state, _tmp) = test_state_with(is_ipc, |cfg| {
+                cfg.http.base_path = "/api".to_string();
+            })
+            .await?;
+            let response = Server::router()
+                .with_state(state)
+                .oneshot(Request::get("/").body(Body::empty())?)
+                .await?;
+            let body = body_json(response).await?;
+            assert_eq!(body["data"]["links"]["ping"], ping, "{body}");
+        }
+
+        let (state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.root_info = false;
+        })
+        .await?;
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn config_kv_overrides_reach_the_handlers() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<Config>::new(tmp.path(), "server-test").await?;
+        repo.cfg.db.auto_create_tables = false;
+        assert!(repo.cfg.http.root_info);
+
+        let sidecar = Sidecar::new();
+        let db = DB::new(sidecar.clone(), repo.clone()).await?;
+        db.set_connection(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([vec![config_kv_model::Model {
+                    key: "http.root_info".to_string(),
+                    value: "false".to_string(),
+                    update_time: chrono::Local::now().into(),
+                }]])
+                .into_connection(),
+        )
+        .await;
+        config_kv::apply(sidecar, &mut repo, db).await?;
+
+        let core = Core::new(Sidecar::new(), repo).await?;
+        let state = AppState {
+            core,
+            is_ipc: false,
+            rate_limiter: None,
+            login_backoff: None,
+            scheduler: None,
+            shutting_down: Arc::default(),
+        };
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_user_can_neither_log_in_nor_authenticate() -> Result<()> {
+        // an identity left active by a soft delete of an earlier version
+        let mut auth = crate::core::model::user_auth::ActiveModel::create();
+        auth.user_id = sea_orm::Set("alice".to_string());
+        auth.auth_token = sea_orm::Set(crate::core::service::user::hash_password("s3cret")?);
+        let (state, _tmp) = test_state(false).await?;
+        state
+            .core
+            .db
+            .set_connection(
+                MockDatabase::new(DatabaseBackend::Postgres)
+                    .append_query_results([vec![auth.try_into_model()?]])
+                    .append_query_results(vec![Vec::<user_model::Model>::new(); 2])
+                    .into_connection(),
+            )
+            .await;
+        let authorization = bearer_token(&state.core.repo.cfg, "alice")?;
+        let router = Router::new()
+            .route(
+                "/whoami",
+                wrap_get_handler(whoami, ApiConfig::new("whoami").with_auth()),
+            )
+            .with_state(state.clone());
+
+        let response = Server::router()
+            .with_state(state)
+            .oneshot(
+                Request::get(
+                    "/api/v1/user/login?auth_type=Username&auth_id=alice&auth_token=s3cret",
+                )
+                .body(Body::empty())?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], Error::UserNotFound.code(), "{body}");
+
+        let response = router
+            .oneshot(
+                Request::get("/whoami")
+                    .header(header::AUTHORIZATION, &authorization)
+                    .body(Body::empty())?,
+            )
+            .await?;
+        let body = body_json(response).await?;
+        assert_eq!(body["code"], Error::Unauthorized.code(), "{body}");
+
+        Ok(())
+    }
+
+    async fn raw_http(addr: SocketAddr, request: String) -> Result<String> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    async fn raw_ipc(path: &Path, request: String) -> Result<String> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = UnixStream::connect(path).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    fn ping_request(extra_header: &str) -> String {
+        format!("GET /ping HTTP/1.1\r\nHost: test\r\n{extra_header}\r\n")
+    }
+
+    async fn spawn_tcp_server(
+        options: TcpServeOptions,
+    ) -> Result<(SocketAddr, oneshot::Sender<()>, TempDir)> {
+        let (state, tmp) = test_state(false).await?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        tokio::spawn(serve_tcp(
+            listener,
+            Server::router().with_state(state),
+            options,
+            async move {
+                _ = shutdown_rx.await;
+            },
+        ));
+        Ok((addr, shutdown_tx, tmp))
+    }
+
+    #[tokio::test]
+    async fn serve_tcp_rejects_oversized_headers() -> Result<()> {
+        let (addr, _shutdown, _tmp) = spawn_tcp_server(TcpServeOptions {
+            max_header_bytes: 8 * 1024,
+            max_header_count: 8,
+            ..TcpServeOptions::from(&Config::default().http)
+        })
+        .await?;
+
+        let ok = raw_http(addr, ping_request("Connection: close\r\n")).await?;
+        assert!(ok.starts_with("HTTP/1.1 200"), "{ok}");
+
+        let big_header = format!("X-Big: {}\r\nConnection: close\r\n", "a".repeat(16 * 1024));
+        let too_large = raw_http(addr, ping_request(&big_header)).await?;
+        assert!(too_large.starts_with("HTTP/1.1 431"), "{too_large}");
+
+        let many_headers = (0..16)
+            .map(|i| format!("X-H{i}: v\r\n"))
+            .collect::<String>();
+        let too_many = raw_http(addr, ping_request(&many_headers)).await?;
+        assert!(too_many.starts_with("HTTP/1.1 431"), "{too_many}");
+
+        Ok(())
+    }
+
+    /// With keep-alive the connection serves pipelined requests, without it the server answers
+    /// `connection: close` and hangs up after the first response
+    #[tokio::test]
+    async fn serve_tcp_applies_keep_alive_and_nodelay() -> Result<()> {
+        let options = TcpServeOptions {
+            tcp_nodelay: true,
+            ..TcpServeOptions::from(&Config::default().http)
+        };
+
+        let (addr, _shutdown, _tmp) = spawn_tcp_server(TcpServeOptions {
+            keep_alive: true,
+            ..options
+        })
+        .await?;
+        let pipelined = ping_request("") + &ping_request("Connection: close\r\n");
+        let response = raw_http(addr, pipelined).await?;
+        assert_eq!(response.matches("HTTP/1.1 200").count(), 2, "{response}");
+
+        let (addr, _shutdown, _tmp) = spawn_tcp_server(TcpServeOptions {
+            keep_alive: false,
+            ..options
+        })
+        .await?;
+        let response = raw_http(addr, ping_request("")).await?;
+        assert_eq!(response.matches("HTTP/1.1 200").count(), 1, "{response}");
+        assert!(response.contains("connection: close"), "{response}");
+
+        Ok(())
+    }
+
+    fn client_ip_router() -> Router<AppState> {
+        Router::new().route(
+            "/ip",
+            get(|ClientIp(ip): ClientIp| async move { ip.to_string() }),
+        )
+    }
+
+    #[tokio::test]
+    async fn forwarded_client_ip_is_honored_from_trusted_proxies_only() -> Result<()> {
+        let (state, _tmp) = test_state_with(false, |cfg| {
+            cfg.http.trusted_proxies = vec!["10.0.0.1".to_string()];
+        })
+        .await?;
+        let router = client_ip_router().with_state(state);
+
+        for (peer, client_ip) in [("10.0.0.1", "198.51.100.1"), ("10.0.0.9", "10.0.0.9")] {
+            let mut request = Request::get("/ip")
+                .header("X-Forwarded-For", "198.51.100.1")
+                .body(Body::empty())?;
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(peer.parse()?, 40000)));
+            let response = router.clone().oneshot(request).await?;
+            let body = to_bytes(response.into_body(), usize::MAX).await?;
+            assert_eq!(body, client_ip, "{peer}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serve_tcp_takes_client_ip_from_proxy_header() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (_shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let (state, _tmp) = test_state_with(false, |cfg| cfg.http.proxy_protocol = true).await?;
+        let router = client_ip_router().with_state(state);
+        tokio::spawn(serve_tcp(
+            listener,
+            router,
+            TcpServeOptions {
+                proxy_protocol: true,
+                ..TcpServeOptions::from(&Config::default().http)
+            },
+            async move {
+                _ = shutdown_rx.await;
+            },
+        ));
+        // a client behind the balancer can't pick its own address
+        let request = "GET /ip HTTP/1.1\r\nHost: test\r\nX-Forwarded-For: 198.51.100.1\r\n\
+                       Connection: close\r\n\r\n";
+
+        let response = raw_http(
+            addr,
+            format!("PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\n{request}"),
+        )
+        .await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("203.0.113.7"), "{response}");
+
+        // the balancer's own connections keep the peer address
+        let response = raw_http(addr, format!("PROXY UNKNOWN\r\n{request}")).await?;
+        assert!(response.ends_with("127.0.0.1"), "{response}");
+
+        // bypassing the balancer is dropped without a response, possibly reset as the request
+        // is left unread
+        let response = raw_http(addr, request.to_string())
+            .await
+            .unwrap_or_default();
+        assert!(response.is_empty(), "{response}");
+
+        Ok(())
+    }
+
+    /// Cancelling the app stops both listeners, new connections are refused afterwards
+    #[tokio::test]
+    async fn start_cancel_shuts_down_ipc_and_http_listeners() -> Result<()> {
+        let (sidecar, server, _tmp) = start_server_on_ephemeral_port().await?;
+
+        let ipc_path = server.repo.ipc_file_path();
+        let http_addr = SocketAddr::from((
+            Ipv4Addr::LOCALHOST,
+            server.http_local_addr().unwrap().port(),
+        ));
+        UnixStream::connect(&ipc_path).await?;
+        tokio::net::TcpStream::connect(http_addr).await?;
+
+        sidecar.cancel().await?;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while UnixStream::connect(&ipc_path).await.is_ok()
+                || tokio::net::TcpStream::connect(http_addr).await.is_ok()
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .wrap_err("listeners still accept connections after cancel")?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ipc_socket_deleted_mid_run_is_rebound() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<Config>::new(tmp.path(), "server-test").await?;
+        repo.cfg.ipc.rebind_check_interval = Duration::from_millis(20);
+        let sidecar = Sidecar::new();
+        let core = Core::new(sidecar.clone(), repo.clone()).await?;
+        let server = Server::new(sidecar.clone(), repo, core).await?;
+        server.start().await?;
+
+        let ipc_path = server.repo.ipc_file_path();
+        let ping = raw_ipc(&ipc_path, ping_request("Connection: close\r\n")).await?;
+        assert!(ping.starts_with("HTTP/1.1 200"), "{ping}");
+
+        std::fs::remove_file(&ipc_path)?;
+        assert!(UnixStream::connect(&ipc_path).await.is_err());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !ipc_path.exists() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .wrap_err("ipc file was not rebound")?;
+        let ping = raw_ipc(&ipc_path, ping_request("Connection: close\r\n")).await?;
+        assert!(ping.starts_with("HTTP/1.1 200"), "{ping}");
+
+        sidecar.cancel().await?;
+        Ok(())
+    }
+
+    async fn start_server_on_ephemeral_port() -> Result<(Sidecar, Arc<Server>, TempDir)> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<Config>::new(tmp.path(), "server-test").await?;
+        repo.cfg.http.enable = true;
+        repo.cfg.http.port = 0;
+        let sidecar = Sidecar::new();
+        let core = Core::new(sidecar.clone(), repo.clone()).await?;
+        let server = Server::new(sidecar.clone(), repo, core).await?;
+        server.start().await?;
+        Ok((sidecar, server, tmp))
+    }
+
+    #[tokio::test]
+    async fn start_exposes_bound_http_addr() -> Result<()> {
+        let (sidecar, server, _tmp) = start_server_on_ephemeral_port().await?;
+
+        let port = server.http_local_addr().unwrap().port();
+        assert_ne!(port, 0);
+        let ping = raw_http(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            ping_request("Connection: close\r\n"),
+        )
+        .await?;
+        assert!(ping.starts_with("HTTP/1.1 200"), "{ping}");
+
+        sidecar.cancel().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn secure_ipc_socket_applies_configured_mode() -> Result<()> {
+        let tmp = tempdir()?;
+        let path = tmp.path().join("ipc.sock");
+        let _listener = UnixListener::bind(&path)?;
+
+        let mut ipc_cfg = Config::default().ipc;
+        ipc_cfg.socket_mode = "640".to_string();
+        secure_ipc_socket(&path, &ipc_cfg)?;
+        assert_eq!(
+            std::fs::metadata(&path)?.permissions().mode() & 0o777,
+            0o640
+        );
+
+        ipc_cfg.socket_mode = "rw".to_string();
+        assert!(secure_ipc_socket(&path, &ipc_cfg).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn jwt_self_test_rejects_broken_config() {
+        let mut jwt_cfg = Config::default().http.jwt;
+        assert!(jwt_self_test(&jwt_cfg).is_ok());
+
+        jwt_cfg.token_hmac_key = String::new();
+        assert!(jwt_self_test(&jwt_cfg).is_err());
+
+        let mut jwt_cfg = Config::default().http.jwt;
+        jwt_cfg.token_valid_duration = Duration::ZERO;
+        assert!(jwt_self_test(&jwt_cfg).is_err());
+    }
+
+    #[test]
+    fn restore_error_returns_known_variant() {
+        let report: Report = Error::ApiMustRequestFromIPC.into();
+        let restored = restore_error_from_report(&report);
+        assert!(matches!(restored, Error::ApiMustRequestFromIPC));
+    }
+
+    #[test]
+    fn restore_error_falls_back_to_unknown() {
+        let report: Report = io::Error::other("boom").into();
+        let restored = restore_error_from_report(&report);
+        match restored {
+            Error::Unknown(msg) => assert!(msg.contains("boom")),
+            other => panic!("expected unknown fallback, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn restore_error_handles_nested_reports() {
+        let report: Report = Error::ApiMustRequestFromIPC.into();
+        let report = report.wrap_err("layer one").wrap_err("layer two");
+        let restored = restore_error_from_report(&report);
+        assert!(matches!(restored, Error::ApiMustRequestFromIPC));
+    }
+
+    #[test]
+    fn restore_error_classifies_db_errors() {
+        let report: Report = DbErr::RecordNotFound("user".to_string()).into();
+        let report = report.wrap_err("load user failed");
+        let restored = restore_error_from_report(&report);
+        assert!(matches!(restored, Error::DB(_)));
     }
 
     #[test]
@@ -0,0 +1,439 @@
+//! Dev only check that json request bodies and success responses match the schemas of
+//! [`base_openapi_doc`], enabled by `http.validate_against_spec`. Catches handlers whose actual
+//! serialization drifted from the documented schema. Divergences are logged and counted in the
+//! [`SPEC_DIVERGENCE_HEADER`] of the response, the request itself is served unchanged.
+//!
+//! Only the json schema subset utoipa generates is understood: `$ref`, `type`, `properties`,
+//! `required`, `additionalProperties`, `items`, `enum`, `nullable` and the `oneOf`/`anyOf`/`allOf`
+//! combinators. Request bodies are buffered up to the `DefaultBodyLimit` the handler would apply
+//! anyway, responses only when they're known to fit [`MAX_RESPONSE_BODY`]; still meant for dev
+
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::{Body, Bytes, HttpBody, to_bytes};
+use axum::extract::{FromRequest, MatchedPath, Request, State};
+use axum::http::{HeaderValue, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response as AxumResponse};
+use serde_json::Value;
+use sidecar::prelude::*;
+use tracing::warn;
+
+#[cfg(doc)]
+use crate::api::http::server::base_openapi_doc;
+
+/// Number of divergences found in the request and response, unset when there are none
+pub const SPEC_DIVERGENCE_HEADER: &str = "X-Spec-Divergence";
+
+/// Largest response body that is buffered to be checked, bigger or streamed ones pass unchecked
+pub const MAX_RESPONSE_BODY: u64 = 8 * 1024 * 1024;
+
+pub struct SpecValidator {
+    /// The spec as json, `$ref`s are resolved against its `components`
+    spec: Value,
+    /// Prefix of the matched routes that the spec paths don't carry, see `http.base_path`
+    base_path: String,
+}
+
+impl SpecValidator {
+    pub fn new(doc: &utoipa::openapi::OpenApi, base_path: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            spec: serde_json::to_value(doc)?,
+            base_path: base_path.unwrap_or_default().to_string(),
+        })
+    }
+
+    /// Validate the routes of `router` when `validator` is set. Routes added afterwards, e.g.
+    /// swagger ui, are left alone
+    pub fn layer<S>(router: Router<S>, validator: Option<Arc<SpecValidator>>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        match validator {
+            Some(validator) => {
+                router.route_layer(middleware::from_fn_with_state(validator, validate))
+            }
+            None => router,
+        }
+    }
+
+    /// Divergences of a request body from the `requestBody` schema of the operation
+    pub fn check_request(&self, method: &str, route: &str, body: &Value) -> Vec<String> {
+        let schema = self.operation(method, route).and_then(|operation| {
+            operation.pointer("/requestBody/content/application~1json/schema")
+        });
+        self.check(schema, body)
+    }
+
+    /// Divergences of a success envelope from the `200` response schema of the operation. Error
+    /// envelopes share one shape across operations and aren't documented per operation
+    pub fn check_response(&self, method: &str, route: &str, body: &Value) -> Vec<String> {
+        if body.get("code").and_then(Value::as_u64) != Some(0) {
+            return Vec::new();
+        }
+        let schema = self.operation(method, route).and_then(|operation| {
+            operation.pointer("/responses/200/content/application~1json/schema")
+        });
+        self.check(schema, body)
+    }
+
+    fn operation(&self, method: &str, route: &str) -> Option<&Value> {
+        let route = route.strip_prefix(&self.base_path).unwrap_or(route);
+        self.spec.get("paths")?.get(route)?.get(method)
+    }
+
+    fn check(&self, schema: Option<&Value>, value: &Value) -> Vec<String> {
+        let mut divergences = Vec::new();
+        if let Some(schema) = schema {
+            self.validate(schema, value, "$", &mut divergences);
+        }
+        divergences
+    }
+
+    fn resolve<'a>(&'a self, schema: &'a Value) -> &'a Value {
+        match schema.get("$ref").and_then(Value::as_str) {
+            Some(reference) => reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.spec.pointer(pointer))
+                .map_or(schema, |resolved| self.resolve(resolved)),
+            None => schema,
+        }
+    }
+
+    fn validate(&self, schema: &Value, value: &Value, path: &str, divergences: &mut Vec<String>) {
+        let schema = self.resolve(schema);
+        if value.is_null() && schema.get("nullable").and_then(Value::as_bool) == Some(true) {
+            return;
+        }
+
+        for combinator in ["oneOf", "anyOf"] {
+            if let Some(alternatives) = schema.get(combinator).and_then(Value::as_array) {
+                let matches_any = alternatives
+                    .iter()
+                    .any(|alternative| self.check(Some(alternative), value).is_empty());
+                if !matches_any {
+                    divergences.push(format!("{path}: matches none of {combinator}"));
+                }
+                return;
+            }
+        }
+        if let Some(parts) = schema.get("allOf").and_then(Value::as_array) {
+            for part in parts {
+                self.validate(part, value, path, divergences);
+            }
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array)
+            && !allowed.contains(value)
+        {
+            divergences.push(format!("{path}: {value} is not one of {allowed:?}"));
+            return;
+        }
+
+        if let Some(types) = schema.get("type") {
+            let types = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                other => other.as_str().into_iter().collect::<Vec<_>>(),
+            };
+            if !types.iter().any(|ty| is_type(value, ty)) {
+                divergences.push(format!(
+                    "{path}: expected {}, got {}",
+                    types.join(" or "),
+                    type_name(value)
+                ));
+                return;
+            }
+        }
+
+        match value {
+            Value::Object(fields) => {
+                let properties = schema.get("properties").and_then(Value::as_object);
+                for required in schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                {
+                    if !fields.contains_key(required) {
+                        divergences.push(format!("{path}.{required}: missing"));
+                    }
+                }
+                let additional = schema.get("additionalProperties");
+                for (key, field) in fields {
+                    let field_path = format!("{path}.{key}");
+                    match (properties.and_then(|p| p.get(key)), additional) {
+                        (Some(property), _) => {
+                            self.validate(property, field, &field_path, divergences)
+                        }
+                        (None, Some(Value::Bool(false))) => {
+                            divergences.push(format!("{field_path}: not in the schema"))
+                        }
+                        (None, Some(additional @ Value::Object(_))) => {
+                            self.validate(additional, field, &field_path, divergences)
+                        }
+                        (None, _) => {}
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (index, item) in items.iter().enumerate() {
+                        self.validate(item_schema, item, &format!("{path}[{index}]"), divergences);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "string" => value.is_string(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn is_json(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+async fn validate(
+    State(validator): State<Arc<SpecValidator>>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> AxumResponse {
+    let Some(route) = matched_path.map(|path| path.as_str().to_string()) else {
+        return next.run(request).await;
+    };
+    let method = request.method().as_str().to_ascii_lowercase();
+
+    let mut divergences = Vec::new();
+    let (parts, body) = request.into_parts();
+    // read with the extensions only, they carry the `DefaultBodyLimit` of the route
+    let mut limited = Request::new(body);
+    *limited.extensions_mut() = parts.extensions.clone();
+    let body = match Bytes::from_request(limited, &()).await {
+        Ok(body) => body,
+        // the handler's own extractor would have rejected it the same way, 413 when too large
+        Err(rejection) => {
+            warn!(
+                err = %rejection.body_text(),
+                route,
+                "spec validation could not read the request body"
+            );
+            return rejection.into_response();
+        }
+    };
+    if is_json(&parts.headers)
+        && let Ok(value) = serde_json::from_slice::<Value>(&body)
+    {
+        divergences.extend(
+            validator
+                .check_request(&method, &route, &value)
+                .into_iter()
+                .map(|divergence| format!("request {divergence}")),
+        );
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let fits = response
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|len| len <= MAX_RESPONSE_BODY);
+    if !is_json(response.headers()) || !fits {
+        return flag(response, &method, &route, divergences);
+    }
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, MAX_RESPONSE_BODY as usize).await {
+        Ok(body) => body,
+        Err(err) => {
+            warn!(err = ?err, route, "spec validation could not read the response body");
+            return AxumResponse::from_parts(parts, Body::empty());
+        }
+    };
+    if let Ok(value) = serde_json::from_slice::<Value>(&body) {
+        divergences.extend(
+            validator
+                .check_response(&method, &route, &value)
+                .into_iter()
+                .map(|divergence| format!("response {divergence}")),
+        );
+    }
+    flag(
+        AxumResponse::from_parts(parts, Body::from(body)),
+        &method,
+        &route,
+        divergences,
+    )
+}
+
+fn flag(
+    mut response: AxumResponse,
+    method: &str,
+    route: &str,
+    divergences: Vec<String>,
+) -> AxumResponse {
+    if !divergences.is_empty() {
+        warn!(method, route, divergences = ?divergences, "api diverges from the openapi spec");
+        response
+            .headers_mut()
+            .insert(SPEC_DIVERGENCE_HEADER, HeaderValue::from(divergences.len()));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::DefaultBodyLimit;
+    use axum::routing::{get, post};
+    use axum::{Json, http};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::api::http::server::base_openapi_doc;
+
+    fn router(base_path: Option<&str>) -> Result<Router> {
+        let validator = SpecValidator::new(&base_openapi_doc(), base_path)?;
+        let router = Router::new()
+            .route(
+                "/ping",
+                get(|| async { Json(json!({"code": 0, "msg": "", "data": "pong"})) }),
+            )
+            // documented as `{"user_id": string}` data
+            .route(
+                "/api/v1/user/register",
+                post(|| async { Json(json!({"code": 0, "msg": "", "data": {"id": 1}})) }),
+            );
+        let router = match base_path {
+            Some(base_path) => Router::new().nest(base_path, router),
+            None => router,
+        };
+        Ok(SpecValidator::layer(router, Some(Arc::new(validator))))
+    }
+
+    async fn divergences(router: Router, request: Request) -> Result<Option<String>> {
+        let response = router.oneshot(request).await?;
+        Ok(response
+            .headers()
+            .get(SPEC_DIVERGENCE_HEADER)
+            .map(|value| value.to_str().unwrap_or_default().to_string()))
+    }
+
+    #[tokio::test]
+    async fn mismatched_response_is_flagged() -> Result<()> {
+        let ping = || Request::get("/ping").body(Body::empty());
+        assert_eq!(divergences(router(None)?, ping()?).await?, None);
+
+        let register = http::Request::post("/api/v1/user/register")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({
+                    "auth_type": "Username",
+                    "auth_id": "alice",
+                    "auth_token": "secret",
+                    "role": "User"
+                })
+                .to_string(),
+            ))?;
+        // data misses `user_id`
+        assert_eq!(
+            divergences(router(None)?, register).await?,
+            Some("1".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mismatched_request_is_flagged_under_base_path() -> Result<()> {
+        let register = http::Request::post("/app/api/v1/user/register")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({"auth_type": "Password"}).to_string()))?;
+        // unknown auth type, auth_id, auth_token and role missing, plus the response
+        assert_eq!(
+            divergences(router(Some("/app"))?, register).await?,
+            Some("5".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oversized_request_is_rejected_not_emptied() -> Result<()> {
+        let router = router(None)?.layer(DefaultBodyLimit::max(16));
+        let register = http::Request::post("/api/v1/user/register")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({"auth_id": "a".repeat(64)}).to_string()))?;
+
+        let response = router.oneshot(register).await?;
+        assert_eq!(response.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn error_envelopes_are_not_checked() -> Result<()> {
+        let validator = SpecValidator::new(&base_openapi_doc(), None)?;
+        let error = json!({"code": 10003, "msg": "Unauthorized", "data": null});
+        assert!(validator.check_response("get", "/ping", &error).is_empty());
+
+        let wrong = json!({"code": 0, "msg": "", "data": 1});
+        assert_eq!(validator.check_response("get", "/ping", &wrong), [
+            "$.data: expected string, got number"
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bigint_fields_pass_in_either_form() -> Result<()> {
+        let validator = SpecValidator::new(&base_openapi_doc(), None)?;
+        let login = |expired_time| {
+            json!({
+                "code": 0,
+                "msg": "",
+                "data": {"user_id": "alice", "jwt_token": "token", "expired_time": expired_time}
+            })
+        };
+        for expired_time in [json!(1_700_000_000), json!("1700000000")] {
+            assert!(
+                validator
+                    .check_response("get", "/api/v1/user/login", &login(expired_time))
+                    .is_empty()
+            );
+        }
+        assert!(
+            !validator
+                .check_response("get", "/api/v1/user/login", &login(json!(true)))
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
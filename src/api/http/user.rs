@@ -1,64 +1,137 @@
 use std::sync::Arc;
 
-use axum::http::HeaderMap;
-use chrono::Duration;
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::response::Response as AxumResponse;
+use chrono::{DateTime, Duration};
+use futures::{Stream, StreamExt};
 use rand::Rng;
 use rand::distr::Alphanumeric;
+use sea_orm::Order;
 use serde::{Deserialize, Serialize};
 use sidecar::prelude::*;
+use tracing::{info, warn};
 use utoipa::OpenApi;
+use validator::{Validate, ValidationErrors};
 
+use crate::api::http::bigint;
+use crate::api::http::list_params::{ListReq, SortDir};
+use crate::api::http::server::AuthUser;
+use crate::api::http::validation::ValidateRequest;
 use crate::core::core::Core;
-use crate::core::model::user::Role;
+use crate::core::model::user::{self as user_model, Role, Status};
 use crate::core::model::user_auth::AuthType;
+use crate::core::service::user::UserFilter;
+use crate::kit::config::SubjectSource;
 use crate::kit::context::Context;
+use crate::kit::error::Error;
 use crate::kit::jwt;
+use crate::kit::query;
 use crate::kit::response::Response;
 
 /// User module OpenAPI documentation
 #[derive(OpenApi)]
 #[openapi(
-    paths(register, login, refresh_token),
+    paths(
+        register,
+        check_availability,
+        login,
+        refresh_token,
+        impersonate,
+        export,
+        list,
+        bulk_delete
+    ),
     components(
         schemas(
             RegisterReq,
             RegisterRes,
             Response<RegisterRes>,
+            Response<bool>,
             LoginReq,
             LoginRes,
             Response<LoginRes>,
             RefreshTokenRes,
             Response<RefreshTokenRes>,
+            ImpersonateReq,
+            ImpersonateRes,
+            Response<ImpersonateRes>,
+            UserView,
+            ListUsersRes,
+            Response<ListUsersRes>,
+            SortDir,
+            BulkDeleteReq,
+            BulkDeleteRes,
+            Response<BulkDeleteRes>,
         )
     ),
     tags((name = "user", description = "User management related APIs"))
 )]
 pub struct UserApiDoc;
 
+/// Custom data carried in the JWT claims
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AuthClaims {
+    /// Role of the user when the token was issued
+    #[serde(default)]
+    pub role: Option<Role>,
+    /// Set on impersonation tokens, the admin acting as the subject (RFC 8693 `act`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    /// Tenant the subject belongs to, requests with the token are scoped to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+/// Acting party of an impersonation token
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Actor {
+    /// Token subject of the admin, same form as `sub` (see `http.jwt.subject_source`)
+    pub sub: String,
+}
+
+/// Impersonation tokens never outlive this, whatever `http.jwt.token_valid_duration` says
+const IMPERSONATION_TOKEN_MAX_VALID_DURATION: std::time::Duration =
+    std::time::Duration::from_secs(60 * 60);
+
+/// Tracing target of security relevant actions, route it to a separate sink to keep an audit trail
+pub const AUDIT_LOG_TARGET: &str = "audit";
+
 /// User registration request body
-#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+#[derive(Debug, Deserialize, Serialize, Validate, utoipa::ToSchema)]
 pub struct RegisterReq {
     /// Authentication method
     /// username: auth_id is username, auth_token is password
     #[schema(example = "Username")]
     pub auth_type: AuthType,
     /// External account unique identifier
-    #[schema(example = "admin")]
+    #[schema(example = "admin", min_length = 1, max_length = 255)]
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
     pub auth_id: String,
     /// Authentication credentials
-    #[schema(example = "admin")]
+    #[schema(example = "admin", min_length = 1)]
+    #[validate(length(min = 1, message = "must not be empty"))]
     pub auth_token: String,
     /// Target role
     #[schema(example = "Admin")]
     pub role: Role,
     /// User nickname, auto-generated if not provided
-    #[schema(nullable = false, example = "admin")]
+    #[schema(nullable = false, example = "admin", max_length = 255)]
+    #[validate(length(max = 255, message = "must be at most 255 characters"))]
     pub nickname: Option<String>,
     /// User description information, default is empty
-    #[schema(nullable = false, example = "admin")]
+    #[schema(nullable = false, example = "admin", max_length = 1000)]
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
     pub desc: Option<String>,
 }
 
+/// Lengths follow the `user`/`user_auth` columns
+impl ValidateRequest for RegisterReq {
+    fn validate_request(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+}
+
 /// User registration response body
 #[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
 pub struct RegisterRes {
@@ -79,7 +152,7 @@ pub struct RegisterRes {
 )]
 pub async fn register(
     state: Arc<Core>,
-    _ctx: Context,
+    ctx: Context,
     _headers: HeaderMap,
     req: RegisterReq,
 ) -> Result<RegisterRes> {
@@ -93,14 +166,53 @@ pub async fn register(
             req.role,
             req.nickname.unwrap_or(format!("user-{}", random_string(6))),
             req.desc.unwrap_or("".to_string()),
+            ctx.deadline,
         )
         .await?;
 
     Ok(RegisterRes { user_id })
 }
 
+/// Auth id availability request parameters
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CheckAvailabilityReq {
+    /// Authentication method
+    #[param(example = "Username")]
+    pub auth_type: AuthType,
+    /// External account unique identifier to check
+    #[param(example = "admin")]
+    pub auth_id: String,
+}
+
+impl ValidateRequest for CheckAvailabilityReq {}
+
+/// Auth id availability endpoint
+#[utoipa::path(
+    tag = "user",
+    operation_id = "user_check_availability",
+    get,
+    path = "/check-availability",
+    params(CheckAvailabilityReq),
+    summary = "Check auth id availability",
+    description = "Return true when the auth id is not registered yet. Strictly rate limited to prevent account enumeration.",
+    responses((status = 200, description = "Success", body = Response<bool>))
+)]
+pub async fn check_availability(
+    state: Arc<Core>,
+    ctx: Context,
+    _headers: HeaderMap,
+    req: CheckAvailabilityReq,
+) -> Result<bool> {
+    state
+        .service
+        .user
+        .is_auth_id_available(req.auth_type, req.auth_id, ctx.deadline)
+        .await
+}
+
 /// User login request parameters
-#[derive(Debug, Deserialize, utoipa::IntoParams, utoipa::ToSchema)]
+#[derive(Debug, Deserialize, Validate, utoipa::IntoParams, utoipa::ToSchema)]
 #[into_params(parameter_in = Query)]
 pub struct LoginReq {
     /// Authentication method
@@ -109,12 +221,20 @@ pub struct LoginReq {
     pub auth_type: AuthType,
     /// External account unique identifier
     #[param(example = "admin")]
+    #[validate(length(min = 1, max = 255, message = "must be 1 to 255 characters"))]
     pub auth_id: String,
     /// Authentication credentials
     #[param(example = "admin")]
+    #[validate(length(min = 1, message = "must not be empty"))]
     pub auth_token: String,
 }
 
+impl ValidateRequest for LoginReq {
+    fn validate_request(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+}
+
 /// User login response body
 #[derive(Debug, Serialize, utoipa::ToSchema)]
 pub struct LoginRes {
@@ -122,7 +242,9 @@ pub struct LoginRes {
     pub user_id: String,
     /// Issued JWT token
     pub jwt_token: String,
-    /// Token expiration time (Unix timestamp, seconds)
+    /// Token expiration time (Unix timestamp, seconds), a string with `http.bigint_as_string`
+    #[serde(serialize_with = "bigint::serialize")]
+    #[schema(value_type = bigint::BigInt)]
     pub expired_time: i64,
 }
 
@@ -139,21 +261,31 @@ pub struct LoginRes {
 )]
 pub async fn login(
     state: Arc<Core>,
-    _ctx: Context,
+    ctx: Context,
     _headers: HeaderMap,
     req: LoginReq,
 ) -> Result<LoginRes> {
     let user_id = state
         .service
         .user
-        .login(req.auth_type, req.auth_id, req.auth_token)
+        .login(req.auth_type, req.auth_id, req.auth_token, ctx.deadline)
+        .await?;
+    let user = state
+        .service
+        .user
+        .info(user_id.clone(), ctx.deadline)
         .await?;
 
     let (jwt_token, expired_time) = jwt::generate_with_hmac_key(
         &state.repo.cfg.http.jwt.token_hmac_key,
-        Duration::from_std(state.repo.cfg.http.jwt.token_valid_duration.into())?,
-        &user_id,
-        (),
+        Duration::from_std(state.repo.cfg.http.jwt.token_valid_duration)?,
+        Duration::from_std(state.repo.cfg.http.jwt.clock_skew)?,
+        &token_subject(&state, &user),
+        AuthClaims {
+            role: Some(user.role),
+            act: None,
+            tenant_id: user.tenant_id,
+        },
     )?;
 
     Ok(LoginRes {
@@ -170,7 +302,9 @@ pub struct RefreshTokenRes {
     pub user_id: String,
     /// Issued JWT token
     pub jwt_token: String,
-    /// Token expiration time (Unix timestamp, seconds)
+    /// Token expiration time (Unix timestamp, seconds), a string with `http.bigint_as_string`
+    #[serde(serialize_with = "bigint::serialize")]
+    #[schema(value_type = bigint::BigInt)]
     pub expired_time: i64,
 }
 
@@ -191,11 +325,22 @@ pub async fn refresh_token(
     _headers: HeaderMap,
     _req: (),
 ) -> Result<RefreshTokenRes> {
+    // the short lifetime of an impersonation is the point, it must not be extended
+    if ctx.actor_id.is_some() {
+        return Err(Error::Forbidden).wrap_err("impersonation tokens can't be refreshed");
+    }
+    // claims come from the user row like at login, a demoted user must not keep the old role
+    let AuthUser(user) = AuthUser::from_context(&ctx)?;
     let (jwt_token, expired_time) = jwt::generate_with_hmac_key(
         &state.repo.cfg.http.jwt.token_hmac_key,
-        Duration::from_std(state.repo.cfg.http.jwt.token_valid_duration.into())?,
-        &ctx.user_id,
-        (),
+        Duration::from_std(state.repo.cfg.http.jwt.token_valid_duration)?,
+        Duration::from_std(state.repo.cfg.http.jwt.clock_skew)?,
+        &token_subject(&state, &user),
+        AuthClaims {
+            role: Some(user.role),
+            act: None,
+            tenant_id: user.tenant_id,
+        },
     )?;
 
     Ok(RefreshTokenRes {
@@ -205,6 +350,416 @@ pub async fn refresh_token(
     })
 }
 
+/// User impersonation request body
+#[derive(Debug, Deserialize, Serialize, Validate, utoipa::ToSchema)]
+pub struct ImpersonateReq {
+    /// Why support acts as the user, kept in the audit log
+    #[schema(nullable = false, example = "ticket 1234", max_length = 1000)]
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
+    pub reason: Option<String>,
+}
+
+impl ValidateRequest for ImpersonateReq {
+    fn validate_request(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+}
+
+/// User impersonation response body
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ImpersonateRes {
+    /// Unique identifier of the impersonated user
+    pub user_id: String,
+    /// Issued JWT token, acts as the user and records the admin in its `act` claim
+    pub jwt_token: String,
+    /// Token expiration time (Unix timestamp, seconds), a string with `http.bigint_as_string`
+    #[serde(serialize_with = "bigint::serialize")]
+    #[schema(value_type = bigint::BigInt)]
+    pub expired_time: i64,
+}
+
+/// User impersonation endpoint
+#[utoipa::path(
+    tag = "user",
+    operation_id = "user_impersonate",
+    post,
+    path = "/{id}/impersonate",
+    summary = "Act as a user",
+    description = "Admin only, for users of the caller's tenant when the request has one. Issue a short lived JWT token for the user that records the admin as actor, the impersonation is written to the audit log.",
+    params(("id" = String, Path, description = "Unique identifier of the user to act as")),
+    request_body = ImpersonateReq,
+    security(("bearer_auth" = [])),
+    responses((status = 200, description = "Impersonation token issued", body = Response<ImpersonateRes>))
+)]
+pub async fn impersonate(
+    state: Arc<Core>,
+    ctx: Context,
+    _headers: HeaderMap,
+    req: ImpersonateReq,
+) -> Result<ImpersonateRes> {
+    ctx.require_role(Role::Admin)?;
+    ctx.require_entitlement("user_impersonate")?;
+    if ctx.actor_id.is_some() {
+        return Err(Error::Forbidden).wrap_err("impersonation tokens can't impersonate");
+    }
+    // the role of the token may be stale, the actor must still be an admin
+    let AuthUser(actor) = AuthUser::from_context(&ctx)?;
+    if !actor.role.at_least(&Role::Admin) {
+        return Err(Error::Forbidden).wrap_err("the actor is no longer an admin");
+    }
+    let target = state
+        .service
+        .user
+        .info(ctx.path_param("id")?.to_string(), ctx.deadline)
+        .await?;
+    ctx.require_tenant(target.tenant_id.as_deref())?;
+    // acting as a peer would let one admin hide behind another
+    if target.role == Role::Admin {
+        return Err(Error::Forbidden).wrap_err("admins can't be impersonated");
+    }
+
+    let valid_duration = state
+        .repo
+        .cfg
+        .http
+        .jwt
+        .token_valid_duration
+        .min(IMPERSONATION_TOKEN_MAX_VALID_DURATION);
+    let (jwt_token, expired_time) = jwt::generate_with_hmac_key(
+        &state.repo.cfg.http.jwt.token_hmac_key,
+        Duration::from_std(valid_duration)?,
+        Duration::from_std(state.repo.cfg.http.jwt.clock_skew)?,
+        &token_subject(&state, &target),
+        AuthClaims {
+            role: Some(target.role.clone()),
+            act: Some(Actor {
+                sub: token_subject(&state, &actor),
+            }),
+            tenant_id: target.tenant_id.clone(),
+        },
+    )?;
+
+    info!(
+        target: AUDIT_LOG_TARGET,
+        action = "impersonate",
+        request_id = ctx.request_id,
+        actor = actor.id,
+        user = target.id,
+        reason = req.reason.unwrap_or_default(),
+        expired_time,
+        "admin impersonates user"
+    );
+
+    Ok(ImpersonateRes {
+        user_id: target.id,
+        jwt_token,
+        expired_time,
+    })
+}
+
+/// The `sub` claim for `user` as configured by `http.jwt.subject_source`
+fn token_subject(state: &Core, user: &user_model::Model) -> String {
+    match state.repo.cfg.http.jwt.subject_source {
+        SubjectSource::UserId => user.id.clone(),
+        SubjectSource::PublicId => user.public_id.clone(),
+    }
+}
+
+/// Public view of a user, every user returned by the api goes through this so soft-delete and
+/// optimistic-lock bookkeeping (`del_state`, `delete_time`, `version`) stays internal
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct UserView {
+    pub id: String,
+    /// Creation time (Unix timestamp, seconds)
+    pub create_time: i64,
+    /// Last update time (Unix timestamp, seconds)
+    pub update_time: i64,
+    pub status: Status,
+    pub role: Role,
+    pub name: String,
+    pub desc: String,
+}
+
+impl From<user_model::Model> for UserView {
+    fn from(user: user_model::Model) -> Self {
+        Self {
+            id: user.id,
+            create_time: user.create_time.timestamp(),
+            update_time: user.update_time.timestamp(),
+            status: user.status,
+            role: user.role,
+            name: user.name,
+            desc: user.desc,
+        }
+    }
+}
+
+/// User export request parameters
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportReq {
+    /// Only active (true) or only non-active (false) users, all when omitted.
+    /// Also accepts 1/0, yes/no and on/off
+    #[serde(default, deserialize_with = "query::flexible_option_bool")]
+    #[param(value_type = Option<bool>, example = true)]
+    pub active: Option<bool>,
+}
+
+impl ValidateRequest for ExportReq {}
+
+/// User export endpoint
+#[utoipa::path(
+    tag = "user",
+    operation_id = "user_export",
+    get,
+    path = "/export",
+    params(ExportReq),
+    summary = "Export all users",
+    description = "Stream the users of the caller's tenant as newline-delimited JSON, one UserView per line. Admin only. \
+                   Browser downloads may pass the token as `access_token` query parameter instead.",
+    security(("bearer_auth" = [])),
+    responses((status = 200, description = "Export stream", body = UserView, content_type = "application/x-ndjson"))
+)]
+pub async fn export(
+    state: Arc<Core>,
+    ctx: Context,
+    _headers: HeaderMap,
+    req: ExportReq,
+) -> Result<AxumResponse> {
+    ctx.require_role(Role::Admin)?;
+    ctx.require_entitlement("user_export")?;
+
+    let filter = UserFilter {
+        tenant_id: ctx.tenant_id.clone(),
+        ..Default::default()
+    };
+    let users = state
+        .service
+        .user
+        .export(&filter, req.active, ctx.deadline)
+        .await?;
+
+    let mut response = AxumResponse::new(ndjson_body(users));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    Ok(response)
+}
+
+/// Serialize each user as one json line, a failed row aborts the body so the client sees a
+/// truncated transfer instead of a silently partial export
+fn ndjson_body<S>(users: S) -> Body
+where
+    S: Stream<Item = Result<user_model::Model>> + Send + 'static,
+{
+    Body::from_stream(users.map(|user| {
+        let user = user.inspect_err(|err| warn!(err = ?err, "user export aborted"))?;
+        let mut line = serde_json::to_vec(&UserView::from(user))?;
+        line.push(b'\n');
+        Ok::<_, Report>(line)
+    }))
+}
+
+/// Fields `user_list` can sort by
+pub const LIST_SORT_FIELDS: [&str; 3] = ["create_time", "update_time", "name"];
+
+/// User list filter parameters, paging and sorting come from the list extractors
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsersReq {
+    /// Only users with this role
+    #[param(example = "User")]
+    pub role: Option<Role>,
+    /// Only users with this status
+    #[param(example = "Active")]
+    pub status: Option<Status>,
+}
+
+impl ValidateRequest for ListUsersReq {}
+
+/// User list response body
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ListUsersRes {
+    /// Number of matching users across all pages
+    pub total: u64,
+    pub users: Vec<UserView>,
+}
+
+/// User list endpoint
+#[utoipa::path(
+    tag = "user",
+    operation_id = "user_list",
+    get,
+    path = "/list",
+    params(
+        ListUsersReq,
+        ("offset" = Option<u64>, Query, description = "Users to skip, default 0"),
+        ("limit" = Option<u64>, Query, description = "Page size, default 20, clamped to 100"),
+        ("sort" = Option<String>, Query, description = "One of create_time, update_time, name. Default create_time"),
+        ("dir" = Option<SortDir>, Query, description = "asc or desc, default desc when sort is omitted, otherwise asc"),
+    ),
+    summary = "List users",
+    description = "Page through users that are not deleted, of the caller's tenant when the request has one. Admin only.",
+    security(("bearer_auth" = [])),
+    responses((status = 200, description = "Success", body = Response<ListUsersRes>))
+)]
+pub async fn list(
+    state: Arc<Core>,
+    ctx: Context,
+    _headers: HeaderMap,
+    req: ListReq<ListUsersReq>,
+) -> Result<ListUsersRes> {
+    ctx.require_role(Role::Admin)?;
+
+    let order_by = req
+        .sort
+        .field
+        .map(|field| {
+            let column = match field {
+                "create_time" => user_model::Column::CreateTime,
+                "update_time" => user_model::Column::UpdateTime,
+                "name" => user_model::Column::Name,
+                _ => {
+                    return Err(Error::InvidRequestParameter(format!(
+                        "unsupported sort field {field}"
+                    )));
+                }
+            };
+            let order = match req.sort.dir {
+                SortDir::Asc => Order::Asc,
+                SortDir::Desc => Order::Desc,
+            };
+            Ok((column, order))
+        })
+        .transpose()?;
+    let filter = UserFilter {
+        role: req.filter.role,
+        status: req.filter.status,
+        tenant_id: ctx.tenant_id.clone(),
+        ..Default::default()
+    };
+
+    let (users, total) = state
+        .service
+        .user
+        .list(
+            &filter,
+            req.page.offset,
+            req.page.limit,
+            order_by,
+            ctx.deadline,
+        )
+        .await?;
+
+    Ok(ListUsersRes {
+        total,
+        users: users.into_iter().map(UserView::from).collect(),
+    })
+}
+
+/// User bulk delete request body
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct BulkDeleteReq {
+    /// Only users with this role
+    #[schema(nullable = false, example = "User")]
+    pub role: Option<Role>,
+    /// Only users with this status
+    #[schema(nullable = false, example = "Active")]
+    pub status: Option<Status>,
+    /// Only users created before this time (Unix timestamp, seconds)
+    #[schema(nullable = false)]
+    pub created_before: Option<i64>,
+    /// Number of matched users returned by a dry run, omit to dry run
+    #[schema(nullable = false)]
+    pub confirm: Option<u64>,
+}
+
+impl ValidateRequest for BulkDeleteReq {}
+
+/// User bulk delete response body
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct BulkDeleteRes {
+    /// Number of users matching the filter
+    pub matched: u64,
+    /// Number of users deleted, always 0 for a dry run
+    pub deleted: u64,
+    /// Whether nothing was deleted because `confirm` was omitted
+    pub dry_run: bool,
+}
+
+/// User bulk delete endpoint
+#[utoipa::path(
+    tag = "user",
+    operation_id = "user_bulk_delete",
+    post,
+    path = "/bulk-delete",
+    summary = "Soft delete users matching a filter",
+    description = "Without confirm, only count the matching users. With confirm equal to that count, soft delete them; a mismatch is rejected and nothing is deleted. The caller is never matched, nor are users outside the caller's tenant when the request has one. Admin only.",
+    request_body = BulkDeleteReq,
+    security(("bearer_auth" = [])),
+    responses((status = 200, description = "Success", body = Response<BulkDeleteRes>))
+)]
+pub async fn bulk_delete(
+    state: Arc<Core>,
+    ctx: Context,
+    _headers: HeaderMap,
+    req: BulkDeleteReq,
+) -> Result<BulkDeleteRes> {
+    ctx.require_role(Role::Admin)?;
+    ctx.require_entitlement("user_bulk_delete")?;
+
+    let created_before = req
+        .created_before
+        .map(|secs| {
+            DateTime::from_timestamp(secs, 0)
+                .map(|t| t.into())
+                .ok_or_else(|| {
+                    Error::InvidRequestParameter(format!("created_before {secs} is out of range"))
+                })
+        })
+        .transpose()?;
+    let filter = UserFilter {
+        role: req.role,
+        status: req.status,
+        created_before,
+        exclude_user_id: Some(ctx.user_id.clone()),
+        tenant_id: ctx.tenant_id.clone(),
+    };
+
+    let Some(confirm) = req.confirm else {
+        let matched = state
+            .service
+            .user
+            .count_matching(&filter, ctx.deadline)
+            .await?;
+        return Ok(BulkDeleteRes {
+            matched,
+            deleted: 0,
+            dry_run: true,
+        });
+    };
+
+    let deleted = state
+        .service
+        .user
+        .soft_delete_matching(&filter, confirm, ctx.deadline)
+        .await?;
+    warn!(
+        target: AUDIT_LOG_TARGET,
+        admin = %ctx.user_id,
+        filter = ?filter,
+        deleted,
+        "users bulk deleted"
+    );
+    // the delete is rolled back unless it touched exactly the confirmed users
+    Ok(BulkDeleteRes {
+        matched: deleted,
+        deleted,
+        dry_run: false,
+    })
+}
+
 /// Generate a random string of specified length, used for fallback nickname
 fn random_string(len: usize) -> String {
     rand::rng()
@@ -213,3 +768,47 @@ fn random_string(len: usize) -> String {
         .map(char::from)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use futures::stream;
+    use sea_orm::{Set, TryIntoModel};
+
+    use super::*;
+
+    fn user(name: &str) -> user_model::Model {
+        let mut user = user_model::ActiveModel::create();
+        user.name = Set(name.to_string());
+        user.try_into_model().expect("active model is fully set")
+    }
+
+    #[tokio::test]
+    async fn ndjson_body_yields_one_line_per_user() -> Result<()> {
+        let users = stream::iter(vec![Ok(user("alice")), Ok(user("bob"))]);
+
+        let bytes = to_bytes(ndjson_body(users), usize::MAX).await?;
+        let body = String::from_utf8(bytes.to_vec())?;
+
+        let names = body
+            .lines()
+            .map(|line| Ok(serde_json::from_str::<serde_json::Value>(line)?["name"].clone()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(names, ["alice", "bob"]);
+        assert!(body.ends_with('\n'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_view_omits_internal_fields() -> Result<()> {
+        let view = serde_json::to_value(UserView::from(user("alice")))?;
+
+        for field in ["del_state", "delete_time", "version"] {
+            assert!(view.get(field).is_none(), "{field} leaked: {view}");
+        }
+        assert_eq!(view["name"], "alice");
+
+        Ok(())
+    }
+}
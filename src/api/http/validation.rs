@@ -0,0 +1,125 @@
+//! Semantic request checks, run by the `wrap_*_handler`s after deserialization and before the
+//! handler. Deserialization only guarantees types, rules like "non-empty" or "fits the db column"
+//! are declared with `#[derive(validator::Validate)]` and enabled through [`ValidateRequest`]
+
+use std::collections::BTreeMap;
+
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::api::http::list_params::ListReq;
+
+/// Messages per field path, e.g. `{"nickname": ["length must be at most 255"]}`
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+/// Implemented by every request type of a wrapped handler, the default accepts everything.
+/// Types with rules forward to their derived [`validator::Validate`]
+pub trait ValidateRequest {
+    fn validate_request(&self) -> Result<(), ValidationErrors> {
+        Ok(())
+    }
+}
+
+impl ValidateRequest for () {}
+
+/// Only the filter of a list request carries rules, paging and sorting are checked on extraction
+impl<Q: ValidateRequest> ValidateRequest for ListReq<Q> {
+    fn validate_request(&self) -> Result<(), ValidationErrors> {
+        self.filter.validate_request()
+    }
+}
+
+/// Flatten nested errors into dotted field paths, `items[0].name` for lists
+pub fn field_errors(errors: &ValidationErrors) -> FieldErrors {
+    let mut fields = FieldErrors::new();
+    collect(errors, "", &mut fields);
+    fields
+}
+
+/// One line summary for the envelope `msg`, fields in name order
+pub fn summary(fields: &FieldErrors) -> String {
+    fields
+        .iter()
+        .map(|(field, messages)| format!("{field}: {}", messages.join(", ")))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, fields: &mut FieldErrors) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{prefix}.{field}")
+        };
+        match kind {
+            ValidationErrorsKind::Field(errs) => {
+                fields
+                    .entry(path)
+                    .or_default()
+                    .extend(errs.iter().map(|err| {
+                        err.message
+                            .as_ref()
+                            .map(|message| message.to_string())
+                            .unwrap_or_else(|| err.code.to_string())
+                    }))
+            }
+            ValidationErrorsKind::Struct(inner) => collect(inner, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, inner) in items {
+                    collect(inner, &format!("{path}[{index}]"), fields);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::Validate;
+
+    use super::*;
+
+    #[derive(Validate)]
+    struct Inner {
+        #[validate(length(min = 1, message = "must not be empty"))]
+        name: String,
+    }
+
+    #[derive(Validate)]
+    struct Outer {
+        #[validate(length(max = 3))]
+        code: String,
+        #[validate(nested)]
+        items: Vec<Inner>,
+    }
+
+    #[test]
+    fn test_field_errors_flatten_nested_paths() {
+        let outer = Outer {
+            code: "toolong".to_string(),
+            items: vec![
+                Inner {
+                    name: "ok".to_string(),
+                },
+                Inner {
+                    name: "".to_string(),
+                },
+            ],
+        };
+
+        let fields = field_errors(&outer.validate().unwrap_err());
+        assert_eq!(
+            fields,
+            FieldErrors::from([
+                ("code".to_string(), vec!["length".to_string()]),
+                ("items[1].name".to_string(), vec![
+                    "must not be empty".to_string()
+                ]),
+            ])
+        );
+        assert_eq!(
+            summary(&fields),
+            "code: length; items[1].name: must not be empty"
+        );
+    }
+}
@@ -1,14 +1,23 @@
-use clap::{Args, Subcommand};
+use std::io;
+use std::path::Path;
+
+use clap::{Args, Subcommand, ValueEnum};
 use sidecar::prelude::*;
-use sidecar::repo::Repo;
+use sidecar::repo::{ConfigSource, Repo};
+use sidecar::sidecar::{Component, Sidecar};
+use tokio::fs;
 
-use crate::kit::config::Config;
+use crate::core::db::DB;
+use crate::core::service::config_kv;
+use crate::kit::config::{Config, REDACTED};
 
 #[derive(Subcommand)]
 pub enum Cmd {
     GenerateDefault(GenerateDefaultArgs),
     Check(CheckArgs),
     Show(ShowArgs),
+    ExportEnv(ExportEnvArgs),
+    Set(SetArgs),
 }
 
 pub async fn run(cmd: Cmd, repo: Repo<Config>) -> Result<()> {
@@ -16,6 +25,8 @@ pub async fn run(cmd: Cmd, repo: Repo<Config>) -> Result<()> {
         Cmd::GenerateDefault(args) => args.run(repo).await,
         Cmd::Check(args) => args.run(repo).await,
         Cmd::Show(args) => args.run(repo).await,
+        Cmd::ExportEnv(args) => args.run(repo).await,
+        Cmd::Set(args) => args.run(repo).await,
     }
 }
 
@@ -32,7 +43,9 @@ impl GenerateDefaultArgs {
             return Ok(());
         }
 
-        repo.save().await?;
+        repo.save()
+            .await
+            .map_err(|err| explain_write_error(err, &repo.config_path()))?;
 
         println!(
             "default config file generated: {}",
@@ -43,6 +56,28 @@ impl GenerateDefaultArgs {
     }
 }
 
+/// Say which path could not be written and why, instead of a bare "Permission denied (os error 13)"
+fn explain_write_error(err: Report, config_path: &Path) -> Report {
+    let Some(io_err) = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<io::Error>())
+    else {
+        return err;
+    };
+    let reason = match io_err.kind() {
+        io::ErrorKind::PermissionDenied => "the current user may not write there",
+        io::ErrorKind::ReadOnlyFilesystem => "the filesystem is mounted read-only",
+        _ => return err,
+    };
+    let dir = config_path.parent().unwrap_or(config_path);
+    err.wrap_err(format!(
+        "Cannot write config file {}: {} is not writable, {reason}. Fix its permissions or pick \
+         another directory with --repo-root",
+        config_path.display(),
+        dir.display()
+    ))
+}
+
 #[derive(Args)]
 pub struct CheckArgs {}
 
@@ -53,21 +88,372 @@ impl CheckArgs {
         }
 
         repo.reload().await?;
+        if let Some(warning) = repo.unknown_keys_warning() {
+            println!("{warning}");
+        }
 
         Ok(())
     }
 }
 
 #[derive(Args)]
-pub struct ShowArgs {}
+pub struct ShowArgs {
+    #[arg(
+        long,
+        help = "Print the db password and jwt hmac key instead of redacting them"
+    )]
+    show_secrets: bool,
+    #[arg(
+        long,
+        help = "Also print whether each value came from the default, config.toml or the environment"
+    )]
+    sources: bool,
+}
 
 impl ShowArgs {
     pub async fn run(self, repo: Repo<Config>) -> Result<()> {
-        let cfg_data = toml::to_string(&repo.cfg)?;
+        let cfg_data = self.render(&repo.cfg)?;
 
         println!("config: \n");
         println!("{cfg_data}");
 
+        if self.sources {
+            println!("sources: \n");
+            for (key, origin) in repo.config_sources()? {
+                println!("{key} = {origin}");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render(&self, cfg: &Config) -> Result<String> {
+        if self.show_secrets {
+            Ok(toml::to_string(cfg)?)
+        } else {
+            Ok(toml::to_string(&cfg.redacted())?)
+        }
+    }
+}
+
+/// Print the effective config as `export` lines, e.g. to move a running setup into a container
+#[derive(Args)]
+pub struct ExportEnvArgs {
+    #[arg(
+        long,
+        help = "Print the db password and jwt hmac key instead of redacting them"
+    )]
+    show_secrets: bool,
+}
+
+impl ExportEnvArgs {
+    pub async fn run(self, repo: Repo<Config>) -> Result<()> {
+        print!("{}", self.render(&repo.cfg, &repo.env_prefix())?);
+        Ok(())
+    }
+
+    /// Keys the env source can't express, field names with `_` (segments are split on it) and
+    /// lists, are written as comments so nothing is dropped silently. So are redacted secrets
+    fn render(&self, cfg: &Config, env_prefix: &str) -> Result<String> {
+        let cfg = if self.show_secrets {
+            cfg.clone()
+        } else {
+            cfg.redacted()
+        };
+        let cfg = toml::Value::try_from(cfg)?;
+        let mut values = Vec::new();
+        flatten_toml(&cfg, "", &mut values);
+
+        let mut out = String::new();
+        for (key, value) in values {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Array(_) => {
+                    out.push_str(&format!(
+                        "# {key}: lists have no env form, set it in config.toml\n"
+                    ));
+                    continue;
+                }
+                value => value.to_string(),
+            };
+            if key.split('.').any(|segment| segment.contains('_')) {
+                out.push_str(&format!(
+                    "# {key}: has no env form because of the '_' in its name, set it in config.toml\n"
+                ));
+                continue;
+            }
+            let name = format!("{env_prefix}_{}", key.replace('.', "_")).to_uppercase();
+            // exporting the placeholder would override the real secret when the output is sourced
+            if !self.show_secrets && value == REDACTED {
+                out.push_str(&format!(
+                    "# {key}: redacted, pass --show-secrets to export it as {name}\n"
+                ));
+                continue;
+            }
+            out.push_str(&format!("export {name}={}\n", shell_quote(&value)));
+        }
+        Ok(out)
+    }
+}
+
+/// Dotted paths of every leaf in `value`, in document order
+fn flatten_toml<'a>(
+    value: &'a toml::Value,
+    prefix: &str,
+    out: &mut Vec<(String, &'a toml::Value)>,
+) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_toml(value, &path, out);
+            }
+        }
+        value => out.push((prefix.to_string(), value)),
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Store {
+    /// config.toml of this node
+    File,
+    /// config_kv table shared by the cluster, overrides config.toml and env. Only read at startup,
+    /// running instances pick the value up when they restart
+    Db,
+}
+
+#[derive(Args)]
+pub struct SetArgs {
+    /// Dotted config path, e.g. http.port
+    key: String,
+    value: String,
+    #[arg(long, value_enum, default_value_t = Store::File)]
+    store: Store,
+}
+
+impl SetArgs {
+    pub async fn run(self, repo: Repo<Config>) -> Result<()> {
+        let default_cfg = toml::Value::try_from(Config::default())?;
+        if lookup_toml_path(&default_cfg, &self.key).is_none() {
+            bail!("unknown config key: {}", self.key);
+        }
+
+        match self.store {
+            Store::File => self.set_file(repo).await,
+            Store::Db => self.set_db(repo).await,
+        }
+    }
+
+    async fn set_file(self, mut repo: Repo<Config>) -> Result<()> {
+        let config_path = repo.config_path();
+        let prev_data = if repo.config_exists() {
+            Some(fs::read_to_string(&config_path).await?)
+        } else {
+            None
+        };
+        let mut table = match &prev_data {
+            Some(data) => data.parse::<toml::Table>()?,
+            None => toml::Table::new(),
+        };
+        set_toml_path(&mut table, &self.key, parse_toml_value(&self.value))?;
+
+        repo.backup_config().await?;
+        fs::write(&config_path, toml::to_string(&table)?).await?;
+        if let Err(err) = repo.reload().await {
+            match prev_data {
+                Some(data) => fs::write(&config_path, data).await?,
+                None => fs::remove_file(&config_path).await?,
+            }
+            return Err(err).wrap_err(format!("invalid value for {}", self.key));
+        }
+
+        println!("{} set in {}", self.key, config_path.display());
+        Ok(())
+    }
+
+    async fn set_db(self, repo: Repo<Config>) -> Result<()> {
+        let sidecar = Sidecar::new();
+        let db = DB::new(sidecar.clone(), repo.clone()).await?;
+        db.start().await?;
+        let result = async {
+            let config_kv = config_kv::Service::new(sidecar, db.clone()).await?;
+            config_kv.create_tables().await?;
+
+            let mut overrides = config_kv.load().await?;
+            overrides.insert(self.key.clone(), self.value.clone());
+            repo.clone()
+                .reload_from(&overrides)
+                .await
+                .wrap_err(format!("invalid value for {}", self.key))?;
+
+            config_kv.set(self.key.clone(), self.value.clone()).await
+        }
+        .await;
+        db.stop().await?;
+        result?;
+
+        println!("{} set in config_kv, applied when instances restart", self.key);
+        Ok(())
+    }
+}
+
+fn lookup_toml_path<'a>(value: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+    key.split('.')
+        .try_fold(value, |value, part| value.get(part))
+}
+
+/// Parse as a toml literal so numbers and bools keep their type, anything else is a string
+fn parse_toml_value(value: &str) -> toml::Value {
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn set_toml_path(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<()> {
+    let (parents, leaf) = match key.rsplit_once('.') {
+        Some((parents, leaf)) => (parents.split('.').collect::<Vec<_>>(), leaf),
+        None => (vec![], key),
+    };
+
+    let mut current = table;
+    for part in parents {
+        current = current
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| eyre!("config key {part} is not a table"))?;
+    }
+    current.insert(leaf.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_explain_write_error_names_path_and_reason() {
+        let path = Path::new("/etc/app/config.toml");
+        let err = explain_write_error(
+            Report::new(io::Error::from(io::ErrorKind::PermissionDenied)),
+            path,
+        );
+        let msg = err.to_string();
+        assert!(msg.contains("/etc/app/config.toml"), "{msg}");
+        assert!(msg.contains("/etc/app is not writable"), "{msg}");
+        assert!(msg.contains("may not write there"), "{msg}");
+
+        // anything else is left alone
+        let err = explain_write_error(eyre!("disk on fire"), path);
+        assert_eq!(err.to_string(), "disk on fire");
+    }
+
+    #[tokio::test]
+    async fn test_generate_default_in_read_only_dir_explains() -> Result<()> {
+        // root writes through permission bits, there is nothing to observe
+        if unsafe { libc::geteuid() } == 0 {
+            return Ok(());
+        }
+        let tmp = tempdir()?;
+        let repo = Repo::<Config>::new(tmp.path(), "config-test").await?;
+        std::fs::set_permissions(tmp.path(), std::fs::Permissions::from_mode(0o555))?;
+
+        let err = GenerateDefaultArgs {}.run(repo).await.unwrap_err();
+        std::fs::set_permissions(tmp.path(), std::fs::Permissions::from_mode(0o755))?;
+        assert!(err.to_string().contains("is not writable"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_redacts_secrets_unless_asked() -> Result<()> {
+        let cfg = Config::default();
+
+        let redacted = ShowArgs {
+            show_secrets: false,
+            sources: false,
+        }
+        .render(&cfg)?;
+        assert!(redacted.contains(&format!("password = \"{REDACTED}\"")));
+        assert!(!redacted.contains(&cfg.http.jwt.token_hmac_key));
+
+        let revealed = ShowArgs {
+            show_secrets: true,
+            sources: false,
+        }
+        .render(&cfg)?;
+        assert!(revealed.contains(&cfg.http.jwt.token_hmac_key));
+        assert!(!revealed.contains(REDACTED));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_env_quotes_values_and_comments_keys_without_env_form() -> Result<()> {
+        let mut cfg = Config::default();
+        cfg.http.port = 9090;
+        cfg.db.host = "it's.db".to_string();
+
+        let redacted = ExportEnvArgs {
+            show_secrets: false,
+        }
+        .render(&cfg, "demo_app")?;
+        assert!(redacted.contains("export DEMO_APP_HTTP_PORT='9090'\n"));
+        assert!(redacted.contains(r"export DEMO_APP_DB_HOST='it'\''s.db'"));
+        assert!(redacted.contains("# db.password: redacted, pass --show-secrets"));
+        assert!(!redacted.contains("export DEMO_APP_DB_PASSWORD"));
+        assert!(!redacted.contains(REDACTED));
+        assert!(redacted.contains("# db.max_connections: has no env form"));
+        assert!(redacted.contains("# auth.enabled_auth_types: lists have no env form"));
+
+        let revealed = ExportEnvArgs { show_secrets: true }.render(&cfg, "demo_app")?;
+        assert!(revealed.contains(&format!(
+            "export DEMO_APP_DB_PASSWORD='{}'",
+            cfg.db.password
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_toml_path_creates_nested_tables_with_typed_values() -> Result<()> {
+        let mut table = "[http]\nport = 8080\n".parse::<toml::Table>()?;
+
+        set_toml_path(
+            &mut table,
+            "http.rate_limit.enable",
+            parse_toml_value("true"),
+        )?;
+        set_toml_path(&mut table, "db.host", parse_toml_value("10.0.0.1"))?;
+
+        let value = toml::Value::Table(table);
+        assert_eq!(
+            lookup_toml_path(&value, "http.rate_limit.enable"),
+            Some(&toml::Value::Boolean(true))
+        );
+        assert_eq!(
+            lookup_toml_path(&value, "http.port"),
+            Some(&toml::Value::Integer(8080))
+        );
+        assert_eq!(
+            lookup_toml_path(&value, "db.host"),
+            Some(&toml::Value::String("10.0.0.1".to_string()))
+        );
+
         Ok(())
     }
 }
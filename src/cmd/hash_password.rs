@@ -0,0 +1,61 @@
+use std::io::{self, BufRead, IsTerminal};
+
+use clap::Args;
+use sidecar::prelude::*;
+
+use crate::core::service::user::hash_password;
+
+/// Print the argon2 hash of a password, for seeding users or fixing credentials by hand
+#[derive(Args)]
+pub struct HashPasswordArgs {
+    #[arg(
+        long,
+        help = "Password to hash, prompted without echo or read from piped stdin when omitted"
+    )]
+    password: Option<String>,
+}
+
+impl HashPasswordArgs {
+    pub async fn run(self) -> Result<()> {
+        let password = match self.password {
+            Some(password) => password,
+            None if io::stdin().is_terminal() => prompt_password()?,
+            None => read_password(io::stdin().lock())?,
+        };
+        ensure!(!password.is_empty(), "password must not be empty");
+
+        println!("{}", hash_password(&password)?);
+        Ok(())
+    }
+}
+
+fn prompt_password() -> Result<String> {
+    let password = rpassword::prompt_password("Password: ")?;
+    let confirm = rpassword::prompt_password("Confirm password: ")?;
+    ensure!(password == confirm, "passwords do not match");
+    Ok(password)
+}
+
+/// First line of `reader` without the line ending
+fn read_password(mut reader: impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::service::user::verify_password;
+
+    #[test]
+    fn test_piped_password_hashes_without_line_ending() -> Result<()> {
+        let password = read_password(" secret \r\nignored\n".as_bytes())?;
+        assert_eq!(password, " secret ");
+
+        let hash = hash_password(&password)?;
+        assert!(verify_password(" secret ", &hash));
+
+        Ok(())
+    }
+}
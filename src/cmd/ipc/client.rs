@@ -1,39 +1,397 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use reqwest_middleware::ClientBuilder;
+use async_trait::async_trait;
+use axum::http::Extensions;
+use clap::Args;
+use humantime_serde::re::humantime;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest_middleware::{ClientBuilder, Middleware, Next};
+use reqwest_retry::RetryTransientMiddleware;
+use reqwest_retry::policies::ExponentialBackoff;
 use sidecar::prelude::*;
+use uuid::Uuid;
 
-use crate::api::http::client::apis::configuration;
-use crate::api::http::client::apis::system_api;
 use crate::api::http::client::apis::system_api::PingParams;
+use crate::api::http::client::apis::{self, configuration, system_api};
+use crate::api::http::client::envelope::{JsonEnvelope, response_data};
+
+/// Retry of transient failures (connection refused, 5xx, ...) so commands survive the short window
+/// where the server is restarting. Only idempotent requests are retried
+#[derive(Args, Clone, Debug)]
+pub struct RetryArgs {
+    #[arg(
+        long,
+        default_value_t = 3,
+        help = "Max retries of a failed ipc request, 0 disables retry"
+    )]
+    pub retries: u32,
+    #[arg(long, default_value = "100ms", value_parser = humantime::parse_duration, help = "Backoff before the first retry")]
+    pub retry_min_backoff: Duration,
+    #[arg(long, default_value = "2s", value_parser = humantime::parse_duration, help = "Upper bound of the backoff between retries")]
+    pub retry_max_backoff: Duration,
+}
+
+impl Default for RetryArgs {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            retry_min_backoff: Duration::from_millis(100),
+            retry_max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Pings before the command so it can be issued while the app is (re)starting, when the socket
+/// is briefly missing and the request retries above would fail at once
+#[derive(Args, Clone, Debug)]
+pub struct PingWaitArgs {
+    #[arg(
+        long,
+        default_value_t = 5,
+        help = "Pings before giving up on the app, 1 disables waiting"
+    )]
+    pub ping_attempts: u32,
+    #[arg(long, default_value = "400ms", value_parser = humantime::parse_duration, help = "Pause between pings")]
+    pub ping_interval: Duration,
+}
+
+impl Default for PingWaitArgs {
+    fn default() -> Self {
+        Self {
+            ping_attempts: 5,
+            ping_interval: Duration::from_millis(400),
+        }
+    }
+}
+
+impl RetryArgs {
+    fn policy(&self) -> Result<ExponentialBackoff> {
+        ensure!(
+            self.retry_min_backoff <= self.retry_max_backoff,
+            "--retry-min-backoff must not exceed --retry-max-backoff"
+        );
+        Ok(ExponentialBackoff::builder()
+            .retry_bounds(self.retry_min_backoff, self.retry_max_backoff)
+            .build_with_max_retries(self.retries))
+    }
+}
+
+/// Replays only idempotent requests, a POST such as user_register may have taken effect before
+/// its response was lost
+struct RetryIdempotent(RetryTransientMiddleware<ExponentialBackoff>);
+
+#[async_trait]
+impl Middleware for RetryIdempotent {
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        if req.method().is_idempotent() {
+            self.0.handle(req, extensions, next).await
+        } else {
+            next.run(req, extensions).await
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct IpcContext {
+    socket_path: PathBuf,
     pub configuration: configuration::Configuration,
+    /// Without retry, [`IpcContext::wait_ready`] repeats the ping itself
+    ping_configuration: configuration::Configuration,
+    /// Sent with every request of this invocation so it can be found in the server log
+    pub request_id: String,
 }
 
 impl IpcContext {
-    pub fn new(socket_path: PathBuf) -> Result<Self> {
+    pub fn new(socket_path: PathBuf, request_id_header: &str, retry: &RetryArgs) -> Result<Self> {
         let display_path = socket_path.display().to_string();
+        let request_id = Uuid::new_v4().simple().to_string();
+
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(
+            HeaderName::try_from(request_id_header)?,
+            HeaderValue::from_str(&request_id)?,
+        );
         let http_client = reqwest::Client::builder()
-            .unix_socket(socket_path)
+            .unix_socket(socket_path.clone())
+            .default_headers(default_headers)
             .build()
             .wrap_err_with(|| format!("Failed to build ipc client: {}", display_path))?;
-        let client = ClientBuilder::new(http_client).build();
+        let client = ClientBuilder::new(http_client.clone())
+            .with(RetryIdempotent(RetryTransientMiddleware::new_with_policy(
+                retry.policy()?,
+            )))
+            .build();
 
         let mut configuration = configuration::Configuration::new();
         configuration.base_path = "http://localhost".to_string();
         configuration.client = client;
+        let mut ping_configuration = configuration.clone();
+        ping_configuration.client = ClientBuilder::new(http_client).build();
 
-        Ok(Self { configuration })
+        Ok(Self {
+            socket_path,
+            configuration,
+            ping_configuration,
+            request_id,
+        })
+    }
+
+    /// [`IpcContext::ping`] until the app answers, at most `wait.ping_attempts` times
+    pub async fn wait_ready(&self, wait: &PingWaitArgs) -> Result<()> {
+        let attempts = wait.ping_attempts.max(1);
+        let mut last_err = None;
+        for attempt in 1..=attempts {
+            let result = if self.socket_path.exists() {
+                self.ping().await
+            } else {
+                Err(eyre!("IPC not exists: {}", self.socket_path.display()))
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) => last_err = Some(err),
+            }
+            if attempt < attempts {
+                tokio::time::sleep(wait.ping_interval).await;
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| eyre!("no ping attempted"))).wrap_err(format!(
+            "Failed to ping IPC after {attempts} attempts, app is not running: {}",
+            self.socket_path.display()
+        ))
+    }
+
+    /// GET an endpoint that has no generated client and return the envelope data
+    pub async fn get_json(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<serde_json::Value> {
+        response_data(self.send_get(path, query).await)
+    }
+
+    async fn send_get(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> std::result::Result<JsonEnvelope, apis::Error<()>> {
+        let uri = format!("{}{path}", self.configuration.base_path);
+        let resp = self
+            .configuration
+            .client
+            .get(uri)
+            .query(query)
+            .send()
+            .await?;
+        let status = resp.status();
+        let content = resp.text().await?;
+        if !status.is_success() {
+            return Err(apis::Error::ResponseError(apis::ResponseContent {
+                status,
+                content,
+                entity: None,
+            }));
+        }
+        Ok(serde_json::from_str(&content)?)
     }
 
     pub async fn ping(&self) -> Result<()> {
-        system_api::ping(&self.configuration, PingParams {
-            content: Some("ping".to_string()),
-        })
-        .await?;
+        response_data(
+            system_api::ping(&self.ping_configuration, PingParams {
+                content: Some("ping".to_string()),
+            })
+            .await,
+        )?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use axum::Router;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::{get, post};
+    use tempfile::tempdir;
+    use tokio::net::UnixListener;
+
+    use super::*;
+    use crate::api::http::client::{ApiError, models};
+
+    /// Answers 503 for the first `not_ready` requests, like a server that is still starting
+    async fn spawn_flaky_server(socket_path: &PathBuf, not_ready: u32) -> Result<Arc<AtomicU32>> {
+        let hits = Arc::new(AtomicU32::new(0));
+        let handler = {
+            let hits = hits.clone();
+            move || async move {
+                if hits.fetch_add(1, Ordering::SeqCst) < not_ready {
+                    StatusCode::SERVICE_UNAVAILABLE.into_response()
+                } else {
+                    axum::Json(serde_json::json!({"code": 0, "msg": "", "data": "ping"}))
+                        .into_response()
+                }
+            }
+        };
+        let router = Router::new()
+            .route("/ping", get(handler.clone()))
+            .route("/internal/status", get(handler.clone()))
+            .route("/api/v1/user/register", post(handler));
+        let listener = UnixListener::bind(socket_path)?;
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Ok(hits)
+    }
+
+    fn fast_retry(retries: u32) -> RetryArgs {
+        RetryArgs {
+            retries,
+            retry_min_backoff: Duration::from_millis(1),
+            retry_max_backoff: Duration::from_millis(5),
+        }
+    }
+
+    #[tokio::test]
+    async fn get_retries_until_server_ready() -> Result<()> {
+        let tmp = tempdir()?;
+        let socket_path = tmp.path().join("ipc.sock");
+        let hits = spawn_flaky_server(&socket_path, 2).await?;
+
+        let ctx = IpcContext::new(socket_path, "X-Request-Id", &fast_retry(3))?;
+        ctx.get_json("/internal/status", &[]).await?;
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_fails_when_retries_exhausted() -> Result<()> {
+        let tmp = tempdir()?;
+        let socket_path = tmp.path().join("ipc.sock");
+        let hits = spawn_flaky_server(&socket_path, 2).await?;
+
+        let ctx = IpcContext::new(socket_path, "X-Request-Id", &fast_retry(0))?;
+        assert!(ctx.get_json("/internal/status", &[]).await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ping_and_post_are_not_retried() -> Result<()> {
+        let tmp = tempdir()?;
+        let socket_path = tmp.path().join("ipc.sock");
+        let hits = spawn_flaky_server(&socket_path, 2).await?;
+        let ctx = IpcContext::new(socket_path, "X-Request-Id", &fast_retry(3))?;
+
+        assert!(ctx.ping().await.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        let register =
+            apis::user_api::user_register(&ctx.configuration, apis::user_api::UserRegisterParams {
+                register_req: models::RegisterReq::new(
+                    "alice".to_string(),
+                    "secret".to_string(),
+                    models::AuthType::Username,
+                    models::Role::User,
+                ),
+            })
+            .await;
+        assert!(register.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_ready_covers_socket_appearing_late() -> Result<()> {
+        let tmp = tempdir()?;
+        let socket_path = tmp.path().join("ipc.sock");
+        let ctx = IpcContext::new(socket_path.clone(), "X-Request-Id", &fast_retry(0))?;
+        let wait = PingWaitArgs {
+            ping_attempts: 20,
+            ping_interval: Duration::from_millis(20),
+        };
+
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            spawn_flaky_server(&socket_path, 0).await
+        });
+        ctx.wait_ready(&wait).await?;
+        assert_eq!(server.await??.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_ready_gives_up_after_attempts() -> Result<()> {
+        let tmp = tempdir()?;
+        let ctx = IpcContext::new(tmp.path().join("ipc.sock"), "X-Request-Id", &fast_retry(0))?;
+        let wait = PingWaitArgs {
+            ping_attempts: 3,
+            ping_interval: Duration::from_millis(10),
+        };
+
+        let err = ctx.wait_ready(&wait).await.unwrap_err();
+        assert!(err.to_string().contains("after 3 attempts"), "{err}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn envelope_errors_become_typed_api_errors() -> Result<()> {
+        let tmp = tempdir()?;
+        let socket_path = tmp.path().join("ipc.sock");
+        let router = Router::new()
+            .route(
+                "/ping",
+                get(|| async {
+                    axum::Json(serde_json::json!({"code": 10004, "msg": "ipc only", "data": null}))
+                }),
+            )
+            .route(
+                "/api/v1/user/refresh-token",
+                get(|| async {
+                    (
+                        StatusCode::UNAUTHORIZED,
+                        axum::Json(serde_json::json!({"code": 10003, "msg": "unauthorized"})),
+                    )
+                }),
+            );
+        let listener = UnixListener::bind(&socket_path)?;
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let ctx = IpcContext::new(socket_path, "X-Request-Id", &fast_retry(0))?;
+        let err = ctx.ping().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ApiError>(),
+            Some(&ApiError {
+                code: 10004,
+                msg: "ipc only".to_string(),
+            })
+        );
+
+        let err = response_data(apis::user_api::user_refresh_token(&ctx.configuration).await)
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ApiError>().map(|e| e.code), Some(10003));
+
+        Ok(())
+    }
+
+    #[test]
+    fn retry_rejects_inverted_backoff_bounds() {
+        let retry = RetryArgs {
+            retry_min_backoff: Duration::from_secs(5),
+            ..RetryArgs::default()
+        };
+        assert!(retry.policy().is_err());
+    }
+}
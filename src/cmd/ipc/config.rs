@@ -0,0 +1,37 @@
+use clap::{Args, Subcommand};
+use sidecar::prelude::*;
+
+use super::OutputFormat;
+use super::client::IpcContext;
+
+#[derive(Subcommand)]
+pub enum Cmd {
+    /// Print the config the running app loaded, including env and config_kv overrides
+    Show(ShowArgs),
+}
+
+#[derive(Args)]
+pub struct ShowArgs {
+    #[arg(
+        long,
+        help = "Print the db password and jwt hmac key instead of redacting them"
+    )]
+    show_secrets: bool,
+}
+
+pub async fn run(cmd: Cmd, ctx: IpcContext, format: OutputFormat) -> Result<()> {
+    match cmd {
+        Cmd::Show(args) => show(args, ctx, format).await,
+    }
+}
+
+async fn show(args: ShowArgs, ctx: IpcContext, format: OutputFormat) -> Result<()> {
+    let cfg = ctx
+        .get_json("/internal/config", &[(
+            "show_secrets",
+            args.show_secrets.to_string(),
+        )])
+        .await?;
+
+    format.print(serde_json::to_string_pretty(&cfg)?, &cfg)
+}
@@ -0,0 +1,34 @@
+use clap::Subcommand;
+use sidecar::prelude::*;
+
+use super::OutputFormat;
+use super::client::IpcContext;
+
+#[derive(Subcommand)]
+pub enum Cmd {
+    /// Print the connection pool utilization of the running app, to tell pool exhaustion apart
+    /// from a slow db
+    Stats,
+}
+
+pub async fn run(cmd: Cmd, ctx: IpcContext, format: OutputFormat) -> Result<()> {
+    match cmd {
+        Cmd::Stats => stats(ctx, format).await,
+    }
+}
+
+async fn stats(ctx: IpcContext, format: OutputFormat) -> Result<()> {
+    let stats = ctx.get_json("/internal/db/stats", &[]).await?;
+    let field = |name: &str| stats[name].as_u64().unwrap_or_default();
+
+    format.print(
+        format!(
+            "in_use: {}, idle: {}, size: {}, max: {}",
+            field("in_use"),
+            field("idle"),
+            field("size"),
+            field("max")
+        ),
+        &stats,
+    )
+}
@@ -1,31 +1,174 @@
-use clap::Subcommand;
+use std::fmt::Display;
+
+use clap::{Args, Subcommand, ValueEnum};
+use serde::Serialize;
+use serde_json::json;
 use sidecar::prelude::*;
 use sidecar::repo::Repo;
 
+use crate::api::http::client::ApiError;
 use crate::kit::config::Config;
 
 mod client;
+mod config;
+mod db;
 mod user;
 
+#[derive(Args, Clone, Debug)]
+pub struct IpcArgs {
+    #[command(flatten)]
+    pub retry: client::RetryArgs,
+    #[command(flatten)]
+    pub ping: client::PingWaitArgs,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        global = true,
+        help = "Output format of results and errors"
+    )]
+    pub format: OutputFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One json object per result, errors go to stderr as `{ "code", "msg", "request_id" }`
+    Json,
+}
+
+impl OutputFormat {
+    pub fn render(self, text: impl Display, value: &impl Serialize) -> Result<String> {
+        Ok(match self {
+            OutputFormat::Text => text.to_string(),
+            OutputFormat::Json => serde_json::to_string(value)?,
+        })
+    }
+
+    pub fn print(self, text: impl Display, value: &impl Serialize) -> Result<()> {
+        println!("{}", self.render(text, value)?);
+        Ok(())
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Cmd {
     #[command(subcommand)]
     User(user::Cmd),
+    #[command(subcommand)]
+    Config(config::Cmd),
+    #[command(subcommand)]
+    Db(db::Cmd),
+}
+pub async fn run(cmd: Cmd, args: IpcArgs, repo: Repo<Config>) -> Result<()> {
+    let IpcArgs {
+        retry,
+        ping,
+        format,
+    } = args;
+    let ctx = client::IpcContext::new(
+        repo.ipc_file_path(),
+        &repo.cfg.http.request_id_header,
+        &retry,
+    )?;
+    let request_id = ctx.request_id.clone();
+
+    let result = dispatch(cmd, ctx, &ping, format).await;
+    if let Err(err) = &result {
+        // stable lines for scripts, the report itself is printed by main
+        let api_err = err.downcast_ref::<ApiError>();
+        match format {
+            OutputFormat::Text => {
+                if let Some(api_err) = api_err {
+                    eprintln!("code: {}", api_err.code);
+                    eprintln!("msg: {}", api_err.msg);
+                    eprintln!("retryable: {}", api_err.is_retryable());
+                }
+                eprintln!("request_id: {request_id}");
+            }
+            OutputFormat::Json => eprintln!(
+                "{}",
+                json!({
+                    "code": api_err.map(|e| e.code),
+                    "msg": api_err.map_or_else(|| format!("{err:#}"), |e| e.msg.clone()),
+                    "retryable": api_err.is_some_and(|e| e.is_retryable()),
+                    "request_id": request_id,
+                })
+            ),
+        }
+    }
+    result
 }
-pub async fn run(cmd: Cmd, repo: Repo<Config>) -> Result<()> {
-    let socket_path = repo.ipc_file_path();
-    ensure!(
-        socket_path.exists(),
-        "IPC not exists, app is not running: {}",
-        socket_path.display()
-    );
-
-    let ctx = client::IpcContext::new(socket_path)?;
-    ctx.ping()
-        .await
-        .wrap_err("Failed to ping IPC, app is not running")?;
+
+async fn dispatch(
+    cmd: Cmd,
+    ctx: client::IpcContext,
+    ping: &client::PingWaitArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    ctx.wait_ready(ping).await?;
 
     match cmd {
-        Cmd::User(user_cmd) => user::run(user_cmd, ctx).await,
+        Cmd::User(user_cmd) => user::run(user_cmd, ctx, format).await,
+        Cmd::Config(config_cmd) => config::run(config_cmd, ctx, format).await,
+        Cmd::Db(db_cmd) => db::run(db_cmd, ctx, format).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        ipc: IpcArgs,
+        #[command(subcommand)]
+        cmd: Cmd,
+    }
+
+    #[test]
+    fn format_defaults_to_text_and_is_global() -> Result<()> {
+        let args = [
+            "app",
+            "user",
+            "register",
+            "--role",
+            "user",
+            "--auth-type",
+            "username",
+        ];
+        let args = args
+            .into_iter()
+            .chain(["--auth-id", "alice", "--auth-token", "secret"]);
+        let cli = TestCli::try_parse_from(args.clone())?;
+        assert_eq!(cli.ipc.format, OutputFormat::Text);
+
+        let cli = TestCli::try_parse_from(args.chain(["--format", "json"]))?;
+        assert_eq!(cli.ipc.format, OutputFormat::Json);
+        assert!(matches!(cli.cmd, Cmd::User(_)));
+
+        let cli = TestCli::try_parse_from(["app", "db", "stats", "--format", "json"])?;
+        assert!(matches!(cli.cmd, Cmd::Db(db::Cmd::Stats)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_json_is_structured() -> Result<()> {
+        let value = json!({ "user_id": "u1" });
+        assert_eq!(
+            OutputFormat::Text.render("user registered, user_id: u1", &value)?,
+            "user registered, user_id: u1"
+        );
+        assert_eq!(
+            OutputFormat::Json.render("ignored", &value)?,
+            r#"{"user_id":"u1"}"#
+        );
+        Ok(())
     }
 }
@@ -1,6 +1,7 @@
 use clap::Subcommand;
 use sidecar::prelude::*;
 
+use super::OutputFormat;
 use super::client::IpcContext;
 
 pub mod register;
@@ -9,8 +10,8 @@ pub mod register;
 pub enum Cmd {
     Register(register::RegisterArgs),
 }
-pub async fn run(cmd: Cmd, ctx: IpcContext) -> Result<()> {
+pub async fn run(cmd: Cmd, ctx: IpcContext, format: OutputFormat) -> Result<()> {
     match cmd {
-        Cmd::Register(args) => register::run(args, ctx).await,
+        Cmd::Register(args) => register::run(args, ctx, format).await,
     }
 }
@@ -1,10 +1,11 @@
 use clap::Args;
 use sidecar::prelude::*;
 
+use super::super::OutputFormat;
 use super::super::client::IpcContext;
-use crate::api::http::client::apis;
 use crate::api::http::client::apis::user_api::{self, UserRegisterParams};
-use crate::api::http::client::models;
+use crate::api::http::client::{ApiError, models, response_data};
+use crate::kit::error::Error;
 
 #[derive(Args)]
 pub struct RegisterArgs {
@@ -38,7 +39,7 @@ fn parse_role(value: &str) -> std::result::Result<models::Role, String> {
     }
 }
 
-pub async fn run(args: RegisterArgs, ctx: IpcContext) -> Result<()> {
+pub async fn run(args: RegisterArgs, ctx: IpcContext, format: OutputFormat) -> Result<()> {
     let RegisterArgs {
         auth_type,
         auth_id,
@@ -48,39 +49,100 @@ pub async fn run(args: RegisterArgs, ctx: IpcContext) -> Result<()> {
         desc,
     } = args;
 
-    let response = user_api::user_register(&ctx.configuration, UserRegisterParams {
-        register_req: models::RegisterReq {
-            auth_type,
-            auth_id,
-            auth_token,
-            role,
-            nickname: name,
-            desc,
-        },
-    })
-    .await
-    .map_err(|err| match err {
-        apis::Error::ResponseError(resp) => eyre!(
-            "Request failed，status code: {}，body: {}",
-            resp.status,
-            resp.content
-        ),
-        other => eyre!(other),
+    let account = format!("{auth_type} account {auth_id}");
+    let data = response_data(
+        user_api::user_register(&ctx.configuration, UserRegisterParams {
+            register_req: models::RegisterReq {
+                auth_type,
+                auth_id,
+                auth_token,
+                role,
+                nickname: name,
+                desc,
+            },
+        })
+        .await,
+    )
+    .map_err(|err| match err.downcast_ref::<ApiError>() {
+        Some(api_err) if api_err.is(&Error::UserAlreadyExists) => {
+            err.wrap_err(format!("{account} is already registered"))
+        }
+        _ => err,
     })?;
 
-    ensure!(
-        response.code == 0,
-        "Request api failed code: {}，msg: {}",
-        response.code,
-        response.msg
-    );
+    format.print(format!("user registered，user_id: {}", data.user_id), &data)?;
 
-    let data = response
-        .data
-        .map(|boxed| *boxed)
-        .ok_or_else(|| eyre!("Not found data"))?;
+    Ok(())
+}
 
-    println!("user registered，user_id: {}", data.user_id);
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::routing::post;
+    use tempfile::tempdir;
+    use tokio::net::UnixListener;
 
-    Ok(())
+    use super::*;
+    use crate::cmd::ipc::client::RetryArgs;
+
+    /// Run the command against a server answering every register with `err`
+    async fn register_rejected_with(err: Error) -> Result<Report> {
+        let tmp = tempdir()?;
+        let socket_path = tmp.path().join("ipc.sock");
+        let router = Router::new().route(
+            "/api/v1/user/register",
+            post(move || async move {
+                axum::Json(serde_json::json!({
+                    "code": err.code(),
+                    "msg": err.to_string(),
+                    "data": null,
+                }))
+            }),
+        );
+        let listener = UnixListener::bind(&socket_path)?;
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let ctx = IpcContext::new(socket_path, "X-Request-Id", &RetryArgs {
+            retries: 0,
+            ..RetryArgs::default()
+        })?;
+        let args = RegisterArgs {
+            role: models::Role::User,
+            auth_type: models::AuthType::Username,
+            auth_id: "alice".to_string(),
+            auth_token: "secret".to_string(),
+            name: None,
+            desc: None,
+        };
+
+        Ok(run(args, ctx, OutputFormat::Text).await.unwrap_err())
+    }
+
+    #[tokio::test]
+    async fn existing_account_is_reported_with_the_typed_error_kept() -> Result<()> {
+        let err = register_rejected_with(Error::UserAlreadyExists).await?;
+        assert_eq!(
+            err.to_string(),
+            "Username account alice is already registered"
+        );
+        assert!(
+            err.downcast_ref::<ApiError>()
+                .is_some_and(|api_err| api_err.is(&Error::UserAlreadyExists))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_parameter_is_not_reported_as_registered() -> Result<()> {
+        let err =
+            register_rejected_with(Error::InvidRequestParameter("auth_id".to_string())).await?;
+        assert!(!err.to_string().contains("already registered"), "{err}");
+        assert!(
+            err.downcast_ref::<ApiError>()
+                .is_some_and(|api_err| !api_err.is(&Error::UserAlreadyExists))
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,39 @@
+use clap::{Args, Subcommand};
+use sidecar::log;
+use sidecar::prelude::*;
+use sidecar::repo::Repo;
+
+use crate::kit::config::Config;
+
+#[derive(Subcommand)]
+pub enum Cmd {
+    Prune(PruneArgs),
+}
+
+pub async fn run(cmd: Cmd, repo: Repo<Config>) -> Result<()> {
+    match cmd {
+        Cmd::Prune(args) => args.run(repo).await,
+    }
+}
+
+/// Apply the `log.max_log_files` retention now instead of at the next rotation, e.g. after
+/// lowering the limit
+#[derive(Args)]
+pub struct PruneArgs {}
+
+impl PruneArgs {
+    pub async fn run(self, repo: Repo<Config>) -> Result<()> {
+        let log_dir = repo.root.join("logs");
+        let deleted = log::prune_log_files(&log_dir, repo.cfg.log.max_log_files)?;
+        for path in &deleted {
+            println!("deleted: {}", path.display());
+        }
+        println!(
+            "{} log file(s) deleted, keeping at most {} in {}",
+            deleted.len(),
+            repo.cfg.log.max_log_files,
+            log_dir.display()
+        );
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
 pub mod config;
+pub mod hash_password;
 pub mod ipc;
+pub mod logs;
 pub mod run;
@@ -1,14 +1,16 @@
+use std::path::Path;
 use std::sync::Arc;
 
 use clap::Args;
 use sidecar::prelude::*;
 use sidecar::repo::Repo;
 use sidecar::sidecar::Sidecar;
-use sidecar::{log, version};
-use tracing::{info, warn};
+use sidecar::{instance, log, version};
+use tracing::{error, info, warn};
 
 use crate::api::http::server::Server;
 use crate::core::core::Core;
+use crate::core::service::config_kv;
 use crate::kit::config::Config;
 
 pub struct App {
@@ -17,7 +19,10 @@ pub struct App {
 }
 
 impl App {
-    pub async fn new(sidecar: Sidecar, repo: Repo<Config>) -> Result<Self> {
+    pub async fn new(sidecar: Sidecar, mut repo: Repo<Config>) -> Result<Self> {
+        // components copy the config when built
+        config_kv::load_into(&mut repo).await?;
+
         // build components
 
         let core = Core::new(sidecar.clone(), repo.clone()).await?;
@@ -32,17 +37,46 @@ impl App {
 }
 
 #[derive(Args)]
-pub struct RunArgs {}
+pub struct RunArgs {
+    #[arg(
+        long,
+        help = "Fork into the background, detach from the terminal and write the pid file (unix only)"
+    )]
+    pub daemonize: bool,
+}
 
 impl RunArgs {
     pub async fn run(self, repo: Repo<Config>) -> Result<()> {
-        let _log_guard = log::setup(
+        instance::init(&repo.cfg.instance.id);
+        log::setup(
             repo.cfg.log.level,
             Some(repo.root.join("logs")),
             repo.cfg.log.max_log_files,
+            repo.cfg.log.install_panic_hook,
+            // a daemon's stdout is logs/daemon.out, the log files have every line already
+            !self.daemonize,
         );
+        // the repo was loaded before logging was set up, so its warning went nowhere
+        if let Some(warning) = repo.unknown_keys_warning() {
+            warn!("{warning}");
+        }
+
+        let result = self.run_app(repo).await;
+        if let Err(e) = &result {
+            error!("app exit with error: {:?}", e);
+        }
+
+        // make sure the last log lines reach the log file before the process exits
+        log::flush();
+
+        result
+    }
 
+    async fn run_app(self, repo: Repo<Config>) -> Result<()> {
         let sidecar = Sidecar::new();
+        if let Some(path) = &repo.cfg.log.lifecycle_events_path {
+            sidecar.log_lifecycle_events_to(&repo.root.join(path))?;
+        }
 
         let _app = App::new(sidecar.clone(), repo.clone()).await?;
 
@@ -55,6 +89,7 @@ impl RunArgs {
                     }
                     let v = version::current();
                     info!("repo_root: {}", repo.root.display());
+                    info!("instance_id: {}", instance::id());
                     info!("{} version: {}", v.app_name, v.version);
                     info!("git_branch：{}", v.git_branch);
                     info!("git_commit：{}", v.git_commit);
@@ -63,12 +98,106 @@ impl RunArgs {
             })
             .await;
 
-        sidecar.run().await?;
+        let result = sidecar.run().await;
 
+        // also on failure, a daemon's pid file is written before the app starts
         if let Err(e) = repo.remove_pid().await {
             warn!("failed to remove pid file: {}", e);
         }
 
-        Ok(())
+        result.map(|_| ())
+    }
+}
+
+/// Detach the process the traditional way: fork, `setsid`, fork again so the daemon can never
+/// reacquire a terminal. The launching process waits for the intermediate child, which writes the
+/// daemon pid to the pid file, prints the pid and exits, only the daemon returns from here.
+/// The daemon works in the repo root, stdin is /dev/null and stdout/stderr are appended to
+/// `logs/daemon.out` so output that bypasses the log files (e.g. early panics) isn't lost. The
+/// daemon logs without the console layer, so nothing else ends up there.
+/// Must be called while the process is still single threaded
+#[cfg(unix)]
+pub fn daemonize(repo_root: &Path) -> Result<()> {
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    let pid_path = repo_root.join(sidecar::repo::PID_FILE_NAME);
+    if let Some(pid) = running_pid(&pid_path) {
+        bail!(
+            "already running with pid {pid}, pid file: {}",
+            pid_path.display()
+        );
+    }
+
+    let log_dir = repo_root.join("logs");
+    fs::create_dir_all(&log_dir)?;
+    let out = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_dir.join("daemon.out"))?;
+    let null = File::open("/dev/null")?;
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()).wrap_err("fork failed"),
+        0 => {}
+        child => {
+            let mut status = 0;
+            if unsafe { libc::waitpid(child, &mut status, 0) } == -1 {
+                return Err(io::Error::last_os_error()).wrap_err("waitpid failed");
+            }
+            ensure!(
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+                "daemonize failed, intermediate process status: {status}"
+            );
+            let pid = fs::read_to_string(&pid_path)?;
+            println!("daemon started, pid: {pid}");
+            std::process::exit(0);
+        }
+    }
+
+    // intermediate child: new session without a controlling terminal
+    if unsafe { libc::setsid() } == -1 {
+        unsafe { libc::_exit(1) };
+    }
+    match unsafe { libc::fork() } {
+        -1 => unsafe { libc::_exit(1) },
+        0 => {}
+        daemon => {
+            let code = match fs::write(&pid_path, daemon.to_string()) {
+                Ok(()) => 0,
+                Err(_) => 1,
+            };
+            unsafe { libc::_exit(code) };
+        }
     }
+
+    std::env::set_current_dir(repo_root)?;
+    for (from, to) in [
+        (null.as_raw_fd(), libc::STDIN_FILENO),
+        (out.as_raw_fd(), libc::STDOUT_FILENO),
+        (out.as_raw_fd(), libc::STDERR_FILENO),
+    ] {
+        if unsafe { libc::dup2(from, to) } == -1 {
+            return Err(io::Error::last_os_error()).wrap_err("redirect stdio failed");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_repo_root: &Path) -> Result<()> {
+    bail!("--daemonize is only supported on unix")
+}
+
+/// Pid in `pid_path` if that process is still alive
+#[cfg(unix)]
+fn running_pid(pid_path: &Path) -> Option<i32> {
+    let pid = std::fs::read_to_string(pid_path)
+        .ok()?
+        .trim()
+        .parse::<i32>()
+        .ok()?;
+    (pid > 0 && unsafe { libc::kill(pid, 0) } == 0).then_some(pid)
 }
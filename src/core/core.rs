@@ -11,6 +11,8 @@ use crate::kit::config::Config;
 pub struct Core {
     pub sidecar: Sidecar,
     pub repo: Repo<Config>,
+    /// See [`sidecar::instance`]
+    pub instance_id: String,
 
     pub db: Arc<DB>,
     pub service: Arc<Service>,
@@ -24,6 +26,7 @@ impl Core {
         Ok(Arc::new(Core {
             sidecar: sidecar.with_component_name("core"),
             repo,
+            instance_id: sidecar::instance::id().to_string(),
             db,
             service,
         }))
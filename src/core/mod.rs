@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod core;
 pub mod db;
 pub mod model;
@@ -0,0 +1,38 @@
+use chrono::Local;
+use sea_orm::Set;
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::IndexCreateStatement;
+
+pub fn create_index_statements() -> Vec<IndexCreateStatement> {
+    vec![]
+}
+
+/// Cluster wide config value keyed by dotted path, e.g. `http.rate_limit.enable`
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "config_kv")]
+pub struct Model {
+    #[sea_orm(
+        primary_key,
+        column_type = "String(StringLen::N(255))",
+        auto_increment = false
+    )]
+    pub key: String,
+    #[sea_orm(column_type = "Text")]
+    pub value: String,
+    pub update_time: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl ActiveModel {
+    pub fn create(key: String, value: String) -> Self {
+        Self {
+            key: Set(key),
+            value: Set(value),
+            update_time: Set(Local::now().into()),
+        }
+    }
+}
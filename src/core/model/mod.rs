@@ -1,3 +1,4 @@
 pub mod common;
+pub mod config_kv;
 pub mod user;
 pub mod user_auth;
@@ -1,14 +1,35 @@
 use chrono::{Local, NaiveDateTime, TimeZone};
 use sea_orm::Set;
 use sea_orm::entity::prelude::*;
-use sea_orm::sea_query::IndexCreateStatement;
+use sea_orm::sea_query::{Index, IndexCreateStatement};
 use serde::{Deserialize, Serialize};
 
 use crate::core::model::common::DeleteState;
 use crate::core::model::common::DeleteState::Active;
 
 pub fn create_index_statements() -> Vec<IndexCreateStatement> {
-    vec![]
+    vec![
+        Index::create()
+            .name("user_public_id_index")
+            .table(Entity.table_ref())
+            .col(Column::PublicId)
+            .unique()
+            .if_not_exists()
+            .to_owned(),
+    ]
+}
+
+/// Bring a `user` table created by an earlier version up to [`Model`], run before
+/// [`create_index_statements`]. No-ops once applied, and while the table doesn't exist yet
+pub fn upgrade_statements() -> Vec<&'static str> {
+    vec![
+        // the volatile default gives every existing row its own id, then it's dropped so inserts
+        // must set one like `ActiveModel::create` does
+        r#"ALTER TABLE IF EXISTS "user" ADD COLUMN IF NOT EXISTS "public_id" varchar(255) NOT NULL DEFAULT replace(gen_random_uuid()::text, '-', '')"#,
+        r#"ALTER TABLE IF EXISTS "user" ALTER COLUMN "public_id" DROP DEFAULT"#,
+        // users of earlier versions belong to no tenant
+        r#"ALTER TABLE IF EXISTS "user" ADD COLUMN IF NOT EXISTS "tenant_id" varchar(255)"#,
+    ]
 }
 
 #[derive(
@@ -37,6 +58,7 @@ pub enum Status {
     Clone,
     PartialEq,
     Eq,
+    Hash,
     EnumIter,
     DeriveActiveEnum,
     Serialize,
@@ -53,6 +75,22 @@ pub enum Role {
     User,
 }
 
+impl Role {
+    /// Privilege level, higher outranks lower
+    pub fn rank(&self) -> u8 {
+        match self {
+            Role::Admin => 3,
+            Role::Manager => 2,
+            Role::User => 1,
+        }
+    }
+
+    /// Whether this role is `other` or above, e.g. `role.at_least(&Role::Manager)`
+    pub fn at_least(&self, other: &Role) -> bool {
+        self.rank() >= other.rank()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "user")]
 pub struct Model {
@@ -62,6 +100,12 @@ pub struct Model {
         auto_increment = false
     )]
     pub id: String,
+    /// Opaque id that can be handed out instead of `id`, e.g. as the jwt subject
+    #[sea_orm(column_type = "String(StringLen::N(255))")]
+    pub public_id: String,
+    /// Tenant the user belongs to, None outside of multi-tenant setups
+    #[sea_orm(column_type = "String(StringLen::N(255))", nullable)]
+    pub tenant_id: Option<String>,
     pub create_time: DateTimeWithTimeZone,
     pub update_time: DateTimeWithTimeZone,
     pub delete_time: DateTimeWithTimeZone,
@@ -87,6 +131,8 @@ impl ActiveModel {
         let now = Local::now().into();
         Self {
             id: Set(Uuid::new_v4().simple().to_string()),
+            public_id: Set(Uuid::new_v4().simple().to_string()),
+            tenant_id: Set(None),
             create_time: Set(now),
             update_time: Set(now),
             delete_time: Set(Local.from_utc_datetime(&NaiveDateTime::default()).into()),
@@ -99,3 +145,20 @@ impl ActiveModel {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_at_least_follows_hierarchy() {
+        assert!(Role::Admin.rank() > Role::Manager.rank());
+        assert!(Role::Manager.rank() > Role::User.rank());
+
+        assert!(Role::Admin.at_least(&Role::Manager));
+        assert!(Role::Manager.at_least(&Role::Manager));
+        assert!(Role::Manager.at_least(&Role::User));
+        assert!(!Role::User.at_least(&Role::Manager));
+        assert!(!Role::Manager.at_least(&Role::Admin));
+    }
+}
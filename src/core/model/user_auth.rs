@@ -1,24 +1,28 @@
 use chrono::{Local, NaiveDateTime, TimeZone};
 use sea_orm::Set;
 use sea_orm::entity::prelude::*;
-use sea_orm::sea_query::{Index, IndexCreateStatement};
+use sea_orm::sea_query::{Index, IndexCreateStatement, IndexDropStatement};
 use serde::{Deserialize, Serialize};
 
 use crate::core::model::common::DeleteState;
 use crate::core::model::common::DeleteState::Active;
 
+/// Enforces what register pre-checks, so concurrent registrations can't both succeed
+pub const AUTH_ID_UNIQUE_INDEX: &str = "user_auth_type_unique_index";
+
 pub fn create_index_statements() -> Vec<IndexCreateStatement> {
     vec![
         Index::create()
-            .name("user_auth_type_index")
-            .table(Entity::default().table_ref())
+            .name(AUTH_ID_UNIQUE_INDEX)
+            .table(Entity.table_ref())
             .col(Column::AuthType)
             .col(Column::AuthId)
+            .unique()
             .if_not_exists()
             .to_owned(),
         Index::create()
             .name("user_auth_user_id_index")
-            .table(Entity::default().table_ref())
+            .table(Entity.table_ref())
             .col(Column::UserId)
             .col(Column::AuthType)
             .if_not_exists()
@@ -26,6 +30,19 @@ pub fn create_index_statements() -> Vec<IndexCreateStatement> {
     ]
 }
 
+/// Indexes replaced by [`create_index_statements`], dropped after their replacement is created.
+/// Creating `user_auth_type_unique_index` fails while duplicated auth ids exist, which have to be
+/// cleaned up by hand before the app starts
+pub fn drop_legacy_index_statements() -> Vec<IndexDropStatement> {
+    vec![
+        Index::drop()
+            .name("user_auth_type_index")
+            .table(Entity.table_ref())
+            .if_exists()
+            .to_owned(),
+    ]
+}
+
 #[derive(
     Debug,
     Clone,
@@ -88,3 +105,33 @@ impl ActiveModel {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::DatabaseBackend;
+
+    use super::*;
+
+    #[test]
+    fn test_auth_id_index_is_unique() {
+        let ddl = create_index_statements()
+            .iter()
+            .map(|statement| DatabaseBackend::Postgres.build(statement).to_string())
+            .collect::<Vec<_>>();
+
+        assert!(
+            ddl[0].starts_with(
+                r#"CREATE UNIQUE INDEX IF NOT EXISTS "user_auth_type_unique_index" ON "user_auth" ("auth_type", "auth_id")"#
+            ),
+            "{ddl:?}"
+        );
+
+        let drop = DatabaseBackend::Postgres
+            .build(&drop_legacy_index_statements()[0])
+            .to_string();
+        assert!(
+            drop.contains(r#"DROP INDEX IF EXISTS "user_auth_type_index""#),
+            "{drop}"
+        );
+    }
+}
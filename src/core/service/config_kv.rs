@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{DatabaseConnection, EntityTrait};
+use sidecar::prelude::*;
+use sidecar::repo::{ConfigSource, Repo};
+use sidecar::sidecar::{Component, Sidecar};
+
+use crate::core::db::DB;
+use crate::core::model::config_kv;
+use crate::kit::config::Config;
+
+/// Sources config values from the `config_kv` table, which take precedence over file and env.
+/// Components copy the config when they are built, so [`load_into`] applies the values before
+/// that, once at startup. Values changed later take effect on the next start only
+pub struct Service {
+    _sidecar: Sidecar,
+    pub db: Arc<DB>,
+}
+
+impl Service {
+    pub async fn new(sidecar: Sidecar, db: Arc<DB>) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            _sidecar: sidecar.with_component_name("config-kv-service"),
+            db,
+        }))
+    }
+
+    pub async fn create_tables(&self) -> Result<()> {
+        self.db
+            .create_table::<config_kv::Entity>(config_kv::create_index_statements())
+            .await
+    }
+
+    pub async fn get_connection(&self) -> Result<DatabaseConnection> {
+        self.db.get_connection().await
+    }
+
+    /// Upsert a value, the latest write for a key wins
+    pub async fn set(&self, key: String, value: String) -> Result<()> {
+        let conn = self.get_connection().await?;
+        config_kv::Entity::insert(config_kv::ActiveModel::create(key, value))
+            .on_conflict(
+                OnConflict::column(config_kv::Column::Key)
+                    .update_columns([config_kv::Column::Value, config_kv::Column::UpdateTime])
+                    .to_owned(),
+            )
+            .exec(&conn)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Reload `repo` with the `config_kv` values when the store is enabled, over a connection that
+/// is closed again before the components are built
+pub async fn load_into(repo: &mut Repo<Config>) -> Result<()> {
+    if !repo.cfg.config_store.enable {
+        return Ok(());
+    }
+    let sidecar = Sidecar::new();
+    let db = DB::new(sidecar.clone(), repo.clone()).await?;
+    db.start().await?;
+    let result = apply(sidecar, repo, db.clone()).await;
+    db.stop().await?;
+    result
+}
+
+/// Reload `repo` with the `config_kv` values read through `db`
+pub async fn apply(sidecar: Sidecar, repo: &mut Repo<Config>, db: Arc<DB>) -> Result<()> {
+    let service = Service::new(sidecar, db).await?;
+    if repo.cfg.db.auto_create_tables {
+        service.create_tables().await?;
+    }
+    repo.reload_from(service.as_ref())
+        .await
+        .wrap_err("Reload config from config_kv failed")
+}
+
+#[async_trait]
+impl ConfigSource for Service {
+    async fn load(&self) -> Result<BTreeMap<String, String>> {
+        let conn = self.get_connection().await?;
+        let entries = config_kv::Entity::find().all(&conn).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect())
+    }
+}
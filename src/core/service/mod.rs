@@ -4,33 +4,39 @@ use async_trait::async_trait;
 use sidecar::prelude::*;
 use sidecar::repo::Repo;
 use sidecar::sidecar::{Component, Sidecar};
+use tracing::info;
 
 use crate::core::db::DB;
 use crate::kit::config::Config;
 
+pub mod config_kv;
 pub mod user;
 
 pub struct Service {
     sidecar: Sidecar,
-    _repo: Repo<Config>,
+    repo: Repo<Config>,
     pub db: Arc<DB>,
+    pub config_kv: Arc<config_kv::Service>,
     pub user: Arc<user::Service>,
 }
 
 impl Service {
     pub async fn new(sidecar: Sidecar, repo: Repo<Config>, db: Arc<DB>) -> Result<Arc<Self>> {
+        let config_kv_service =
+            config_kv::Service::new(sidecar.clone(), db.clone()).await?;
         let user_service = user::Service::new(sidecar.clone(), repo.clone(), db.clone()).await?;
 
-        let service = Arc::new(Self {
-            sidecar: sidecar.with_component_name("service"),
-            _repo: repo,
-            db,
-            user: user_service,
-        });
-
-        sidecar.register_component(service.clone()).await?;
-
-        Ok(service)
+        sidecar
+            .build_component("service", |sidecar| async move {
+                Ok(Self {
+                    sidecar,
+                    repo,
+                    db,
+                    config_kv: config_kv_service,
+                    user: user_service,
+                })
+            })
+            .await
     }
 }
 
@@ -41,7 +47,17 @@ impl Component for Service {
     }
 
     async fn start(&self) -> Result<()> {
-        self.user.create_tables().await?;
+        let auto_create_tables = self.repo.cfg.db.auto_create_tables;
+        if auto_create_tables {
+            self.user.create_tables().await?;
+        } else {
+            info!("db.auto_create_tables is disabled, skip creating tables");
+        }
+
+        // the values were applied by `config_kv::load_into` before the components were built
+        if self.repo.cfg.config_store.enable && auto_create_tables {
+            self.config_kv.create_tables().await?;
+        }
 
         Ok(())
     }
@@ -50,3 +66,34 @@ impl Component for Service {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_start_skips_table_creation_when_disabled() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<Config>::new(tmp.path(), "service-test").await?;
+        let sidecar = Sidecar::new();
+        // no connection, creating a table would fail with DBConnectionNotInitialized
+        let db = DB::new(sidecar.clone(), repo.clone()).await?;
+
+        assert!(
+            Service::new(sidecar.clone(), repo.clone(), db.clone())
+                .await?
+                .start()
+                .await
+                .is_err()
+        );
+
+        // a fresh sidecar, the first service already holds the name
+        repo.cfg.db.auto_create_tables = false;
+        let service = Service::new(Sidecar::new(), repo, db).await?;
+        service.start().await?;
+
+        Ok(())
+    }
+}
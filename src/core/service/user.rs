@@ -4,43 +4,100 @@ use argon2::{
     Argon2,
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
 };
+use chrono::{DateTime, Local};
+use futures::{Stream, StreamExt, stream};
+use sea_orm::sea_query::{Expr, ExprTrait};
 use sea_orm::{
-    ActiveEnum, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set,
+    ActiveEnum, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    EntityTrait, Order, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set,
     TransactionTrait,
 };
 use sidecar::prelude::*;
 use sidecar::repo::Repo;
 use sidecar::sidecar::Sidecar;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 
-use crate::core::db::DB;
-use crate::core::model::user::Role;
-use crate::core::model::user_auth::{AuthType, Column};
+use crate::core::db::{DB, is_unique_violation, until_deadline};
+use crate::core::model::common::DeleteState;
+use crate::core::model::user::{Role, Status};
+use crate::core::model::user_auth::{AUTH_ID_UNIQUE_INDEX, AuthType, Column};
 use crate::core::model::{user, user_auth};
 use crate::kit::config::Config;
 use crate::kit::error::Error;
 
+/// Selects users for bulk operations, unset fields match everything. Deleted users never match
+#[derive(Debug, Clone, Default)]
+pub struct UserFilter {
+    pub role: Option<Role>,
+    pub status: Option<Status>,
+    /// Only users created before this time
+    pub created_before: Option<DateTime<Local>>,
+    /// Never matched, e.g. the admin running the operation
+    pub exclude_user_id: Option<String>,
+    /// Only users of this tenant
+    pub tenant_id: Option<String>,
+}
+
+impl UserFilter {
+    fn condition(&self) -> Condition {
+        let mut condition = Condition::all().add(user::Column::DelState.eq(DeleteState::Active));
+        if let Some(role) = &self.role {
+            condition = condition.add(user::Column::Role.eq(role.clone()));
+        }
+        if let Some(status) = &self.status {
+            condition = condition.add(user::Column::Status.eq(status.clone()));
+        }
+        if let Some(created_before) = self.created_before {
+            condition = condition.add(user::Column::CreateTime.lt(created_before));
+        }
+        if let Some(exclude_user_id) = &self.exclude_user_id {
+            condition = condition.add(user::Column::Id.ne(exclude_user_id.clone()));
+        }
+        if let Some(tenant_id) = &self.tenant_id {
+            condition = condition.add(user::Column::TenantId.eq(tenant_id.clone()));
+        }
+        condition
+    }
+}
+
 pub struct Service {
-    _sidecar: Sidecar,
-    _repo: Repo<Config>,
+    sidecar: Sidecar,
+    repo: Repo<Config>,
     pub db: Arc<DB>,
 }
 
 impl Service {
     pub async fn new(sidecar: Sidecar, repo: Repo<Config>, db: Arc<DB>) -> Result<Arc<Self>> {
         Ok(Arc::new(Self {
-            _sidecar: sidecar.with_component_name("user-service"),
-            _repo: repo,
+            sidecar: sidecar.with_component_name("user-service"),
+            repo,
             db,
         }))
     }
 
     pub async fn create_tables(&self) -> Result<()> {
+        self.upgrade_tables().await?;
         self.db
             .create_table::<user::Entity>(user::create_index_statements())
             .await?;
         self.db
             .create_table::<user_auth::Entity>(user_auth::create_index_statements())
             .await?;
+        self.db
+            .drop_indexes(user_auth::drop_legacy_index_statements())
+            .await?;
+        Ok(())
+    }
+
+    /// Add the columns tables of an earlier version lack, `create_table` skips existing tables
+    async fn upgrade_tables(&self) -> Result<()> {
+        for sql in user::upgrade_statements() {
+            self.db
+                .exec_str_sql(sql, None)
+                .await
+                .wrap_err_with(|| format!("Upgrade table failed: {sql}"))?;
+        }
         Ok(())
     }
 
@@ -48,6 +105,7 @@ impl Service {
         self.db.get_connection().await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn register(
         &self,
         auth_type: AuthType,
@@ -56,19 +114,15 @@ impl Service {
         role: Role,
         name: String,
         desc: String,
+        deadline: Option<Instant>,
     ) -> Result<String> {
+        self.ensure_auth_type_enabled(&auth_type)?;
         let conn = self.get_connection().await?;
 
         let auth_type_name = auth_type.to_value();
         let auth_id_for_error = auth_id.clone();
 
-        let user_auth: Option<user_auth::Model> = user_auth::Entity::find()
-            .filter(Column::AuthType.eq(auth_type.clone()))
-            .filter(Column::AuthId.eq(auth_id.clone()))
-            .one(&conn)
-            .await?;
-
-        if user_auth.is_some() {
+        if until_deadline(deadline, auth_exists(&conn, auth_type.clone(), auth_id.clone())).await? {
             return Err(Error::UserAlreadyExists).wrap_err(format!(
                 "auth_type: {}, auth_id: {}",
                 auth_type_name, auth_id_for_error
@@ -90,14 +144,30 @@ impl Service {
         match auth_type {
             AuthType::Username => {
                 // hash password
-                user_auth.auth_token = Set(hash_password(&auth_token)?);
+                user_auth.auth_token = Set(self.hash_password(auth_token).await?);
             }
         }
 
-        let txn = conn.begin().await?;
-        user.insert(&txn).await?;
-        user_auth.insert(&txn).await?;
-        txn.commit().await?;
+        let insert = self.db.with_retry(|| async {
+            let txn = conn.begin().await?;
+            user.clone().insert(&txn).await?;
+            user_auth.clone().insert(&txn).await?;
+            txn.commit().await?;
+            Ok(())
+        });
+        until_deadline(deadline, insert)
+            .await
+            .map_err(|err| {
+                // a concurrent registration of the same auth_id got past the pre-check too
+                if is_unique_violation(&err, AUTH_ID_UNIQUE_INDEX) {
+                    err.wrap_err(Error::UserAlreadyExists).wrap_err(format!(
+                        "auth_type: {}, auth_id: {}",
+                        auth_type_name, auth_id_for_error
+                    ))
+                } else {
+                    err
+                }
+            })?;
 
         Ok(user_id)
     }
@@ -107,17 +177,15 @@ impl Service {
         auth_type: AuthType,
         auth_id: String,
         auth_token: String,
+        deadline: Option<Instant>,
     ) -> Result<String> {
+        self.ensure_auth_type_enabled(&auth_type)?;
         let conn = self.get_connection().await?;
 
         let auth_type_name = auth_type.to_value();
         let auth_id_for_error = auth_id.clone();
 
-        let user_auth: Option<user_auth::Model> = user_auth::Entity::find()
-            .filter(Column::AuthType.eq(auth_type))
-            .filter(Column::AuthId.eq(auth_id.clone()))
-            .one(&conn)
-            .await?;
+        let user_auth = until_deadline(deadline, find_user_auth(&conn, auth_type, auth_id)).await?;
 
         let Some(user_auth) = user_auth else {
             return Err(Error::UserNotFound).wrap_err(format!(
@@ -126,7 +194,10 @@ impl Service {
             ));
         };
 
-        if !verify_password(&auth_token, &user_auth.auth_token) {
+        if !self
+            .verify_password(auth_token, user_auth.auth_token.clone())
+            .await?
+        {
             return Err(Error::UserInvalidPassword).wrap_err(format!(
                 "auth_type: {}, auth_id: {}",
                 auth_type_name, auth_id_for_error
@@ -136,14 +207,276 @@ impl Service {
         Ok(user_auth.user_id.clone())
     }
 
-    pub async fn info(&self, user_id: String) -> Result<user::Model> {
+    /// Argon2 takes tens of milliseconds of CPU, run on the blocking pool instead of a worker
+    async fn hash_password(&self, password: String) -> Result<String> {
+        self.sidecar
+            .spawn_blocking_core("hash_password", move || hash_password(&password))
+            .join()
+            .await?
+    }
+
+    async fn verify_password(&self, password: String, hash: String) -> Result<bool> {
+        self.sidecar
+            .spawn_blocking_core("verify_password", move || verify_password(&password, &hash))
+            .join()
+            .await
+    }
+
+    fn ensure_auth_type_enabled(&self, auth_type: &AuthType) -> Result<()> {
+        if self.repo.cfg.auth.enabled_auth_types.contains(auth_type) {
+            return Ok(());
+        }
+        Err(Error::AuthTypeDisabled).wrap_err(format!("auth_type: {}", auth_type.to_value()))
+    }
+
+    /// Whether `auth_id` is registered, only the id of the row is fetched
+    pub async fn auth_exists(
+        &self,
+        auth_type: AuthType,
+        auth_id: String,
+        deadline: Option<Instant>,
+    ) -> Result<bool> {
         let conn = self.get_connection().await?;
-        if let Some(res) = user::Entity::find_by_id(user_id.clone()).one(&conn).await? {
+        until_deadline(deadline, auth_exists(&conn, auth_type, auth_id)).await
+    }
+
+    /// Whether `auth_id` is still free, matched exactly like the uniqueness check of `register`
+    pub async fn is_auth_id_available(
+        &self,
+        auth_type: AuthType,
+        auth_id: String,
+        deadline: Option<Instant>,
+    ) -> Result<bool> {
+        Ok(!self.auth_exists(auth_type, auth_id, deadline).await?)
+    }
+
+    pub async fn info(&self, user_id: String, deadline: Option<Instant>) -> Result<user::Model> {
+        let conn = self.get_connection().await?;
+        let query = user::Entity::find_by_id(user_id.clone())
+            .filter(user::Column::DelState.eq(DeleteState::Active))
+            .one(&conn);
+        if let Some(res) = until_deadline(deadline, async { Ok(query.await?) }).await? {
             Ok(res)
         } else {
             Err(Error::UserNotFound).wrap_err(format!("user_id: {}", user_id))
         }
     }
+
+    pub async fn info_by_public_id(
+        &self,
+        public_id: String,
+        deadline: Option<Instant>,
+    ) -> Result<user::Model> {
+        let conn = self.get_connection().await?;
+        let query = user::Entity::find()
+            .filter(user::Column::PublicId.eq(public_id.clone()))
+            .filter(user::Column::DelState.eq(DeleteState::Active))
+            .one(&conn);
+        if let Some(res) = until_deadline(deadline, async { Ok(query.await?) }).await? {
+            Ok(res)
+        } else {
+            Err(Error::UserNotFound).wrap_err(format!("public_id: {}", public_id))
+        }
+    }
+
+    pub async fn count_matching(
+        &self,
+        filter: &UserFilter,
+        deadline: Option<Instant>,
+    ) -> Result<u64> {
+        let conn = self.get_connection().await?;
+        until_deadline(deadline, count_matching(&conn, filter)).await
+    }
+
+    /// One page of the users matching `filter` and the total number of matches. Ties of
+    /// `order_by` are broken by id so pages don't overlap
+    pub async fn list(
+        &self,
+        filter: &UserFilter,
+        offset: u64,
+        limit: u64,
+        order_by: Option<(user::Column, Order)>,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<user::Model>, u64)> {
+        let conn = self.get_connection().await?;
+        let query = user::Entity::find().filter(filter.condition());
+
+        until_deadline(deadline, async {
+            let total = query.clone().count(&conn).await?;
+            let users = query
+                .apply_if(order_by, |query, (column, order)| {
+                    query.order_by(column, order)
+                })
+                .order_by_asc(user::Column::Id)
+                .offset(offset)
+                .limit(limit)
+                .all(&conn)
+                .await?;
+            Ok((users, total))
+        })
+        .await
+    }
+
+    /// Soft delete the users matching `filter`, only when `confirm` equals the number of matched
+    /// users, as returned by [`Service::count_matching`]. The transaction takes no row locks, so
+    /// users changed after the count can still match the delete; the delete is rolled back
+    /// unless it touched exactly `confirm` users
+    pub async fn soft_delete_matching(
+        &self,
+        filter: &UserFilter,
+        confirm: u64,
+        deadline: Option<Instant>,
+    ) -> Result<u64> {
+        let conn = self.get_connection().await?;
+        // the transaction is dropped at the deadline, which rolls it back
+        until_deadline(deadline, soft_delete_matching(&conn, filter, confirm)).await
+    }
+
+    /// Stream all users that aren't deleted without buffering them. The db stream runs in a core
+    /// task, so shutdown waits for it, and stops as soon as the returned stream is dropped.
+    /// `active` keeps only users whose status is (or with false, is not) active. `deadline` bounds
+    /// opening the stream, not the download of the rows
+    pub async fn export(
+        &self,
+        filter: &UserFilter,
+        active: Option<bool>,
+        deadline: Option<Instant>,
+    ) -> Result<impl Stream<Item = Result<user::Model>> + Send + 'static> {
+        let conn = self.get_connection().await?;
+        let condition = filter.condition();
+        let (tx, rx) = mpsc::channel::<Result<user::Model>>(EXPORT_BUFFER_SIZE);
+
+        self.sidecar.spawn_core_task("user-export", async move {
+            let users = user::Entity::find()
+                .filter(condition)
+                .apply_if(active, |query, active| match active {
+                    true => query.filter(user::Column::Status.eq(Status::Active)),
+                    false => query.filter(user::Column::Status.ne(Status::Active)),
+                })
+                .order_by_asc(user::Column::CreateTime)
+                .stream(&conn);
+            let users = until_deadline(deadline, async { Ok(users.await?) }).await;
+            let mut users = match users {
+                Ok(users) => users,
+                Err(err) => {
+                    _ = tx.send(Err(err)).await;
+                    return;
+                }
+            };
+            while let Some(user) = users.next().await {
+                if tx.send(user.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|user| (user, rx))
+        }))
+    }
+}
+
+const EXPORT_BUFFER_SIZE: usize = 64;
+
+async fn count_matching(conn: &impl ConnectionTrait, filter: &UserFilter) -> Result<u64> {
+    Ok(user::Entity::find()
+        .filter(filter.condition())
+        .count(conn)
+        .await?)
+}
+
+async fn soft_delete_matching(
+    conn: &DatabaseConnection,
+    filter: &UserFilter,
+    confirm: u64,
+) -> Result<u64> {
+    let txn = conn.begin().await?;
+
+    let matched = count_matching(&txn, filter).await?;
+    if matched != confirm {
+        return Err(Error::InvidRequestParameter(format!(
+            "confirm {confirm} does not match {matched} matched users"
+        ))
+        .into());
+    }
+
+    let now = Local::now().fixed_offset();
+    // the identities go first, the matched users are no longer active afterwards
+    user_auth::Entity::update_many()
+        .col_expr(
+            Column::DelState,
+            Expr::value(DeleteState::Deleted.to_value()),
+        )
+        .col_expr(Column::DeleteTime, Expr::value(now))
+        .col_expr(Column::UpdateTime, Expr::value(now))
+        .col_expr(Column::Version, Expr::col(Column::Version).add(1))
+        .filter(
+            Column::UserId.in_subquery(
+                user::Entity::find()
+                    .select_only()
+                    .column(user::Column::Id)
+                    .filter(filter.condition())
+                    .into_query(),
+            ),
+        )
+        .exec(&txn)
+        .await?;
+    let res = user::Entity::update_many()
+        .col_expr(
+            user::Column::DelState,
+            Expr::value(DeleteState::Deleted.to_value()),
+        )
+        .col_expr(user::Column::DeleteTime, Expr::value(now))
+        .col_expr(user::Column::UpdateTime, Expr::value(now))
+        .col_expr(
+            user::Column::Version,
+            Expr::col(user::Column::Version).add(1),
+        )
+        .filter(filter.condition())
+        .exec(&txn)
+        .await?;
+    if res.rows_affected != confirm {
+        // dropping the transaction rolls back both updates
+        return Err(Error::InvidRequestParameter(format!(
+            "confirm {confirm} does not match {} matched users",
+            res.rows_affected
+        ))
+        .into());
+    }
+    txn.commit().await?;
+
+    Ok(res.rows_affected)
+}
+
+/// Load the active auth identity for login. Register and availability checks only need to
+/// know it exists, see [`auth_exists`]
+async fn find_user_auth(
+    conn: &DatabaseConnection,
+    auth_type: AuthType,
+    auth_id: String,
+) -> Result<Option<user_auth::Model>> {
+    Ok(user_auth::Entity::find()
+        .filter(Column::AuthType.eq(auth_type))
+        .filter(Column::AuthId.eq(auth_id))
+        .filter(Column::DelState.eq(DeleteState::Active))
+        .one(conn)
+        .await?)
+}
+
+async fn auth_exists(
+    conn: &DatabaseConnection,
+    auth_type: AuthType,
+    auth_id: String,
+) -> Result<bool> {
+    let id = user_auth::Entity::find()
+        .select_only()
+        .column(Column::Id)
+        .filter(Column::AuthType.eq(auth_type))
+        .filter(Column::AuthId.eq(auth_id))
+        .into_tuple::<String>()
+        .one(conn)
+        .await?;
+    Ok(id.is_some())
 }
 
 pub fn hash_password(password: &str) -> Result<String> {
@@ -164,7 +497,17 @@ pub fn verify_password(password: &str, hash: &str) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
+    use sea_orm::sqlx::postgres::PgPoolOptions;
+    use sea_orm::{
+        DatabaseBackend, MockDatabase, MockExecResult, Set, SqlxPostgresConnector, TryIntoModel,
+        Value,
+    };
+    use tempfile::tempdir;
+
     use super::*;
+    use crate::kit::test_support::db_error;
 
     #[test]
     fn test_password_hash_and_verify() {
@@ -175,4 +518,423 @@ mod tests {
         assert!(verify_password(password, &hashed));
         assert!(!verify_password("wrong-password", &hashed));
     }
+
+    #[tokio::test]
+    async fn test_auth_exists_selects_only_the_id() -> Result<()> {
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([vec![BTreeMap::from([(
+                    "id",
+                    Value::String(Some("auth-1".to_string())),
+                )])]])
+                .append_query_results([Vec::<BTreeMap<&str, Value>>::new()]),
+        )
+        .await?;
+
+        assert!(
+            service
+                .auth_exists(AuthType::Username, "alice".to_string(), None)
+                .await?
+        );
+        assert!(
+            !service
+                .auth_exists(AuthType::Username, "bob".to_string(), None)
+                .await?
+        );
+
+        let log = service.db.get_connection().await?.into_transaction_log();
+        let select = log[0].statements()[0].to_string();
+        assert!(
+            select.starts_with(r#"SELECT "user_auth"."id" FROM "user_auth""#),
+            "{select}"
+        );
+        assert!(select.ends_with("LIMIT 1"), "{select}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_registered_auth_id_is_unavailable() -> Result<()> {
+        let mut auth = user_auth::ActiveModel::create();
+        auth.user_id = Set("user-1".to_string());
+        auth.auth_id = Set("alice".to_string());
+        let auth = auth.try_into_model()?;
+
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([vec![auth]])
+                .append_query_results([Vec::<user_auth::Model>::new()]),
+        )
+        .await?;
+
+        assert!(
+            !service
+                .is_auth_id_available(AuthType::Username, "alice".to_string(), None)
+                .await?
+        );
+        assert!(
+            service
+                .is_auth_id_available(AuthType::Username, "bob".to_string(), None)
+                .await?
+        );
+
+        Ok(())
+    }
+
+    async fn service_with(db: MockDatabase) -> Result<(Arc<Service>, tempfile::TempDir)> {
+        service_with_config(db, |_| {}).await
+    }
+
+    async fn service_with_config(
+        db: MockDatabase,
+        configure: impl FnOnce(&mut Config),
+    ) -> Result<(Arc<Service>, tempfile::TempDir)> {
+        let tmp = tempdir()?;
+        let mut repo = Repo::<Config>::new(tmp.path(), "user-service-test").await?;
+        configure(&mut repo.cfg);
+        let db_component = DB::new(Sidecar::new(), repo.clone()).await?;
+        db_component.set_connection(db.into_connection()).await;
+        Ok((Service::new(Sidecar::new(), repo, db_component).await?, tmp))
+    }
+
+    fn count_row(count: i64) -> Vec<BTreeMap<&'static str, Value>> {
+        vec![BTreeMap::from([("num_items", Value::BigInt(Some(count)))])]
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_adds_missing_user_columns() -> Result<()> {
+        let statements = user::upgrade_statements();
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres).append_exec_results(
+                statements.iter().map(|_| MockExecResult {
+                    last_insert_id: 0,
+                    rows_affected: 0,
+                }),
+            ),
+        )
+        .await?;
+
+        service.upgrade_tables().await?;
+
+        let log = service.db.get_connection().await?.into_transaction_log();
+        let executed = log
+            .iter()
+            .map(|transaction| transaction.statements()[0].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(executed, statements);
+        assert!(executed[0].contains(r#"ADD COLUMN IF NOT EXISTS "public_id""#));
+        assert!(executed[2].contains(r#"ADD COLUMN IF NOT EXISTS "tenant_id""#));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_delete_dry_run_count_then_confirmed_delete() -> Result<()> {
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([count_row(3), count_row(3)])
+                .append_exec_results([
+                    MockExecResult {
+                        last_insert_id: 0,
+                        rows_affected: 4,
+                    },
+                    MockExecResult {
+                        last_insert_id: 0,
+                        rows_affected: 3,
+                    },
+                ]),
+        )
+        .await?;
+        let filter = UserFilter {
+            role: Some(Role::User),
+            ..Default::default()
+        };
+
+        let matched = service.count_matching(&filter, None).await?;
+        assert_eq!(matched, 3);
+        assert_eq!(service.soft_delete_matching(&filter, matched, None).await?, 3);
+
+        // the identities of the deleted users are deleted with them
+        let log = service.get_connection().await?.into_transaction_log();
+        let delete = log[1].statements()[2].to_string();
+        assert!(delete.starts_with(r#"UPDATE "user_auth""#), "{delete}");
+        assert!(delete.contains(r#""user_id" IN (SELECT"#), "{delete}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_with_order_and_total() -> Result<()> {
+        let user = user::ActiveModel::create().try_into_model()?;
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([count_row(7)])
+                .append_query_results([vec![user.clone()]]),
+        )
+        .await?;
+
+        let (users, total) = service
+            .list(
+                &UserFilter::default(),
+                5,
+                1,
+                Some((user::Column::Name, Order::Desc)),
+                None,
+            )
+            .await?;
+        assert_eq!((users, total), (vec![user], 7));
+
+        let log = service.db.get_connection().await?.into_transaction_log();
+        let select = log[1].statements()[0].to_string();
+        assert!(
+            select.contains(r#"ORDER BY "user"."name" DESC, "user"."id" ASC LIMIT 1 OFFSET 5"#),
+            "{select}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_scoped_by_tenant() -> Result<()> {
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([count_row(0)])
+                .append_query_results([Vec::<user::Model>::new()]),
+        )
+        .await?;
+        let filter = UserFilter {
+            tenant_id: Some("acme".to_string()),
+            ..Default::default()
+        };
+
+        service.list(&filter, 0, 10, None, None).await?;
+
+        let log = service.db.get_connection().await?.into_transaction_log();
+        for transaction in &log {
+            let statement = transaction.statements()[0].to_string();
+            assert!(
+                statement.contains(r#""user"."tenant_id" = 'acme'"#),
+                "{statement}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_delete_rejects_confirm_mismatch() -> Result<()> {
+        // no exec result is queued, an update would fail the test with a different error
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres).append_query_results([count_row(5)]),
+        )
+        .await?;
+
+        let err = service
+            .soft_delete_matching(&UserFilter::default(), 3, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::InvidRequestParameter(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicate_register_is_user_already_exists() -> Result<()> {
+        let user = user::ActiveModel::create().try_into_model()?;
+        // the other registration committed between the pre-check and the insert
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([Vec::<BTreeMap<&str, Value>>::new()])
+                .append_query_results([vec![user]])
+                .append_query_errors([db_error("23505", Some(AUTH_ID_UNIQUE_INDEX))]),
+        )
+        .await?;
+
+        let err = service
+            .register(
+                AuthType::Username,
+                "alice".to_string(),
+                "secret".to_string(),
+                Role::User,
+                "alice".to_string(),
+                String::new(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(Error::UserAlreadyExists)),
+            "{err:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deleted_user_is_not_found_at_login_and_info() -> Result<()> {
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([Vec::<user_auth::Model>::new()])
+                .append_query_results([Vec::<user::Model>::new()])
+                .append_query_results([Vec::<user::Model>::new()]),
+        )
+        .await?;
+        let is_not_found = |err: Report| matches!(err.downcast_ref(), Some(Error::UserNotFound));
+
+        let err = service
+            .login(
+                AuthType::Username,
+                "alice".to_string(),
+                "secret".to_string(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(is_not_found(err));
+        assert!(is_not_found(
+            service.info("user-1".to_string(), None).await.unwrap_err()
+        ));
+        assert!(is_not_found(
+            service
+                .info_by_public_id("public-1".to_string(), None)
+                .await
+                .unwrap_err()
+        ));
+
+        let log = service.get_connection().await?.into_transaction_log();
+        assert_eq!(log.len(), 3);
+        for transaction in log {
+            let statement = transaction.statements()[0].to_string();
+            assert!(statement.contains(r#""del_state" = 0"#), "{statement}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_skips_deleted_users() -> Result<()> {
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([Vec::<user::Model>::new()]),
+        )
+        .await?;
+
+        let users = service
+            .export(&UserFilter::default(), None, None)
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        assert!(users.is_empty());
+
+        let log = service.get_connection().await?.into_transaction_log();
+        let statement = log[0].statements()[0].to_string();
+        assert!(statement.contains(r#""del_state" = 0"#), "{statement}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_is_scoped_to_the_tenant() -> Result<()> {
+        let (service, _tmp) = service_with(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([Vec::<user::Model>::new()]),
+        )
+        .await?;
+        let filter = UserFilter {
+            tenant_id: Some("tenant-a".to_string()),
+            ..Default::default()
+        };
+
+        let users = service
+            .export(&filter, None, None)
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        assert!(users.is_empty());
+
+        let log = service.get_connection().await?.into_transaction_log();
+        let statement = log[0].statements()[0].to_string();
+        assert!(
+            statement.contains(r#""user"."tenant_id" = 'tenant-a'"#),
+            "{statement}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queries_are_cancelled_at_the_request_deadline() -> Result<()> {
+        // the listener never answers the startup message, so every query hangs
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let pool = PgPoolOptions::new().connect_lazy(&format!(
+            "postgres://user:pass@{}/db",
+            listener.local_addr()?
+        ))?;
+        let (service, _tmp) = service_with(MockDatabase::new(DatabaseBackend::Postgres)).await?;
+        service
+            .db
+            .set_connection(SqlxPostgresConnector::from_sqlx_postgres_pool(pool))
+            .await;
+        let is_timeout = |err: Report| matches!(err.downcast_ref(), Some(Error::RequestTimeout));
+
+        let deadline = Some(Instant::now() + std::time::Duration::from_millis(50));
+        assert!(is_timeout(
+            service.info("user-1".to_string(), deadline).await.unwrap_err()
+        ));
+        assert!(is_timeout(
+            service
+                .count_matching(&UserFilter::default(), deadline)
+                .await
+                .unwrap_err()
+        ));
+        let users = service
+            .export(&UserFilter::default(), None, deadline)
+            .await?;
+        let users = users.collect::<Vec<_>>().await;
+        assert_eq!(users.len(), 1);
+        assert!(is_timeout(users.into_iter().next().unwrap().unwrap_err()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disabled_auth_type_is_rejected_at_register_and_login() -> Result<()> {
+        // no query results are queued, reaching the db would fail with a different error
+        let (service, _tmp) =
+            service_with_config(MockDatabase::new(DatabaseBackend::Postgres), |cfg| {
+                cfg.auth.enabled_auth_types = vec![];
+            })
+            .await?;
+        let is_disabled = |err: Report| matches!(err.downcast_ref(), Some(Error::AuthTypeDisabled));
+
+        let err = service
+            .register(
+                AuthType::Username,
+                "alice".to_string(),
+                "secret".to_string(),
+                Role::User,
+                "alice".to_string(),
+                String::new(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(is_disabled(err));
+
+        let err = service
+            .login(
+                AuthType::Username,
+                "alice".to_string(),
+                "secret".to_string(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(is_disabled(err));
+
+        Ok(())
+    }
 }
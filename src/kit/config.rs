@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
@@ -8,11 +9,19 @@ use sidecar::prelude::*;
 use sidecar::repo::IConfig;
 use tracing::Level;
 
+use crate::core::model::user::Role;
+use crate::core::model::user_auth::AuthType;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub db: DB,
     pub http: HTTP,
     pub log: Log,
+    pub config_store: ConfigStore,
+    pub ipc: IPC,
+    pub auth: Auth,
+    pub config_backup: ConfigBackup,
+    pub instance: Instance,
 }
 
 impl Default for Config {
@@ -28,32 +37,126 @@ impl Default for Config {
                 schema: "public".into(),
                 ssl_mode: "disable".into(),
                 log_sql: false,
+                max_connections: 10,
+                acquire_timeout: Duration::from_secs(30),
+                max_lifetime: Duration::from_secs(30 * 60),
+                warmup_connections: 0,
+                auto_create_schema: true,
+                auto_create_tables: true,
+                transient_retries: 3,
+                transient_retry_backoff: Duration::from_millis(20),
             },
             http: HTTP {
                 enable: false,
                 port: 8080,
+                base_path: "".to_string(),
                 swagger: Swagger {
                     enable: true,
                     host: "http://127.0.0.1".to_string(),
+                    disabled_redirect: None,
                 },
                 jwt: JWT {
                     token_valid_duration: Duration::from_secs(3 * 24 * 60 * 60),
                     token_hmac_key: "rs-project-startup-hmac-key@2509".to_string(),
+                    subject_source: SubjectSource::UserId,
+                    clock_skew: Duration::from_secs(5),
+                },
+                rate_limit: RateLimit {
+                    enable: false,
+                    window: Duration::from_secs(60),
+                    anonymous: 60,
+                    authenticated: 600,
+                    strict: 10,
+                },
+                login_backoff: LoginBackoff {
+                    enable: false,
+                    base_delay: Duration::from_millis(200),
+                    max_delay: Duration::from_secs(5),
+                    reset_after: Duration::from_secs(15 * 60),
+                },
+                request_id_header: "X-Request-Id".to_string(),
+                bigint_as_string: false,
+                proxy_protocol: false,
+                validate_against_spec: false,
+                instance_id_header: None,
+                trusted_proxies: vec![],
+                max_header_bytes: 64 * 1024,
+                max_header_count: 100,
+                tcp_nodelay: true,
+                keep_alive: true,
+                disabled_endpoints: vec![],
+                page_limits: HashMap::new(),
+                root_info: true,
+                request_timeout: Duration::from_secs(30),
+                json_limit: JsonLimit {
+                    max_depth: 32,
+                    max_fields: 1000,
+                },
+                concurrency: Concurrency {
+                    enable: false,
+                    max_in_flight: 512,
+                    ipc_reserved: 8,
+                    queue_timeout: Duration::from_secs(5),
                 },
             },
             log: Log {
                 level: Level::DEBUG,
                 max_log_files: 14,
+                install_panic_hook: true,
+                lifecycle_events_path: None,
+            },
+            config_store: ConfigStore {
+                enable: false,
+            },
+            ipc: IPC {
+                socket_mode: "600".to_string(),
+                uid: None,
+                gid: None,
+                allowed_operations: vec![],
+                rebind_check_interval: Duration::from_secs(5),
+            },
+            auth: Auth {
+                enabled_auth_types: vec![AuthType::Username],
+                entitlements: HashMap::from([
+                    (Role::Admin, vec![
+                        "user_export".to_string(),
+                        "user_bulk_delete".to_string(),
+                        "user_impersonate".to_string(),
+                    ]),
+                    (Role::Manager, vec![]),
+                    (Role::User, vec![]),
+                ]),
+            },
+            config_backup: ConfigBackup {
+                retention: sidecar::repo::DEFAULT_CONFIG_BACKUP_RETENTION,
             },
+            instance: Instance { id: String::new() },
         }
     }
 }
 
+/// Placeholder for secret values in config dumps
+pub const REDACTED: &str = "******";
+
+impl Config {
+    /// Copy with secrets (db password, jwt hmac key) replaced by [`REDACTED`], for dumps and logs
+    pub fn redacted(&self) -> Self {
+        let mut cfg = self.clone();
+        cfg.db.password = REDACTED.to_string();
+        cfg.http.jwt.token_hmac_key = REDACTED.to_string();
+        cfg
+    }
+}
+
 #[async_trait]
 impl IConfig for Config {
     async fn init(&mut self, _repo_root: PathBuf) -> Result<()> {
         Ok(())
     }
+
+    fn config_backup_retention(&self) -> usize {
+        self.config_backup.retention
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -67,12 +170,37 @@ pub struct DB {
     pub schema: String,
     pub ssl_mode: String,
     pub log_sql: bool,
+    pub max_connections: u32,
+    /// Max wait for a free pooled connection before the query fails
+    #[serde(with = "humantime_serde")]
+    pub acquire_timeout: Duration,
+    /// Pooled connections are closed and reopened once this old, before a proxy or the server
+    /// drops them and the next query fails with "connection reset by peer"
+    #[serde(with = "humantime_serde")]
+    pub max_lifetime: Duration,
+    /// Connections opened at startup instead of lazily on the first queries, capped by
+    /// `max_connections`, 0 disables
+    pub warmup_connections: u32,
+    /// Create `schema` on startup when it's missing, `public` is left alone. An existing schema
+    /// needs no CREATE right on the database
+    pub auto_create_schema: bool,
+    /// Create missing tables and indexes on startup, disable when the schema is managed outside
+    /// the app or its db user has no DDL rights
+    pub auto_create_tables: bool,
+    /// Replays of a transaction aborted by a serialization failure or deadlock, 0 disables
+    pub transient_retries: u32,
+    /// Backoff before the first replay, doubled on every further one
+    #[serde(with = "humantime_serde")]
+    pub transient_retry_backoff: Duration,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Swagger {
     pub enable: bool,
     pub host: String,
+    /// Where requests to `/swagger-ui` are redirected while swagger is disabled, they get a
+    /// plain 404 when unset. Either way they aren't logged
+    pub disabled_redirect: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -80,14 +208,129 @@ pub struct JWT {
     #[serde(with = "humantime_serde")]
     pub token_valid_duration: Duration,
     pub token_hmac_key: String,
+    /// What the `sub` claim of issued tokens holds
+    pub subject_source: SubjectSource,
+    /// Tolerated clock difference between instances. Issued tokens are valid from this long
+    /// before now, and `nbf`/`exp` are checked with this much leeway
+    #[serde(with = "humantime_serde")]
+    pub clock_skew: Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubjectSource {
+    /// The internal user id
+    UserId,
+    /// The opaque `public_id` of the user, for integrations that must not see internal ids
+    PublicId,
+}
+
+/// Requests allowed per window, authenticated users are keyed by user id, anonymous traffic by
+/// client ip. A request whose token fails to authenticate counts as anonymous
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimit {
+    /// Turns on the anonymous and authenticated limits, `strict` applies either way
+    pub enable: bool,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    pub anonymous: u64,
+    pub authenticated: u64,
+    /// Extra limit of endpoints that reveal whether an account exists, e.g. check-availability
+    pub strict: u64,
+}
+
+/// Delay of failed logins per client ip, doubled on every consecutive failure
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginBackoff {
+    pub enable: bool,
+    /// Delay of the first failure
+    #[serde(with = "humantime_serde")]
+    pub base_delay: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_delay: Duration,
+    /// Failures are forgotten after this long without a new one
+    #[serde(with = "humantime_serde")]
+    pub reset_after: Duration,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HTTP {
     pub enable: bool,
     pub port: u64,
+    /// Serve every route under this prefix, e.g. "/myapp" behind a path based reverse proxy that
+    /// keeps the prefix. Empty serves at the root, ipc is never prefixed
+    pub base_path: String,
     pub swagger: Swagger,
     pub jwt: JWT,
+    pub rate_limit: RateLimit,
+    pub login_backoff: LoginBackoff,
+    pub request_id_header: String,
+    /// Render integer fields that can exceed 2^53, e.g. `expired_time`, as json strings for
+    /// javascript clients
+    pub bigint_as_string: bool,
+    /// Expect a PROXY protocol v1/v2 header on every tcp connection, e.g. behind AWS NLB, and take
+    /// the client address from it. Connections without one are dropped
+    pub proxy_protocol: bool,
+    /// Check json request and response bodies against the openapi spec, log and flag divergences
+    /// in the X-Spec-Divergence header. Dev only, bodies are buffered in full
+    pub validate_against_spec: bool,
+    /// Answer every request with the instance id in this header, e.g. "X-Instance-Id"
+    pub instance_id_header: Option<String>,
+    /// Proxy ips whose forwarded client ips and request ids are honored
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Upper bound of the request head size, larger requests are rejected with 431
+    pub max_header_bytes: usize,
+    pub max_header_count: usize,
+    /// Disable Nagle's algorithm on accepted connections, trades bandwidth for latency
+    pub tcp_nodelay: bool,
+    /// Reuse http/1 connections across requests, otherwise every response closes the connection
+    pub keep_alive: bool,
+    /// Operation ids of endpoints that answer with `FeatureDisabled`, e.g. ["user_register"]
+    #[serde(default)]
+    pub disabled_endpoints: Vec<String>,
+    /// Page limits of list endpoints by operation id, e.g. `[http.page_limits.user_list]`,
+    /// over the ones set in code
+    #[serde(default)]
+    pub page_limits: HashMap<String, PageLimits>,
+    /// Answer `/` with the app name, version and entry point links instead of 404
+    pub root_info: bool,
+    /// Deadline of api handlers, db queries made for a request are cancelled once it passes
+    #[serde(with = "humantime_serde")]
+    pub request_timeout: Duration,
+    pub json_limit: JsonLimit,
+    pub concurrency: Concurrency,
+}
+
+/// Unset fields keep the endpoint's own limit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct PageLimits {
+    pub default_limit: Option<u64>,
+    /// Larger limits are clamped instead of rejected
+    pub max_limit: Option<u64>,
+}
+
+/// Shape limits of json request bodies, on top of the body size limit
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JsonLimit {
+    /// Max nesting of objects and arrays
+    pub max_depth: usize,
+    /// Max object members in the whole body
+    pub max_fields: usize,
+}
+
+/// Api handlers running at once, ipc requests are served ahead of http ones under load. See
+/// `api::http::priority` for the tradeoffs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Concurrency {
+    pub enable: bool,
+    /// Handlers running at once across the ipc and http listeners
+    pub max_in_flight: usize,
+    /// Slots of `max_in_flight` that http requests can't take, kept for ipc
+    pub ipc_reserved: usize,
+    /// Longest an http request waits for a slot before it's answered with `TooManyRequests`
+    #[serde(with = "humantime_serde")]
+    pub queue_timeout: Duration,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -95,6 +338,88 @@ pub struct Log {
     #[serde(with = "level_serde")]
     pub level: Level,
     pub max_log_files: u64,
+    /// Chain a panic hook that logs panics through tracing, disable it to keep an existing hook
+    pub install_panic_hook: bool,
+    /// File that component start/stop transitions are appended to as json lines, besides the
+    /// normal log. Relative paths are under the repo root, unset disables
+    pub lifecycle_events_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IPC {
+    /// Octal permission bits applied to the ipc socket after bind, e.g. "660"
+    pub socket_mode: String,
+    /// Owner uid of the ipc socket, unchanged when unset
+    pub uid: Option<u32>,
+    /// Owner gid of the ipc socket, unchanged when unset
+    pub gid: Option<u32>,
+    /// Operation ids reachable over ipc, e.g. ["user_register"]. Empty allows every operation,
+    /// `ping` is always allowed since the ipc commands probe the app with it
+    #[serde(default)]
+    pub allowed_operations: Vec<String>,
+    /// How often to check that the socket file still exists and bind it again when it was
+    /// deleted, zero disables
+    #[serde(with = "humantime_serde")]
+    pub rebind_check_interval: Duration,
+}
+
+impl HTTP {
+    /// `base_path` without trailing slashes, None when routes are served at the root
+    pub fn base_path(&self) -> Result<Option<&str>> {
+        let base_path = self.base_path.trim_end_matches('/');
+        if base_path.is_empty() {
+            return Ok(None);
+        }
+        ensure!(
+            base_path.starts_with('/') && !base_path.contains(['{', '}', '?', '#']),
+            "Invalid http.base_path, expected a path like \"/myapp\": {}",
+            self.base_path
+        );
+        Ok(Some(base_path))
+    }
+}
+
+impl IPC {
+    pub fn socket_mode(&self) -> Result<u32> {
+        let mode = self.socket_mode.trim_start_matches("0o");
+        let mode = u32::from_str_radix(mode, 8)
+            .wrap_err(format!("Invalid ipc.socket_mode: {}", self.socket_mode))?;
+        ensure!(
+            mode <= 0o777,
+            "Invalid ipc.socket_mode: {}",
+            self.socket_mode
+        );
+        Ok(mode)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Auth {
+    /// Auth types accepted by register and login, e.g. ["Username"]
+    pub enabled_auth_types: Vec<AuthType>,
+    /// Features each role is entitled to, e.g. `Manager = ["user_export"]`, checked by handlers
+    /// with `Context::require_entitlement` on top of their role check
+    pub entitlements: HashMap<Role, Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Instance {
+    /// Identifies this process in logs and responses, empty generates `<hostname>-<random>`
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigBackup {
+    /// Timestamped copies of config.toml kept next to it, taken before each change. 0 disables
+    pub retention: usize,
+}
+
+/// Source values from the `config_kv` db table, they override config.toml and env.
+/// Precedence from low to high: default < config.toml < env < config_kv. The store is only read
+/// at startup, a value changed later takes effect when the instance restarts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigStore {
+    pub enable: bool,
 }
 
 mod level_serde {
@@ -122,11 +447,37 @@ mod level_serde {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_redacted_hides_secrets_only() {
+        let cfg = Config::default();
+        let redacted = cfg.redacted();
+
+        assert_eq!(redacted.db.password, REDACTED);
+        assert_eq!(redacted.http.jwt.token_hmac_key, REDACTED);
+        assert_eq!(redacted.db.username, cfg.db.username);
+        assert_eq!(redacted.http.port, cfg.http.port);
+    }
+
+    #[test]
+    fn test_base_path_normalization() {
+        let mut http = Config::default().http;
+        for (base_path, expected) in [("", None), ("/", None), ("/myapp/", Some("/myapp"))] {
+            http.base_path = base_path.to_string();
+            assert_eq!(http.base_path().unwrap(), expected, "{base_path}");
+        }
+        for base_path in ["myapp", "/{id}"] {
+            http.base_path = base_path.to_string();
+            assert!(http.base_path().is_err(), "{base_path}");
+        }
+    }
+
     #[test]
     fn test_log_level_serde_round_trip() {
         let log = Log {
             level: Level::INFO,
             max_log_files: 7,
+            install_panic_hook: false,
+            lifecycle_events_path: None,
         };
 
         let json = serde_json::to_string(&log).expect("Failed to serialize log configuration");
@@ -136,5 +487,6 @@ mod tests {
             serde_json::from_str(&json).expect("Failed to deserialize log configuration");
         assert_eq!(parsed.level, Level::INFO);
         assert_eq!(parsed.max_log_files, 7);
+        assert!(!parsed.install_panic_hook);
     }
 }
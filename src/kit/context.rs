@@ -1,22 +1,361 @@
-use std::sync::Arc;
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
 
-use tokio::sync::RwLock;
+use sidecar::prelude::*;
+use tokio::time::Instant;
+
+use crate::core::model::user::Role;
+use crate::kit::error::Error;
 
 #[derive(Default, Clone, Debug)]
 pub struct Context {
+    pub request_id: String,
+    /// The request came in over the ipc socket rather than http
+    pub is_ipc: bool,
     pub user_id: String,
-    pub log_fields: Arc<RwLock<Vec<(String, String)>>>,
-    pub log_fields_on_error: Arc<RwLock<Vec<(String, String)>>>,
+    /// Matched route template, e.g. `/api/v1/user/{id}`
+    pub route: String,
+    /// Values of the route's `{name}` segments, see [`Context::path_param`]
+    pub path_params: BTreeMap<String, String>,
+    /// Role of the loaded user row, only set on authenticated routes
+    pub role: Option<Role>,
+    /// Features the role is entitled to by `auth.entitlements`, see [`Context::require_entitlement`]
+    pub entitlements: Vec<String>,
+    /// Admin acting through an impersonation token, `user_id` is then the impersonated user
+    pub actor_id: Option<String>,
+    /// Tenant the request is scoped to, see [`Context::set_tenant`]
+    pub tenant_id: Option<String>,
+    /// When the request times out, pass it to db queries so they stop with the request
+    pub deadline: Option<Instant>,
+    pub log_fields: LogFields,
+    pub log_fields_on_error: LogFields,
+    /// Typed values a handler leaves for post-processing, see [`Context::insert`]
+    pub extensions: Extensions,
+}
+
+/// Append-only fields of the request log line. Guarded by a std mutex that is never held across
+/// an await, so adding a field is a short critical section instead of an async lock round trip
+#[derive(Default, Clone, Debug)]
+pub struct LogFields(Arc<Mutex<Vec<(String, String)>>>);
+
+impl LogFields {
+    pub fn push(&self, key: impl Into<String>, value: impl Into<String>) {
+        let entry = (key.into(), value.into());
+        self.lock().push(entry);
+    }
+
+    /// Add a batch of fields under one lock
+    pub fn extend<K, V>(&self, fields: impl IntoIterator<Item = (K, V)>)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let fields = fields
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect::<Vec<_>>();
+        self.lock().extend(fields);
+    }
+
+    /// Later fields win on duplicated keys
+    pub fn snapshot(&self) -> BTreeMap<String, String> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(String, String)>> {
+        // a panic while pushing can't leave the vec half updated
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// One value per type, shared by the clones of a [`Context`] like [`LogFields`]. Also attached to
+/// the response extensions, so layers outside the handler (audit, metrics) read what the handler
+/// left there
+#[derive(Default, Clone)]
+pub struct Extensions(Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>);
+
+impl Extensions {
+    /// Returns the value of the same type it replaced
+    pub fn insert<T>(&self, value: T) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.lock()
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast::<T>().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn get<T>(&self) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.lock()
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<TypeId, Box<dyn Any + Send + Sync>>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl std::fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.lock().len())
+            .finish()
+    }
 }
 
 impl Context {
-    pub async fn add_log_field(&self, key: impl Into<String>, value: impl Into<String>) {
-        let mut log_fields = self.log_fields.write().await;
-        log_fields.push((key.into(), value.into()));
+    /// Annotate the request for post-processing, e.g. mark it as a privileged action for the
+    /// audit log. Every clone of the context sees it
+    pub fn insert<T>(&self, value: T) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.extensions.insert(value)
+    }
+
+    pub fn get<T>(&self) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.extensions.get()
+    }
+
+    pub fn add_log_field(&self, key: impl Into<String>, value: impl Into<String>) {
+        self.log_fields.push(key, value);
     }
 
-    pub async fn add_log_field_on_error(&self, key: impl Into<String>, value: impl Into<String>) {
-        let mut log_fields_on_error = self.log_fields_on_error.write().await;
-        log_fields_on_error.push((key.into(), value.into()));
+    pub fn add_log_field_on_error(&self, key: impl Into<String>, value: impl Into<String>) {
+        self.log_fields_on_error.push(key, value);
+    }
+
+    /// Value of the `{name}` route segment
+    pub fn path_param(&self, name: &str) -> Result<&str> {
+        match self.path_params.get(name) {
+            Some(value) => Ok(value),
+            None => Err(Error::InvidRequestParameter(format!("missing path param: {name}")).into()),
+        }
+    }
+
+    /// Scope the request to the tenant of the authenticated user, or of the tenant header when the
+    /// user has none. A header naming another tenant than the user's is rejected, not ignored
+    pub fn set_tenant(&mut self, user_tenant: Option<String>, header: Option<&str>) -> Result<()> {
+        let header = header
+            .map(str::trim)
+            .filter(|tenant_id| !tenant_id.is_empty());
+        let tenant_id = match (user_tenant, header) {
+            (Some(user_tenant), Some(header)) if user_tenant != header => {
+                return Err(Error::Forbidden).wrap_err(format!(
+                    "tenant header {header} does not match user tenant {user_tenant}"
+                ));
+            }
+            (Some(user_tenant), _) => Some(user_tenant),
+            (None, header) => header.map(str::to_string),
+        };
+        if let Some(tenant_id) = &tenant_id {
+            self.add_log_field("tenant", tenant_id.clone());
+        }
+        self.tenant_id = tenant_id;
+        Ok(())
+    }
+
+    /// Require the current role to be `role` or above
+    pub fn require_role(&self, role: Role) -> Result<()> {
+        if self
+            .role
+            .as_ref()
+            .is_some_and(|current| current.at_least(&role))
+        {
+            return Ok(());
+        }
+
+        Err(Error::Forbidden).wrap_err(format!(
+            "required role: {:?}, current role: {:?}",
+            role, self.role
+        ))
+    }
+
+    /// Require a user of `tenant_id` to be within reach of the request, a request without a
+    /// tenant reaches every user
+    pub fn require_tenant(&self, tenant_id: Option<&str>) -> Result<()> {
+        match &self.tenant_id {
+            Some(current) if tenant_id != Some(current.as_str()) => Err(Error::Forbidden).wrap_err(
+                format!("user tenant {tenant_id:?} is outside of request tenant {current}"),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Require the current role to be entitled to the `name` feature
+    pub fn require_entitlement(&self, name: &str) -> Result<()> {
+        if self
+            .entitlements
+            .iter()
+            .any(|entitlement| entitlement == name)
+        {
+            return Ok(());
+        }
+
+        Err(Error::FeatureNotEntitled(name.to_string()))
+            .wrap_err(format!("current role: {:?}", self.role))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_log_fields_concurrent_adds_keep_every_field() -> Result<()> {
+        const TASKS: usize = 32;
+        const FIELDS_PER_TASK: usize = 500;
+
+        let ctx = Context::default();
+        let handles = (0..TASKS)
+            .map(|task| {
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    for i in 0..FIELDS_PER_TASK {
+                        if i % 2 == 0 {
+                            ctx.add_log_field(format!("{task}-{i}"), i.to_string());
+                        } else {
+                            ctx.log_fields
+                                .extend([(format!("{task}-{i}"), i.to_string())]);
+                        }
+                        if i % 100 == 0 {
+                            ctx.add_log_field_on_error(format!("{task}-{i}"), "err");
+                            tokio::task::yield_now().await;
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.await?;
+        }
+
+        let fields = ctx.log_fields.snapshot();
+        assert_eq!(fields.len(), TASKS * FIELDS_PER_TASK);
+        for task in 0..TASKS {
+            for i in 0..FIELDS_PER_TASK {
+                assert_eq!(fields[&format!("{task}-{i}")], i.to_string());
+            }
+        }
+        assert_eq!(
+            ctx.log_fields_on_error.snapshot().len(),
+            TASKS * FIELDS_PER_TASK / 100
+        );
+
+        Ok(())
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Privileged(&'static str);
+
+    #[test]
+    fn test_extensions_are_typed_and_shared_by_clones() {
+        let ctx = Context::default();
+        assert_eq!(ctx.get::<Privileged>(), None);
+
+        let handler_ctx = ctx.clone();
+        assert_eq!(handler_ctx.insert(Privileged("delete users")), None);
+        handler_ctx.insert(42u32);
+
+        assert_eq!(ctx.get::<Privileged>(), Some(Privileged("delete users")));
+        assert_eq!(ctx.get::<u32>(), Some(42));
+        assert_eq!(ctx.get::<u64>(), None);
+        assert_eq!(
+            ctx.insert(Privileged("impersonate")),
+            Some(Privileged("delete users"))
+        );
+        assert_eq!(format!("{:?}", ctx.extensions), "Extensions { len: 2 }");
+    }
+
+    #[test]
+    fn test_require_role_allows_matching_or_higher_role() {
+        let ctx = Context {
+            role: Some(Role::Manager),
+            ..Default::default()
+        };
+
+        assert!(ctx.require_role(Role::Manager).is_ok());
+        assert!(ctx.require_role(Role::User).is_ok());
+    }
+
+    #[test]
+    fn test_require_role_denies_lower_or_missing_role() {
+        let ctx = Context {
+            role: Some(Role::User),
+            ..Default::default()
+        };
+        let err = ctx.require_role(Role::Admin).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Forbidden)
+        ));
+
+        assert!(Context::default().require_role(Role::User).is_err());
+    }
+
+    #[test]
+    fn test_require_entitlement_rejects_role_lacking_it() {
+        let ctx = Context {
+            role: Some(Role::Manager),
+            entitlements: vec!["user_export".to_string()],
+            ..Default::default()
+        };
+        assert!(ctx.require_entitlement("user_export").is_ok());
+
+        let err = ctx.require_entitlement("user_bulk_delete").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::FeatureNotEntitled(name)) if name == "user_bulk_delete"
+        ));
+    }
+
+    #[test]
+    fn test_require_tenant_rejects_users_of_other_tenants() {
+        let ctx = Context {
+            tenant_id: Some("acme".to_string()),
+            ..Default::default()
+        };
+        assert!(ctx.require_tenant(Some("acme")).is_ok());
+        assert!(ctx.require_tenant(Some("globex")).is_err());
+        assert!(ctx.require_tenant(None).is_err());
+
+        assert!(Context::default().require_tenant(Some("globex")).is_ok());
+    }
+
+    #[test]
+    fn test_set_tenant_prefers_user_tenant_and_rejects_mismatch() -> Result<()> {
+        let mut ctx = Context::default();
+        ctx.set_tenant(None, Some(" acme "))?;
+        assert_eq!(ctx.tenant_id.as_deref(), Some("acme"));
+
+        let mut ctx = Context::default();
+        ctx.set_tenant(None, Some(""))?;
+        assert_eq!(ctx.tenant_id, None);
+
+        let mut ctx = Context::default();
+        ctx.set_tenant(Some("acme".to_string()), None)?;
+        assert_eq!(ctx.tenant_id.as_deref(), Some("acme"));
+        ctx.set_tenant(Some("acme".to_string()), Some("acme"))?;
+        assert_eq!(ctx.tenant_id.as_deref(), Some("acme"));
+
+        let err = ctx
+            .set_tenant(Some("acme".to_string()), Some("globex"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Forbidden)
+        ));
+
+        Ok(())
     }
 }
@@ -1,3 +1,5 @@
+use sea_orm::sqlx;
+use sea_orm::{DbErr, RuntimeErr};
 use thiserror::Error;
 
 #[derive(Error, Debug, Clone)]
@@ -18,6 +20,48 @@ pub enum Error {
     #[error("Db connection not initialized")]
     DBConnectionNotInitialized,
 
+    #[error("Too many requests")]
+    TooManyRequests,
+
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("Feature disabled")]
+    FeatureDisabled,
+
+    #[error("Request timeout")]
+    RequestTimeout,
+
+    #[error("Api not allowed over ipc")]
+    ApiNotAllowedOverIPC,
+
+    #[error("Db error: {0}")]
+    DB(String),
+
+    #[error("Db unique constraint violation: {0}")]
+    DBUniqueViolation(String),
+
+    #[error("Db unavailable: {0}")]
+    DBUnavailable(String),
+
+    #[error("Validation failed: {0}")]
+    ValidationFailed(String),
+
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    #[error("Missing token")]
+    MissingToken,
+
+    #[error("Invalid token")]
+    InvalidToken,
+
+    #[error("Token expired")]
+    ExpiredToken,
+
+    #[error("Feature not entitled: {0}")]
+    FeatureNotEntitled(String),
+
     // -------------- user --------------
     #[error("User not found")]
     UserNotFound,
@@ -27,6 +71,9 @@ pub enum Error {
 
     #[error("User invalid password")]
     UserInvalidPassword,
+
+    #[error("Auth type disabled")]
+    AuthTypeDisabled,
 }
 
 impl Error {
@@ -38,11 +85,163 @@ impl Error {
             Error::Unauthorized => 10003,
             Error::ApiMustRequestFromIPC => 10004,
             Error::DBConnectionNotInitialized => 10005,
+            Error::TooManyRequests => 10006,
+            Error::Forbidden => 10007,
+            Error::FeatureDisabled => 10008,
+            Error::RequestTimeout => 10009,
+            Error::ApiNotAllowedOverIPC => 10010,
+            Error::DB(_) => 10011,
+            Error::DBUniqueViolation(_) => 10012,
+            Error::DBUnavailable(_) => 10013,
+            Error::ValidationFailed(_) => 10014,
+            Error::ServiceUnavailable(_) => 10015,
+            Error::MissingToken => 10016,
+            Error::InvalidToken => 10017,
+            Error::ExpiredToken => 10018,
+            Error::FeatureNotEntitled(_) => 10019,
 
             // -------------- user --------------
             Error::UserNotFound => 10101,
-            Error::UserAlreadyExists => 10002,
-            Error::UserInvalidPassword => 10003,
+            Error::UserAlreadyExists => 10103,
+            Error::UserInvalidPassword => 10104,
+            Error::AuthTypeDisabled => 10102,
+        }
+    }
+
+    /// Transient failures, the same request may succeed when retried later. Validation, auth and
+    /// business errors are final
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Error::DBConnectionNotInitialized
+                | Error::TooManyRequests
+                | Error::RequestTimeout
+                | Error::DBUnavailable(_)
+                | Error::ServiceUnavailable(_)
+        )
+    }
+
+    /// [`Error::is_retryable`] for clients that only see the code of the response envelope
+    pub fn is_retryable_code(code: u64) -> bool {
+        Self::variants()
+            .iter()
+            .any(|err| err.code() == code && err.is_retryable())
+    }
+
+    /// One value of every variant with an empty message, a new variant has to be added here
+    pub fn variants() -> [Error; 23] {
+        [
+            Error::Unknown(String::new()),
+            Error::InvidRequestParameter(String::new()),
+            Error::Unauthorized,
+            Error::ApiMustRequestFromIPC,
+            Error::DBConnectionNotInitialized,
+            Error::TooManyRequests,
+            Error::Forbidden,
+            Error::FeatureDisabled,
+            Error::RequestTimeout,
+            Error::ApiNotAllowedOverIPC,
+            Error::DB(String::new()),
+            Error::DBUniqueViolation(String::new()),
+            Error::DBUnavailable(String::new()),
+            Error::ValidationFailed(String::new()),
+            Error::ServiceUnavailable(String::new()),
+            Error::MissingToken,
+            Error::InvalidToken,
+            Error::ExpiredToken,
+            Error::FeatureNotEntitled(String::new()),
+            Error::UserNotFound,
+            Error::UserAlreadyExists,
+            Error::UserInvalidPassword,
+            Error::AuthTypeDisabled,
+        ]
+    }
+}
+
+/// Classify a sea-orm error so it surfaces with a db specific code instead of `Unknown`
+impl From<&DbErr> for Error {
+    fn from(err: &DbErr) -> Self {
+        match err {
+            DbErr::ConnectionAcquire(_) | DbErr::Conn(_) => Error::DBUnavailable(err.to_string()),
+            DbErr::Exec(RuntimeErr::SqlxError(sqlx_err))
+            | DbErr::Query(RuntimeErr::SqlxError(sqlx_err)) => match sqlx_err.as_ref() {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                    Error::DBUniqueViolation(db_err.message().to_string())
+                }
+                sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+                    Error::DBUnavailable(err.to_string())
+                }
+                _ => Error::DB(err.to_string()),
+            },
+            _ => Error::DB(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sea_orm::ConnAcquireErr;
+
+    use super::*;
+    use crate::kit::test_support::db_error;
+
+    #[test]
+    fn test_db_errors_map_to_db_codes() {
+        let err = Error::from(&db_error("23505", Some("user_auth_type_unique_index")));
+        assert!(matches!(&err, Error::DBUniqueViolation(msg) if msg.starts_with("duplicate key")));
+
+        let err = Error::from(&db_error("42601", None));
+        assert!(matches!(err, Error::DB(_)));
+        assert!(!err.is_retryable());
+
+        let err = Error::from(&DbErr::ConnectionAcquire(ConnAcquireErr::Timeout));
+        assert!(matches!(err, Error::DBUnavailable(_)));
+        assert!(err.is_retryable());
+
+        let err = Error::from(&DbErr::Query(RuntimeErr::SqlxError(Arc::new(
+            sqlx::Error::PoolTimedOut,
+        ))));
+        assert!(matches!(err, Error::DBUnavailable(_)));
+
+        assert!(matches!(
+            Error::from(&DbErr::RecordNotFound("user".to_string())),
+            Error::DB(_)
+        ));
+    }
+
+    #[test]
+    fn test_every_variant_has_its_own_code() {
+        let mut seen = std::collections::HashMap::new();
+        for err in Error::variants() {
+            if let Some(other) = seen.insert(err.code(), err.clone()) {
+                panic!("{err:?} and {other:?} share the code {}", err.code());
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_retryable_only_for_transient_errors() {
+        for err in [
+            Error::DBConnectionNotInitialized,
+            Error::TooManyRequests,
+            Error::RequestTimeout,
+            Error::DBUnavailable("pool timed out".to_string()),
+            Error::ServiceUnavailable("shutting down".to_string()),
+        ] {
+            assert!(err.is_retryable(), "{err:?}");
+            assert!(Error::is_retryable_code(err.code()), "{err:?}");
+        }
+
+        for err in [
+            Error::InvidRequestParameter("name".to_string()),
+            Error::Unauthorized,
+            Error::Forbidden,
+            Error::UserInvalidPassword,
+        ] {
+            assert!(!err.is_retryable(), "{err:?}");
+            assert!(!Error::is_retryable_code(err.code()), "{err:?}");
         }
     }
 }
@@ -1,9 +1,15 @@
-use chrono::{Duration, Local};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use chrono::{DateTime, Duration, Local};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use sidecar::prelude::*;
 
+/// The only algorithm tokens are issued and accepted with. Pinned on both sides so a token can't
+/// pick its own verification, e.g. `alg: none` or another algorithm keyed with the hmac secret
+const ALGORITHM: Algorithm = Algorithm::HS256;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(bound(deserialize = "T: DeserializeOwned", serialize = "T: Serialize"))]
 pub struct Claims<T>
@@ -30,26 +36,42 @@ where
     }
 }
 
+/// `nbf` is backdated by `clock_skew`, so instances whose clocks are up to that much behind
+/// accept a token as soon as it's issued
 pub fn generate_with_hmac_key<T>(
     hmac_key: impl AsRef<[u8]>,
     valid_duration: Duration,
+    clock_skew: Duration,
+    id: &str,
+    data: T,
+) -> Result<(String, i64)>
+where
+    T: Serialize + DeserializeOwned,
+{
+    generate_at(Local::now(), hmac_key, valid_duration, clock_skew, id, data)
+}
+
+fn generate_at<T>(
+    now: DateTime<Local>,
+    hmac_key: impl AsRef<[u8]>,
+    valid_duration: Duration,
+    clock_skew: Duration,
     id: &str,
     data: T,
 ) -> Result<(String, i64)>
 where
     T: Serialize + DeserializeOwned,
 {
-    let now = Local::now();
     let exp_time = now + valid_duration;
 
     let claims = Claims {
         sub: id.to_string(),
         exp: exp_time.timestamp(),
-        nbf: now.timestamp(),
+        nbf: (now - clock_skew).timestamp(),
         data,
     };
 
-    let header = Header::new(Algorithm::HS256);
+    let header = Header::new(ALGORITHM);
     let token = encode(
         &header,
         &claims,
@@ -59,11 +81,25 @@ where
     Ok((token, exp_time.timestamp()))
 }
 
-pub fn parse_with_hmac_key<T>(hmac_key: impl AsRef<[u8]>, token: &str) -> Result<(String, T)>
+/// `nbf` and `exp` are checked with `clock_skew` of leeway, the same skew the issuer applied
+pub fn parse_with_hmac_key<T>(
+    hmac_key: impl AsRef<[u8]>,
+    clock_skew: Duration,
+    token: &str,
+) -> Result<(String, T)>
 where
     T: Clone + Serialize + DeserializeOwned,
 {
-    let validation = Validation::new(Algorithm::HS256);
+    let header = decode_header(token)?;
+    ensure!(
+        header.alg == ALGORITHM,
+        "unexpected jwt algorithm: {:?}",
+        header.alg
+    );
+    let mut validation = Validation::new(ALGORITHM);
+    validation.algorithms = vec![ALGORITHM];
+    validation.validate_nbf = true;
+    validation.leeway = u64::try_from(clock_skew.num_seconds()).unwrap_or_default();
     let token_data = decode::<Claims<T>>(
         token,
         &DecodingKey::from_secret(hmac_key.as_ref()),
@@ -71,3 +107,133 @@ where
     )?;
     Ok((token_data.claims.sub.clone(), token_data.claims.data))
 }
+
+/// Read the claims WITHOUT verifying signature, expiry or nbf. Only for diagnostics such as token
+/// inspection, never for authentication
+pub fn decode_unverified<T>(token: &str) -> Result<Claims<T>>
+where
+    T: Clone + Serialize + DeserializeOwned,
+{
+    let mut validation = Validation::new(ALGORITHM);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_nbf = false;
+    validation.required_spec_claims.clear();
+
+    let token_data = decode::<Claims<T>>(token, &DecodingKey::from_secret(&[]), &validation)?;
+    Ok(token_data.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_unverified_reads_expired_token_claims() -> Result<()> {
+        let (token, exp) = generate_with_hmac_key(
+            "key",
+            Duration::hours(-1),
+            Duration::zero(),
+            "user-1",
+            "payload".to_string(),
+        )?;
+
+        assert!(parse_with_hmac_key::<String>("key", Duration::zero(), &token).is_err());
+
+        let claims = decode_unverified::<String>(&token)?;
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.exp, exp);
+        assert_eq!(claims.data, "payload");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rejects_unsigned_alg_none_token() {
+        // {"alg":"none","typ":"JWT"}.{"sub":"admin","exp":4102444800,"nbf":0,"data":"payload"}.
+        let token = "eyJhbGciOiJub25lIiwidHlwIjoiSldUIn0.\
+                     eyJzdWIiOiJhZG1pbiIsImV4cCI6NDEwMjQ0NDgwMCwibmJmIjowLCJkYXRhIjoicGF5bG9hZCJ9.";
+        assert!(parse_with_hmac_key::<String>("key", Duration::zero(), token).is_err());
+        // same with a signature segment copied from a real token
+        let (signed, _) = generate_with_hmac_key(
+            "key",
+            Duration::hours(1),
+            Duration::zero(),
+            "user-1",
+            "payload".to_string(),
+        )
+        .unwrap();
+        let signature = signed.rsplit('.').next().unwrap();
+        assert!(
+            parse_with_hmac_key::<String>("key", Duration::zero(), &format!("{token}{signature}"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_other_algorithm_with_same_key() -> Result<()> {
+        let now = Local::now();
+        let claims = Claims {
+            sub: "user-1".to_string(),
+            exp: (now + Duration::hours(1)).timestamp(),
+            nbf: now.timestamp(),
+            data: "payload".to_string(),
+        };
+        let token = encode(
+            &Header::new(Algorithm::HS512),
+            &claims,
+            &EncodingKey::from_secret(b"key"),
+        )?;
+
+        let err = parse_with_hmac_key::<String>("key", Duration::zero(), &token).unwrap_err();
+        assert!(err.to_string().contains("HS512"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_unverified_ignores_signature() -> Result<()> {
+        let (token, _) = generate_with_hmac_key(
+            "key",
+            Duration::hours(1),
+            Duration::zero(),
+            "user-1",
+            "payload".to_string(),
+        )?;
+
+        assert!(parse_with_hmac_key::<String>("other-key", Duration::zero(), &token).is_err());
+        assert_eq!(decode_unverified::<String>(&token)?.sub, "user-1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clock_skew_lets_a_slightly_behind_clock_accept_fresh_tokens() -> Result<()> {
+        // issued by an instance whose clock runs 3s ahead of ours
+        let ahead = Local::now() + Duration::seconds(3);
+
+        let (token, _) = generate_at(
+            ahead,
+            "key",
+            Duration::hours(1),
+            Duration::seconds(5),
+            "user-1",
+            "payload".to_string(),
+        )?;
+        let (subject, _) = parse_with_hmac_key::<String>("key", Duration::seconds(5), &token)?;
+        assert_eq!(subject, "user-1");
+
+        // without skew the token is not valid yet over here
+        let (token, _) = generate_at(
+            ahead,
+            "key",
+            Duration::hours(1),
+            Duration::zero(),
+            "user-1",
+            "payload".to_string(),
+        )?;
+        assert!(parse_with_hmac_key::<String>("key", Duration::zero(), &token).is_err());
+
+        Ok(())
+    }
+}
@@ -2,4 +2,7 @@ pub mod config;
 pub mod context;
 pub mod error;
 pub mod jwt;
+pub mod query;
 pub mod response;
+#[cfg(test)]
+pub mod test_support;
@@ -0,0 +1,228 @@
+//! Lenient deserializers for query params, use with `#[serde(default, deserialize_with = "...")]`
+//! on the query struct of a `wrap_get_handler` handler. Query strings carry every value as text and
+//! clients differ in how they spell booleans, so `1`/`yes`/`on` are accepted alongside `true`
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::Deserializer;
+use serde::de::{self, Visitor};
+
+/// `true`/`1`/`yes`/`on` and `false`/`0`/`no`/`off`, case-insensitive
+pub fn flexible_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    deserializer.deserialize_any(BoolVisitor)
+}
+
+/// Like [`flexible_bool`], an empty value (`?active=`) is treated as absent
+pub fn flexible_option_bool<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<bool>, D::Error> {
+    deserializer.deserialize_any(OptionVisitor(BoolVisitor))
+}
+
+/// Any `FromStr` number, surrounding whitespace is ignored
+pub fn flexible_number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    deserializer.deserialize_any(NumberVisitor(PhantomData))
+}
+
+/// Like [`flexible_number`], an empty value is treated as absent
+pub fn flexible_option_number<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    deserializer.deserialize_any(OptionVisitor(NumberVisitor(PhantomData)))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+struct BoolVisitor;
+
+impl Visitor<'_> for BoolVisitor {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a boolean such as true, 1 or yes")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<bool, E> {
+        Ok(value)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<bool, E> {
+        match value {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(E::invalid_value(de::Unexpected::Unsigned(value), &self)),
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<bool, E> {
+        match value {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(E::invalid_value(de::Unexpected::Signed(value), &self)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<bool, E> {
+        parse_bool(value).ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+struct NumberVisitor<T>(PhantomData<T>);
+
+impl<T> Visitor<'_> for NumberVisitor<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<T, E> {
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        value.trim().parse().map_err(E::custom)
+    }
+}
+
+/// Empty strings and unit map to `None`, everything else goes to the inner visitor
+struct OptionVisitor<V>(V);
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for OptionVisitor<V> {
+    type Value = Option<V::Value>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.0.expecting(formatter)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Self::Value, E> {
+        self.0.visit_bool(value).map(Some)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        self.0.visit_u64(value).map(Some)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        self.0.visit_i64(value).map(Some)
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        self.0.visit_f64(value).map(Some)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        if value.trim().is_empty() {
+            return Ok(None);
+        }
+        self.0.visit_str(value).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Query;
+    use axum::extract::rejection::QueryRejection;
+    use axum::http::Uri;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Filter {
+        #[serde(default, deserialize_with = "flexible_option_bool")]
+        active: Option<bool>,
+        #[serde(default, deserialize_with = "flexible_option_number")]
+        limit: Option<u32>,
+    }
+
+    /// Same extraction `wrap_get_handler` performs
+    fn parse(query: &str) -> Result<Filter, QueryRejection> {
+        let uri: Uri = format!("/users?{query}").parse().unwrap();
+        Query::try_from_uri(&uri).map(|Query(filter)| filter)
+    }
+
+    #[test]
+    fn test_truthy_and_falsy_spellings() {
+        for value in ["1", "true", "TRUE", "yes", "on"] {
+            assert_eq!(
+                parse(&format!("active={value}")).unwrap().active,
+                Some(true)
+            );
+        }
+        for value in ["0", "false", "No", "off"] {
+            assert_eq!(
+                parse(&format!("active={value}")).unwrap().active,
+                Some(false)
+            );
+        }
+        assert!(parse("active=maybe").is_err());
+    }
+
+    #[test]
+    fn test_missing_or_empty_is_none() {
+        let filter = parse("active=&limit=").unwrap();
+        assert_eq!((filter.active, filter.limit), (None, None));
+
+        let filter = parse("").unwrap();
+        assert_eq!((filter.active, filter.limit), (None, None));
+    }
+
+    #[test]
+    fn test_number_coercion() {
+        assert_eq!(parse("limit=%2042%20").unwrap().limit, Some(42));
+        assert!(parse("limit=-1").is_err());
+        assert!(parse("limit=ten").is_err());
+    }
+
+    #[test]
+    fn test_json_values_are_accepted_too() {
+        #[derive(Deserialize)]
+        struct Flag {
+            #[serde(deserialize_with = "flexible_bool")]
+            on: bool,
+            #[serde(deserialize_with = "flexible_number")]
+            size: u8,
+        }
+
+        let flag: Flag = serde_json::from_str(r#"{"on":1,"size":"7"}"#).unwrap();
+        assert!(flag.on);
+        assert_eq!(flag.size, 7);
+    }
+}
@@ -0,0 +1,62 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use sea_orm::sqlx::error::{DatabaseError, ErrorKind};
+use sea_orm::{DbErr, RuntimeErr, sqlx};
+
+/// An error the database server returns with the SQLSTATE `code`, naming the violated
+/// `constraint` if any
+#[derive(Debug, thiserror::Error)]
+#[error("{}", self.message())]
+pub struct TestDatabaseError {
+    pub code: &'static str,
+    pub constraint: Option<&'static str>,
+}
+
+impl DatabaseError for TestDatabaseError {
+    fn message(&self) -> &str {
+        match self.code {
+            "23505" => "duplicate key value violates unique constraint",
+            "40001" => "could not serialize access due to concurrent update",
+            "40P01" => "deadlock detected",
+            _ => "database error",
+        }
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(self.code.into())
+    }
+
+    fn constraint(&self) -> Option<&str> {
+        self.constraint
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self.code {
+            "23505" => ErrorKind::UniqueViolation,
+            "23503" => ErrorKind::ForeignKeyViolation,
+            "23502" => ErrorKind::NotNullViolation,
+            "23514" => ErrorKind::CheckViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// A failed statement as sea-orm reports it, see [`TestDatabaseError`]
+pub fn db_error(code: &'static str, constraint: Option<&'static str>) -> DbErr {
+    DbErr::Exec(RuntimeErr::SqlxError(Arc::new(sqlx::Error::Database(
+        Box::new(TestDatabaseError { code, constraint }),
+    ))))
+}
@@ -14,6 +14,7 @@ use sidecar::{setup, version};
 
 use crate::kit::config::Config;
 
+#[cfg(feature = "jemalloc")]
 #[global_allocator]
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
@@ -33,21 +34,40 @@ enum Commands {
         command: cmd::config::Cmd,
     },
     Ipc {
+        #[command(flatten)]
+        args: cmd::ipc::IpcArgs,
         #[command(subcommand)]
         command: cmd::ipc::Cmd,
     },
+    Logs {
+        #[command(subcommand)]
+        command: cmd::logs::Cmd,
+    },
     Run(cmd::run::RunArgs),
+    HashPassword(cmd::hash_password::HashPasswordArgs),
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    setup::setup_libs()?;
+fn main() -> Result<()> {
     version::init(load_version_from_env());
 
     let cli = parse_cli();
+    let repo_root = resolve_repo_root(cli.repo_root.clone())?;
+
+    // forking only keeps the calling thread, so it has to happen before the runtime spawns workers
+    if let Some(Commands::Run(args)) = &cli.command
+        && args.daemonize
+    {
+        cmd::run::daemonize(&repo_root)?;
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(cli.command, repo_root))
+}
 
-    let Cli { repo_root, command } = cli;
-    let repo_root = resolve_repo_root(repo_root)?;
+async fn run(command: Option<Commands>, repo_root: PathBuf) -> Result<()> {
+    setup::setup_libs()?;
 
     let v = version::current();
     let repo = Repo::<Config>::new(repo_root, v.app_name).await?;
@@ -55,7 +75,9 @@ async fn main() -> Result<()> {
     match command {
         Some(Commands::Run(args)) => args.run(repo).await,
         Some(Commands::Config { command }) => cmd::config::run(command, repo).await,
-        Some(Commands::Ipc { command }) => cmd::ipc::run(command, repo).await,
+        Some(Commands::Ipc { args, command }) => cmd::ipc::run(command, args, repo).await,
+        Some(Commands::Logs { command }) => cmd::logs::run(command, repo).await,
+        Some(Commands::HashPassword(args)) => args.run().await,
         None => {
             println!("{} {}", v.app_name, v.version);
             println!("git_branch：{}", v.git_branch);
@@ -0,0 +1,91 @@
+#![cfg(unix)]
+
+use std::path::Path;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+fn wait_until(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        sleep(Duration::from_millis(50));
+    }
+    condition()
+}
+
+/// Content of the `.log` files in the log dir, `daemon.out` excluded
+fn read_logs(repo_root: &Path) -> String {
+    std::fs::read_dir(repo_root.join("logs"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .collect()
+}
+
+fn read_pid(repo_root: &Path) -> i32 {
+    std::fs::read_to_string(repo_root.join("process.pid"))
+        .expect("pid file written")
+        .trim()
+        .parse()
+        .expect("pid file holds a pid")
+}
+
+#[test]
+fn daemonize_writes_pid_and_parent_exits() {
+    let tmp = tempdir().unwrap();
+    let repo_root = tmp.path();
+    std::fs::write(
+        repo_root.join("config.toml"),
+        "[db]\nauto_create_tables = false\n",
+    )
+    .unwrap();
+
+    // returns once the launching process exits, the daemon keeps running
+    let output = Command::new(env!("CARGO_BIN_EXE_rs-project-startup"))
+        .arg("--repo-root")
+        .arg(repo_root)
+        .args(["run", "--daemonize"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let pid = read_pid(repo_root);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("pid: {pid}")), "{stdout}");
+    assert_ne!(pid, std::process::id() as i32);
+    assert_eq!(unsafe { libc::kill(pid, 0) }, 0, "daemon not running");
+
+    // a second daemon refuses to start while the first is alive
+    let output = Command::new(env!("CARGO_BIN_EXE_rs-project-startup"))
+        .arg("--repo-root")
+        .arg(repo_root)
+        .args(["run", "--daemonize"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(read_pid(repo_root), pid);
+
+    assert!(
+        wait_until(Duration::from_secs(10), || read_logs(repo_root)
+            .contains("app is running")),
+        "daemon never became ready"
+    );
+    // the log lines only go to the log files, daemon.out is for what bypasses them
+    let daemon_out = std::fs::read_to_string(repo_root.join("logs/daemon.out")).unwrap();
+    assert!(!daemon_out.contains("app is running"), "{daemon_out}");
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+    assert!(
+        wait_until(Duration::from_secs(20), || !repo_root
+            .join("process.pid")
+            .exists()),
+        "daemon didn't remove its pid file on shutdown"
+    );
+}